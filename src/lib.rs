@@ -508,35 +508,41 @@
 //!
 //! | type     | description                                                   |
 //! | -------- | ------------------------------------------------------------- |
-//! | `String`   | the name of the corresponding (by $\psi_N$) abstract category |
+//! | `String`   | the name of the corresponding (by ψN) abstract category      |
 //! | `Int`      | the first concrete category                                   |
 //! | `Int`      | the last concrete category                                    |
 //! | [`String`] | a list of constituent names
-
-use std::env;
-use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
-use std::collections::HashMap;
+//!
+//! ---
+//! # Differences in Version 2.1
+//!
+//! Files written by current GF compilers carry major version 2 (minor 1). The
+//! reader selects the layout from the version header; the writer always emits
+//! 2.1. The changes relative to 1.0 are (see `theory/pgf-schema-v2_1.md`):
+//!
+//! - Identifiers (flag, function, category and language names) store their
+//!   length in bytes; tokens, labels and print names keep the character count.
+//! - An abstract category stores its functions as `(Float, String)` pairs and
+//!   is followed by a `Float` category probability.
+//! - The concrete syntax has a `[LinRef]` list after the lindefs.
+//! - A `Symbol` with tag 3 is a single token, tag 4 holds symbols in both the
+//!   default form and the alternatives, and tags 5 to 10 are `BIND`,
+//!   `SOFT_BIND`, `nonExist`, `SOFT_SPACE`, `CAPIT` and `ALL_CAPIT`.
+//! - A coercion stores its category as an `Int`.
+
+use bincode::Options;
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::{self, Cursor, Read};
+use std::io::{self, Cursor, Read, Write};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
 use bytes::Bytes;
-use serde::{Deserialize, Serialize};
+use serde::{ser::SerializeMap, Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
 use thiserror::Error;
 
-/// Check if DEBUG mode is enabled via environment variable
-fn is_debug_enabled() -> bool {
-    std::env::var("DEBUG").unwrap_or_default() == "1"
-}
-
-/// Print debug message if DEBUG mode is enabled
-macro_rules! debug_println {
-    ($($arg:tt)*) => {
-        if is_debug_enabled() {
-            println!($($arg)*);
-        }
-    };
-}
-
 // Errors that can occur during PGF operations.
 #[derive(Error, Debug)]
 pub enum PgfError {
@@ -544,14 +550,136 @@ pub enum PgfError {
     Io(#[from] io::Error),
     #[error("Unknown language: {0}")]
     UnknownLanguage(String),
-    #[error("Deserialization error at offset {offset}: {message}")]
-    DeserializeError { offset: u64, message: String },
+    /// The binary data could not be decoded. `path` names the structure that
+    /// was being read, e.g. `concrete[FoodIta].sequences[42].symbol[3]`, and is
+    /// empty when the failure is outside any named structure.
+    #[error("Deserialization error at offset {offset}{}: {kind}", at_path(.path))]
+    DeserializeError {
+        offset: u64,
+        path: String,
+        kind: DecodeErrorKind,
+        #[source]
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
     #[error("Serialization error: {0}")]
     SerializeError(String),
     #[error("Type checking error: {0}")]
     TypeCheckError(String),
     #[error("Parsing error: {0}")]
     ParseError(String),
+    #[error("Linearization error: {0}")]
+    LinearizeError(String),
+    #[error("Invalid grammar: {0}")]
+    InvalidGrammar(String),
+    #[error("JSON error at {path}: {message}")]
+    JsonError { path: String, message: String },
+    #[error("Limit exceeded at offset {offset}: {limit} {value} is over the maximum of {max}")]
+    LimitExceeded { offset: u64, limit: Limit, value: u64, max: u64 },
+    /// A loaded grammar refers to something that cannot exist, such as a
+    /// negative function id.
+    #[error("Invalid reference in {path}: expected {expected}, found {found}")]
+    InvalidReference { path: String, expected: &'static str, found: i64 },
+}
+
+impl PgfError {
+    fn decode(offset: u64, kind: DecodeErrorKind) -> Self {
+        PgfError::DeserializeError { offset, path: String::new(), kind, source: None }
+    }
+
+    /// The data ended while reading `what`.
+    fn eof(offset: u64, what: &'static str, source: io::Error) -> Self {
+        PgfError::DeserializeError {
+            offset,
+            path: String::new(),
+            kind: DecodeErrorKind::UnexpectedEof { what },
+            source: Some(Box::new(source)),
+        }
+    }
+
+    /// What went wrong, if this is a [`PgfError::DeserializeError`].
+    #[must_use]
+    pub fn decode_kind(&self) -> Option<&DecodeErrorKind> {
+        match self {
+            PgfError::DeserializeError { kind, .. } => Some(kind),
+            _ => None,
+        }
+    }
+}
+
+fn at_path(path: &str) -> String {
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!(" in {path}")
+    }
+}
+
+/// The reason a [`PgfError::DeserializeError`] was raised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    /// The data ended in the middle of `what`.
+    UnexpectedEof { what: &'static str },
+    /// A tag byte that names no variant of `what`.
+    UnknownTag { what: &'static str, expected: &'static str, found: u8 },
+    /// A negative length prefix.
+    NegativeLength { what: &'static str, found: i32 },
+    /// A string that is not valid UTF-8.
+    InvalidUtf8 { what: &'static str },
+    /// A variable-length integer that does not fit in 32 bits.
+    IntegerOverflow,
+    /// A version other than 1.x or 2.x.
+    UnsupportedVersion { major: i16, minor: i16 },
+    /// A well-formed value that is not allowed here.
+    InvalidValue { what: &'static str, expected: &'static str, found: String },
+    /// Compressed input whose decompressor is behind a disabled feature.
+    UnsupportedCompression { format: &'static str, feature: &'static str },
+}
+
+impl std::fmt::Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeErrorKind::UnexpectedEof { what } => write!(f, "unexpected end of data in {what}"),
+            DecodeErrorKind::UnknownTag { what, expected, found } => {
+                write!(f, "unknown {what} tag: expected {expected}, found {found}")
+            }
+            DecodeErrorKind::NegativeLength { what, found } => write!(f, "negative {what} length {found}"),
+            DecodeErrorKind::InvalidUtf8 { what } => write!(f, "invalid UTF-8 in {what}"),
+            DecodeErrorKind::IntegerOverflow => f.write_str("integer does not fit in 32 bits"),
+            DecodeErrorKind::UnsupportedVersion { major, minor } => {
+                write!(f, "unsupported PGF version {major}.{minor}, expected 1.x or 2.x")
+            }
+            DecodeErrorKind::InvalidValue { what, expected, found } => {
+                write!(f, "invalid {what}: expected {expected}, found {found}")
+            }
+            DecodeErrorKind::UnsupportedCompression { format, feature } => {
+                write!(f, "input is {format} compressed; enable the `{feature}` feature to read it")
+            }
+        }
+    }
+}
+
+/// The resource a [`Limits`] bound applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    /// Size of the whole input.
+    Bytes,
+    /// Number of elements of a list.
+    ListLength,
+    /// Length of an identifier or token.
+    StringLength,
+    /// Nesting of types, expressions and patterns.
+    Depth,
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Limit::Bytes => "input size",
+            Limit::ListLength => "list length",
+            Limit::StringLength => "string length",
+            Limit::Depth => "nesting depth",
+        })
+    }
 }
 
 // Represents a Portable Grammar Format (PGF) structure.
@@ -562,16 +690,39 @@ pub struct Pgf {
     r#abstract: Abstract,
     startcat: CId,
     flags: HashMap<CId, Literal>,
+    /// Offsets of concrete syntaxes left out by [`LoadOptions::languages`].
+    unloaded: HashMap<Language, u64>,
+    /// The options the grammar was loaded with, reused by
+    /// [`Pgf::load_language`].
+    #[serde(skip)]
+    options: LoadOptions,
+    /// Length and FNV-1a hash of the input, recorded while some languages are
+    /// unloaded so that they are only ever read from that same input.
+    #[serde(skip)]
+    source: Option<(usize, u64)>,
+}
+
+// The load options are how a grammar was read, not part of it.
+impl PartialEq for Pgf {
+    fn eq(&self, other: &Self) -> bool {
+        self.absname == other.absname
+            && self.concretes == other.concretes
+            && self.r#abstract == other.r#abstract
+            && self.startcat == other.startcat
+            && self.flags == other.flags
+            && self.unloaded == other.unloaded
+    }
 }
 
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Abstract {
+    aflags: HashMap<CId, Literal>,
     funs: HashMap<CId, Function>,
     cats: HashMap<CId, Category>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Concrete {
     cflags: HashMap<CId, Literal>,
     productions: HashMap<i32, Vec<Production>>, // From cCats - changed to Vec for efficiency
@@ -585,7 +736,7 @@ pub struct Concrete {
     total_cats: i32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Function {
     ty: Type,
     weight: i32,
@@ -595,14 +746,139 @@ pub struct Function {
     prob: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Category {
     hypos: Vec<Hypo>,
-    funs: Vec<(usize, CId)>,
+    funs: Vec<(f64, CId)>, // (probability, function name)
+    prob: f64,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub struct CId(String);
+pub struct CId(BytesStr);
+
+/// An immutable UTF-8 string backed by a reference-counted [`Bytes`] buffer.
+///
+/// Identifiers decoded by [`parse_pgf`] are slices of the input buffer
+/// rather than fresh allocations, so every [`CId`] of a grammar loaded with
+/// `read_pgf_mmap` (the `mmap` feature) points straight into the mapped
+/// file. Cloning is cheap and never copies the text.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct BytesStr(Bytes);
+
+impl BytesStr {
+    /// Wraps `bytes` without copying, or returns them back if they are not
+    /// valid UTF-8.
+    ///
+    /// # Errors
+    ///
+    /// Returns the original buffer together with the UTF-8 error.
+    fn from_utf8(bytes: Bytes) -> Result<Self, (Bytes, std::str::Utf8Error)> {
+        match std::str::from_utf8(&bytes) {
+            Ok(_) => Ok(BytesStr(bytes)),
+            Err(e) => Err((bytes, e)),
+        }
+    }
+
+    #[must_use]
+    fn as_str(&self) -> &str {
+        // SAFETY: the only constructors check or preserve UTF-8 validity.
+        unsafe { std::str::from_utf8_unchecked(&self.0) }
+    }
+
+    /// The underlying buffer slice.
+    #[must_use]
+    fn as_bytes(&self) -> &Bytes {
+        &self.0
+    }
+}
+
+impl std::ops::Deref for BytesStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for BytesStr {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl std::borrow::Borrow<str> for BytesStr {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+// Hashed as a `str`, as `Borrow<str>` requires; the derived comparisons
+// already agree with `str` because both compare the UTF-8 bytes.
+impl std::hash::Hash for BytesStr {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl std::fmt::Debug for BytesStr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl std::fmt::Display for BytesStr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&str> for BytesStr {
+    fn from(s: &str) -> Self {
+        BytesStr(Bytes::copy_from_slice(s.as_bytes()))
+    }
+}
+
+impl From<String> for BytesStr {
+    fn from(s: String) -> Self {
+        BytesStr(Bytes::from(s))
+    }
+}
+
+impl From<BytesStr> for String {
+    fn from(s: BytesStr) -> Self {
+        s.as_str().to_owned()
+    }
+}
+
+impl PartialEq<str> for BytesStr {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for BytesStr {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for BytesStr {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl Serialize for BytesStr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for BytesStr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(BytesStr::from)
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Language(CId);
@@ -737,13 +1013,13 @@ pub struct Alt {
     prefixes: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Equation {
     patterns: Vec<Pattern>,
     result: Expr,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Pattern {
     PApp(CId, Vec<Pattern>),
     PVar(CId),
@@ -851,1918 +1127,4080 @@ impl std::hash::Hash for Expr {
     }
 }
 
-pub mod cid {
-    use super::CId;
-
+impl Pgf {
+    /// The name of the abstract syntax.
     #[must_use]
-    pub fn mk_cid(s: &str) -> CId {
-        CId(s.to_string())
+    pub fn abstract_name(&self) -> &CId {
+        &self.absname
     }
 
     #[must_use]
-    pub fn wild_cid() -> CId {
-        CId("*".to_string())
+    pub fn abstract_syntax(&self) -> &Abstract {
+        &self.r#abstract
     }
 
+    /// The start category, from the `startcat` flag or the loader's fallback.
     #[must_use]
-    pub fn show_cid(cid: &CId) -> String {
-        cid.0.clone()
+    pub fn start_cat(&self) -> &CId {
+        &self.startcat
+    }
+
+    /// Global flags of the grammar.
+    pub fn flags(&self) -> impl Iterator<Item = (&CId, &Literal)> + '_ {
+        self.flags.iter()
+    }
+
+    /// The concrete syntaxes, in no particular order.
+    pub fn concretes(&self) -> impl Iterator<Item = (&Language, &Concrete)> + '_ {
+        self.concretes.iter()
     }
 
     #[must_use]
-    pub fn read_cid(s: &str) -> Option<CId> {
-        if s.is_empty() {
-            None
-        } else {
-            Some(CId(s.to_string()))
+    pub fn concrete(&self, lang: &Language) -> Option<&Concrete> {
+        self.concretes.get(lang)
+    }
+
+    /// Languages in the file that were skipped by [`LoadOptions::languages`]
+    /// and can still be loaded with [`Pgf::load_language`].
+    pub fn unloaded_languages(&self) -> impl Iterator<Item = &Language> + '_ {
+        self.unloaded.keys()
+    }
+
+    /// Load a language that was skipped when the grammar was read from `path`.
+    ///
+    /// `path` must be the same file the grammar was loaded from, which is
+    /// checked against the length and hash recorded at load time. Loading a
+    /// language that is already present just returns it. The language is
+    /// decoded with the [`LoadOptions`] the grammar was loaded with: under the
+    /// same limits, strictly or not, and reporting to the same observer. The
+    /// problems recovered from are returned next to it.
+    ///
+    /// # Errors
+    /// Returns `PgfError::UnknownLanguage` if the file has no such language.
+    /// Returns `PgfError::InvalidGrammar` if the file is not the one the
+    /// grammar was loaded from, e.g. because it was rebuilt since.
+    /// Returns `PgfError::Io` if the file cannot be read.
+    /// Returns `PgfError::DeserializeError` if the concrete syntax is malformed.
+    /// Returns `PgfError::LimitExceeded` if the file is over the limits.
+    pub fn load_language(&mut self, path: &str, name: &str) -> Result<(&Concrete, Vec<LoadWarning>), PgfError> {
+        let lang = self.unloaded_language(name)?;
+        let mut warnings = Vec::new();
+        if self.unloaded.contains_key(&lang) {
+            let data = read_input(File::open(path)?, &self.options.limits)?;
+            warnings = self.load_language_at(&data, lang.clone())?;
+        }
+        Ok((&self.concretes[&lang], warnings))
+    }
+
+    /// Like [`Pgf::load_language`], for a grammar that was parsed from `data`.
+    ///
+    /// # Errors
+    /// Returns `PgfError::UnknownLanguage` if the data has no such language.
+    /// Returns `PgfError::InvalidGrammar` if `data` is not what the grammar
+    /// was parsed from.
+    /// Returns `PgfError::DeserializeError` if the concrete syntax is malformed.
+    /// Returns `PgfError::LimitExceeded` if the data is over the limits.
+    pub fn load_language_from(&mut self, data: &Bytes, name: &str) -> Result<(&Concrete, Vec<LoadWarning>), PgfError> {
+        let lang = self.unloaded_language(name)?;
+        let mut warnings = Vec::new();
+        if self.unloaded.contains_key(&lang) {
+            warnings = self.load_language_at(data, lang.clone())?;
+        }
+        Ok((&self.concretes[&lang], warnings))
+    }
+
+    /// Writers call this so that a grammar read with [`LoadOptions::languages`]
+    /// is not exported without the concrete syntaxes it skipped.
+    fn check_fully_loaded(&self) -> Result<(), PgfError> {
+        if self.unloaded.is_empty() {
+            return Ok(());
+        }
+        let mut names: Vec<String> = self.unloaded.keys().map(|lang| cid::show_cid(&lang.0)).collect();
+        names.sort_unstable();
+        Err(PgfError::SerializeError(format!(
+            "concrete syntaxes {} are not loaded; load them with Pgf::load_language first", names.join(", "))))
+    }
+
+    fn unloaded_language(&self, name: &str) -> Result<Language, PgfError> {
+        language::read_language(name)
+            .filter(|lang| self.concretes.contains_key(lang) || self.unloaded.contains_key(lang))
+            .ok_or_else(|| PgfError::UnknownLanguage(name.to_string()))
+    }
+
+    fn load_language_at(&mut self, data: &Bytes, lang: Language) -> Result<Vec<LoadWarning>, PgfError> {
+        let mut cursor = Input::new(data, &self.options);
+        cursor.check(Limit::Bytes, 0, data.len())?;
+        if self.source != Some((data.len(), fnv1a(data))) {
+            return Err(PgfError::InvalidGrammar(format!(
+                "cannot load {}: the input is not the one the grammar was loaded from", lang.name().0)));
         }
+        let major_version = cursor.read_i16::<BigEndian>()
+            .map_err(|e| PgfError::eof(0, "major version", e))?;
+        let offset = self.unloaded[&lang];
+        cursor.set_position(offset);
+        let mut state = LoadState::new(&self.options);
+        let section = format!("concrete[{}]", lang.name().0);
+        state.start(&section, offset);
+        let concrete = read_concrete(&mut cursor, major_version >= 2, lang.name(), &mut state)?;
+        state.end(&section, cursor.position(), None);
+        self.unloaded.remove(&lang);
+        self.concretes.insert(lang, concrete);
+        Ok(state.warnings)
     }
 }
 
-pub mod language {
-    use super::{CId, Language, Pgf, Literal};
+impl Abstract {
+    pub fn flags(&self) -> impl Iterator<Item = (&CId, &Literal)> + '_ {
+        self.aflags.iter()
+    }
 
     #[must_use]
-    pub fn show_language(lang: &Language) -> String {
-        super::cid::show_cid(&lang.0)
+    pub fn flag(&self, name: &str) -> Option<&Literal> {
+        self.aflags.get(&cid::mk_cid(name))
+    }
+
+    /// The abstract functions, in no particular order.
+    pub fn functions(&self) -> impl Iterator<Item = (&CId, &Function)> + '_ {
+        self.funs.iter()
     }
 
     #[must_use]
-    pub fn read_language(s: &str) -> Option<Language> {
-        super::cid::read_cid(s).map(Language)
+    pub fn function(&self, name: &CId) -> Option<&Function> {
+        self.funs.get(name)
+    }
+
+    /// The abstract categories, in no particular order.
+    pub fn categories(&self) -> impl Iterator<Item = (&CId, &Category)> + '_ {
+        self.cats.iter()
     }
 
     #[must_use]
-    pub fn languages(pgf: &Pgf) -> Vec<Language> {
-        pgf.concretes.keys().cloned().collect()
+    pub fn category(&self, name: &CId) -> Option<&Category> {
+        self.cats.get(name)
     }
+}
 
+impl Function {
     #[must_use]
-    pub fn language_code(pgf: &Pgf, lang: &Language) -> Option<String> {
-        pgf.concretes.get(lang).and_then(|cnc| {
-            cnc.cflags.get(&CId("language".to_string())).and_then(|lit| {
-                match lit {
-                    Literal::Str(s) => Some(s.replace('_', "-")),
-                    _ => None,
-                }
-            })
-        })
+    pub fn ty(&self) -> &Type {
+        &self.ty
     }
 
+    /// The number of patterns in the `def` equations (0 without equations).
     #[must_use]
-    pub fn abstract_name(pgf: &Pgf) -> Language {
-        Language(pgf.absname.clone())
+    pub fn arity(&self) -> i32 {
+        self.arity
     }
-}
 
-pub mod types {
-    use super::{CId, Hypo, Type, Pgf};
+    #[must_use]
+    pub fn weight(&self) -> i32 {
+        self.weight
+    }
 
     #[must_use]
-    pub fn mk_type(hypos: Vec<Hypo>, cat: CId, exprs: Vec<super::Expr>) -> Type {
-        Type {
-            hypos,
-            category: cat,
-            exprs,
-        }
+    pub fn is_constructor(&self) -> bool {
+        self.is_constructor
     }
 
+    /// The probability of the function among the functions of its category.
     #[must_use]
-    pub fn mk_hypo(binding: super::Binding, ty: Type) -> Hypo {
-        Hypo { binding, ty }
+    pub fn probability(&self) -> f64 {
+        self.prob
     }
 
+    /// The `def` equations, if the function has a definition.
     #[must_use]
-    pub fn start_cat(pgf: &Pgf) -> Type {
-        Type {
-            hypos: vec![],
-            category: pgf.startcat.clone(),
-            exprs: vec![],
-        }
+    pub fn equations(&self) -> Option<&[Equation]> {
+        self.equations.as_deref()
     }
 }
 
-pub mod parse {
-    use super::{Pgf, Language, Type, Expr, Production, Symbol, PgfError, CncFun, BracketedString, cid};
-    use std::collections::HashMap;
+impl Category {
+    /// The category's context, i.e. the hypotheses of a dependent category.
+    #[must_use]
+    pub fn hypos(&self) -> &[Hypo] {
+        &self.hypos
+    }
 
-    #[derive(Debug, Clone)]
-    pub struct ParseState {
-        pgf: Pgf,
-        lang: Language,
-        typ: Type,
-        active_items: HashMap<i32, Vec<Item>>,
-        passive_items: HashMap<i32, Vec<Item>>,
-        tokens: Vec<String>,
-        current_pos: usize,
+    /// The functions producing this category with their probabilities.
+    pub fn functions(&self) -> impl Iterator<Item = (f64, &CId)> + '_ {
+        self.funs.iter().map(|(prob, name)| (*prob, name))
     }
 
-    #[derive(Debug, Clone)]
-    pub struct Item {
-        fid: i32,
-        seqid: i32,
-        dot: usize,
-        args: Vec<(i32, Expr)>,
-        tree: Option<Expr>,
+    /// The probability of the category (always 0 for PGF 1.0).
+    #[must_use]
+    pub fn probability(&self) -> f64 {
+        self.prob
     }
+}
 
-    #[derive(Debug, Clone)]
-    pub struct ParseInput {
-        pub token: String,
+impl Concrete {
+    pub fn flags(&self) -> impl Iterator<Item = (&CId, &Literal)> + '_ {
+        self.cflags.iter()
     }
 
-    #[derive(Debug, Clone)]
-    pub enum ParseOutput {
-        ParseOk(Vec<Expr>),
-        ParseFail,
+    #[must_use]
+    pub fn flag(&self, name: &str) -> Option<&Literal> {
+        self.cflags.get(&cid::mk_cid(name))
     }
 
-    /// Initialize a parsing state for the given grammar, language, and type.
-    /// 
-    /// # Errors
-    /// Returns `PgfError::UnknownLanguage` if the language is not found in the PGF.
-    /// Returns `PgfError::ParseError` if the category is not found in the concrete syntax.
-    pub fn init_state(pgf: &Pgf, lang: &Language, typ: &Type) -> Result<ParseState, PgfError> {
-        let cnc = pgf.concretes.get(lang).ok_or_else(|| PgfError::UnknownLanguage(cid::show_cid(&lang.0)))?;
-        let cat_id = cnc.cnccats.get(&typ.category)
-            .map(|cat| cat.start)
-            .ok_or_else(|| PgfError::ParseError(format!("Category not found: {}", cid::show_cid(&typ.category))))?;
-        let mut active_items = HashMap::new();
-        if let Some(prods) = cnc.productions.get(&cat_id) {
-            for prod in prods {
-                if let Production::Apply { fid, args: _ } = prod {
-                    let item = Item {
-                        fid: *fid,
-                        seqid: cnc.cncfuns.get(usize::try_from(*fid).map_err(|_| PgfError::DeserializeError { offset: 0, message: "Function ID cannot be negative".to_string() })?).map_or(0, |f| f.lins.first().copied().unwrap_or(0)),
-                        dot: 0,
-                        args: vec![],
-                        tree: None,
-                    };
-                    active_items.entry(cat_id).or_insert_with(Vec::new).push(item);
-                }
-            }
-        }
-        Ok(ParseState {
-            pgf: pgf.clone(),
-            lang: lang.clone(),
-            typ: typ.clone(),
-            active_items,
-            passive_items: HashMap::new(),
-            tokens: vec![],
-            current_pos: 0,
-        })
+    /// All sequences; `CncFun::lins` indexes into this slice.
+    #[must_use]
+    pub fn sequences(&self) -> &[Vec<Symbol>] {
+        &self.sequences
     }
 
-    /// Advance the parsing state with the next input token.
-    /// 
-    /// # Errors
-    /// Returns `PgfError::ParseError` if the language is not found or parsing fails.
-    #[allow(clippy::too_many_lines)]
-    pub fn next_state(state: &mut ParseState, input: &ParseInput) -> Result<(), PgfError> {
-        state.tokens.push(input.token.clone());
-        let cnc = state.pgf.concretes.get(&state.lang)
-            .ok_or_else(|| PgfError::ParseError("Language not found".to_string()))?;
+    #[must_use]
+    pub fn sequence(&self, index: i32) -> Option<&[Symbol]> {
+        usize::try_from(index).ok().and_then(|i| self.sequences.get(i)).map(Vec::as_slice)
+    }
 
-        let mut new_active = HashMap::new();
-        let mut new_passive = state.passive_items.clone();
+    /// All concrete functions; `Production::Apply::fid` indexes into this slice.
+    #[must_use]
+    pub fn functions(&self) -> &[CncFun] {
+        &self.cncfuns
+    }
 
-        for (cat_id, items) in &state.active_items {
-            for item in items {
-                if let Some(seq) = cnc.sequences.get(usize::try_from(item.seqid).map_err(|_| PgfError::DeserializeError { offset: 0, message: "Sequence ID cannot be negative".to_string() })?) {
-                    if item.dot < seq.len() {
-                        match &seq[item.dot] {
-                            Symbol::SymKS(token) => {
-                                if token == &input.token {
-                                    let new_item = Item {
-                                        dot: item.dot + 1,
-                                        ..item.clone()
-                                    };
-                                    new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
-                                }
-                            }
-                            Symbol::SymKP(tokens, alts) => {
-                                let matches = tokens.iter().any(|t| match t {
-                                    Symbol::SymKS(s) => s == &input.token,
-                                    _ => false
-                                }) ||
-                                    alts.iter().any(|alt| alt.tokens.iter().any(|t| match t {
-                                        Symbol::SymKS(s) => s == &input.token,
-                                        _ => false
-                                    }) &&
-                                        alt.prefixes.iter().any(|p| input.token.starts_with(p)));
-                                if matches {
-                                    let new_item = Item {
-                                        dot: item.dot + 1,
-                                        ..item.clone()
-                                    };
-                                    new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
-                                }
-                            }
-                            Symbol::SymCat(_, next_fid) | Symbol::SymLit(_, next_fid) => {
-                                if let Some(passive) = new_passive.get(next_fid) {
-                                    for pitem in passive {
-                                        if let Some(tree) = &pitem.tree {
-                                            let mut new_args = item.args.clone();
-                                            new_args.push((*next_fid, tree.clone()));
-                                            let new_item = Item {
-                                                dot: item.dot + 1,
-                                                args: new_args,
-                                                ..item.clone()
-                                            };
-                                            new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
-                                        }
-                                    }
-                                }
-                            }
-                            Symbol::SymVar(_, next_fid) => {
-                                // Handle variable symbols (e.g., high-order arguments)
-                                let new_item = Item {
-                                    dot: item.dot + 1,
-                                    ..item.clone()
-                                };
-                                new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
-                            }
-                            // Handle all the new symbol types - most don't affect parsing directly
-                            Symbol::SymBind | Symbol::SymSoftBind | Symbol::SymNE | 
-                            Symbol::SymSoftSpace | Symbol::SymCapital | Symbol::SymAllCapital => {
-                                // These symbols generally don't consume input tokens, just advance
-                                let new_item = Item {
-                                    dot: item.dot + 1,
-                                    ..item.clone()
-                                };
-                                new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
-                            }
-                        }
-                    } else {
-                        let tree = build_tree(&cnc.cncfuns[usize::try_from(item.fid).map_err(|_| PgfError::DeserializeError { offset: 0, message: "Function ID cannot be negative".to_string() })?], &item.args);
-                        let passive_item = Item {
-                            tree: Some(tree),
-                            ..item.clone()
-                        };
-                        new_passive.entry(*cat_id).or_default().push(passive_item);
-                    }
-                }
-            }
-        }
+    #[must_use]
+    pub fn function(&self, fid: i32) -> Option<&CncFun> {
+        usize::try_from(fid).ok().and_then(|i| self.cncfuns.get(i))
+    }
 
-        for (cat_id, prods) in &cnc.productions {
-            for prod in prods {
-                if let Production::Coerce { arg } = prod {
-                    if let Some(passive) = new_passive.get(arg) {
-                        for pitem in passive {
-                            if let Some(tree) = &pitem.tree {
-                                let new_item = Item {
-                                    fid: *cat_id,
-                                    seqid: 0,
-                                    dot: 0,
-                                    args: vec![(*arg, tree.clone())],
-                                    tree: None,
-                                };
-                                new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    /// The productions of every concrete category, in no particular order.
+    pub fn productions(&self) -> impl Iterator<Item = (i32, &[Production])> + '_ {
+        self.productions.iter().map(|(fid, prods)| (*fid, prods.as_slice()))
+    }
 
-        state.active_items = new_active;
-        state.passive_items = new_passive;
-        state.current_pos += 1;
-        Ok(())
+    /// The productions of the concrete category `fid`; empty if it has none.
+    #[must_use]
+    pub fn productions_for(&self, fid: i32) -> &[Production] {
+        self.productions.get(&fid).map_or(&[], Vec::as_slice)
     }
 
-    fn build_tree(cnc_fun: &CncFun, args: &[(i32, Expr)]) -> Expr {
-        let mut tree = Expr::Fun(cnc_fun.name.clone());
-        for (_, arg) in args {
-            tree = Expr::App(Box::new(tree), Box::new(arg.clone()));
-        }
-        tree
+    /// The concrete categories, sorted by id.
+    #[must_use]
+    pub fn ccats(&self) -> &[CCat] {
+        &self.ccats
+    }
+
+    /// The ranges of concrete categories per abstract category, in no particular order.
+    pub fn categories(&self) -> impl Iterator<Item = (&CId, &CncCat)> + '_ {
+        self.cnccats.iter()
     }
 
-    /// Get the parse output and bracketed string from the parsing state.
-    /// 
-    /// # Panics
-    /// Panics if the language is not found in the PGF concrete syntaxes.
     #[must_use]
-    pub fn get_parse_output(state: &ParseState, typ: &Type, depth: Option<i32>) -> (ParseOutput, BracketedString) {
-        let max_depth = depth.unwrap_or(i32::MAX);
-        let cnc = state.pgf.concretes.get(&state.lang).expect("Language not found");
-        let cat_id = cnc.cnccats.get(&typ.category).map_or(0, |cat| cat.start);
+    pub fn category(&self, name: &CId) -> Option<&CncCat> {
+        self.cnccats.get(name)
+    }
 
-        let mut trees = vec![];
-        if let Some(items) = state.passive_items.get(&cat_id) {
-            for item in items {
-                if let Some(tree) = &item.tree {
-                    if let Ok(seqid_usize) = usize::try_from(item.seqid) {
-                            if item.dot == cnc.sequences.get(seqid_usize).map_or(0, std::vec::Vec::len) {
-                                trees.push(tree.clone());
-                            }
-                        }
-                    }
-            }
-        }
+    #[must_use]
+    pub fn printnames(&self) -> &[PrintName] {
+        &self.printnames
+    }
 
-        let bracketed = if trees.is_empty() {
-            BracketedString::Leaf(String::new())
-        } else {
-            BracketedString::Branch(typ.category.clone(), trees.iter().map(expr_to_bracketed).collect())
-        };
+    #[must_use]
+    pub fn printname(&self, name: &CId) -> Option<&str> {
+        self.printnames.iter().find(|p| &p.name == name).map(|p| p.printname.as_str())
+    }
 
-        if trees.is_empty() {
-            (ParseOutput::ParseFail, bracketed)
-        } else {
-            (ParseOutput::ParseOk(trees), bracketed)
-        }
+    #[must_use]
+    pub fn lindefs(&self) -> &[LinDef] {
+        &self.lindefs
     }
 
-    fn expr_to_bracketed(expr: &Expr) -> BracketedString {
-        match expr {
-            Expr::Fun(cid) => BracketedString::Leaf(cid::show_cid(cid)),
-            Expr::App(e1, e2) => {
-                let mut children = vec![expr_to_bracketed(e1)];
-                children.push(expr_to_bracketed(e2));
-                BracketedString::Branch(cid::wild_cid(), children)
-            }
-            _ => BracketedString::Leaf(String::new()),
-        }
+    #[must_use]
+    pub fn linrefs(&self) -> &[LinRef] {
+        &self.linrefs
+    }
+
+    /// The number of concrete categories, including the literal categories.
+    #[must_use]
+    pub fn total_cats(&self) -> i32 {
+        self.total_cats
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum BracketedString {
-    Leaf(String),
-    Branch(CId, Vec<BracketedString>),
+impl CId {
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
-/// Read a PGF file from the given path.
-/// 
-/// # Errors
-/// Returns `PgfError::IoError` if the file cannot be read.
-/// Returns other `PgfError` variants if parsing fails.
-pub fn read_pgf(path: &str) -> Result<Pgf, PgfError> {
-    let mut file = File::open(path)?;
-    let mut bytes = Vec::new();
-    file.read_to_end(&mut bytes)?;
-    parse_pgf(&Bytes::from(bytes))
+impl Language {
+    #[must_use]
+    pub fn name(&self) -> &CId {
+        &self.0
+    }
 }
 
-/// Parses a PGF binary data structure from bytes.
-///
-/// # Errors
-///
-/// Returns [`PgfError::DeserializeError`] if:
-/// - The binary data is malformed or truncated
-/// - Version numbers cannot be read from the data
-/// - Any binary field parsing fails during deserialization
-pub fn parse_pgf(data: &Bytes) -> Result<Pgf, PgfError> {
-    let mut cursor = Cursor::new(&data[..]);
-    parse_pgf_binary(&mut cursor)
+impl Hypo {
+    #[must_use]
+    pub fn binding(&self) -> &Binding {
+        &self.binding
+    }
+
+    #[must_use]
+    pub fn ty(&self) -> &Type {
+        &self.ty
+    }
 }
 
-fn parse_pgf_binary(cursor: &mut Cursor<&[u8]>) -> Result<Pgf, PgfError> {
-    let offset = cursor.position();
-    let file_size = cursor.get_ref().len();
-    let major_version = cursor.read_i16::<BigEndian>()
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read major version: {e}") })?;
-    let minor_version = cursor.read_i16::<BigEndian>()
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read minor version: {e}") })?;
+impl Type {
+    #[must_use]
+    pub fn hypos(&self) -> &[Hypo] {
+        &self.hypos
+    }
 
-    if !(1..=2).contains(&major_version) {
-        return Err(PgfError::DeserializeError {
-            offset,
-            message: format!("Unsupported PGF version: {major_version}.{minor_version}"),
-        });
+    #[must_use]
+    pub fn category(&self) -> &CId {
+        &self.category
     }
 
-    // Properly detect PGF version for format handling
-    let is_pgf_2_1 = major_version == 2 && minor_version == 1;
-    println!("PARSER: PGF version {major_version}.{minor_version}, is_pgf_2_1={is_pgf_2_1}");
+    /// The arguments of a dependent type.
+    #[must_use]
+    pub fn exprs(&self) -> &[Expr] {
+        &self.exprs
+    }
+}
 
-    // Pass is_pgf_2_1 to functions that call read_string
-    debug_println!("Reading flags...");
-    let flags = read_flags(cursor, is_pgf_2_1)?;
-    debug_println!("Reading abstract...");
-    let (absname, r#abstract) = read_abstract(cursor, is_pgf_2_1)?;
-    let pos_before_concretes = cursor.position();
-    println!("PARSER: Reading concretes at position {pos_before_concretes}...");
-    let concretes = match read_concretes(cursor, is_pgf_2_1) {
-        Ok(c) => {
-            debug_println!("Successfully parsed {} concretes", c.len());
-            c
-        }
-        Err(e) => {
-            debug_println!("Concrete parsing failed: {:?}", e);
-            return Err(e);
-        }
-    };
-    debug_println!("Parsing complete!");
+impl CncCat {
+    #[must_use]
+    pub fn name(&self) -> &CId {
+        &self.name
+    }
 
-    let startcat = flags.get(&cid::mk_cid("startcat"))
-        .and_then(|lit| match lit {
-            Literal::Str(s) => {
-                println!("PARSER: Found startcat flag: {s}");
-                Some(cid::mk_cid(s))
-            }
-            _ => None,
-        })
-        .unwrap_or_else(|| {
-            println!("PARSER: No startcat flag found, using fallback");
-            // Look for common startcat names first, then fall back to alphabetical order
-            let common_startcats = ["Phrase", "Utt", "S", "Sentence"];
-            for candidate in &common_startcats {
-                let candidate_cid = cid::mk_cid(candidate);
-                if r#abstract.cats.contains_key(&candidate_cid) {
-                    println!("PARSER: Using common startcat: {}", candidate);
-                    return candidate_cid;
-                }
-            }
-            // If no common startcat found, use first alphabetically
-            r#abstract.cats.keys().next().cloned().unwrap_or(cid::mk_cid("S"))
-        });
+    #[must_use]
+    pub fn start(&self) -> i32 {
+        self.start
+    }
 
-    Ok(Pgf {
-        absname,
-        concretes,
-        r#abstract,
-        startcat,
-        flags,
+    #[must_use]
+    pub fn end(&self) -> i32 {
+        self.end
+    }
+
+    /// The concrete categories `start..=end`.
+    #[must_use]
+    pub fn fids(&self) -> std::ops::RangeInclusive<i32> {
+        self.start..=self.end
+    }
+
+    /// The names of the category's fields (constituents).
+    #[must_use]
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+}
+
+impl CncFun {
+    #[must_use]
+    pub fn name(&self) -> &CId {
+        &self.name
+    }
+
+    /// One sequence index per field of the result category.
+    #[must_use]
+    pub fn lins(&self) -> &[i32] {
+        &self.lins
+    }
+}
+
+impl PArg {
+    /// An argument of concrete category `fid`; `hypos` are the categories of
+    /// its high-order arguments.
+    #[must_use]
+    pub fn new(hypos: Vec<i32>, fid: i32) -> Self {
+        PArg { hypos, fid }
+    }
+
+    #[must_use]
+    pub fn hypos(&self) -> &[i32] {
+        &self.hypos
+    }
+
+    #[must_use]
+    pub fn fid(&self) -> i32 {
+        self.fid
+    }
+}
+
+impl PrintName {
+    #[must_use]
+    pub fn name(&self) -> &CId {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn printname(&self) -> &str {
+        &self.printname
+    }
+}
+
+impl LinDef {
+    #[must_use]
+    pub fn cat(&self) -> i32 {
+        self.cat
+    }
+
+    #[must_use]
+    pub fn funs(&self) -> &[i32] {
+        &self.funs
+    }
+}
+
+impl LinRef {
+    #[must_use]
+    pub fn cat(&self) -> i32 {
+        self.cat
+    }
+
+    #[must_use]
+    pub fn funs(&self) -> &[i32] {
+        &self.funs
+    }
+}
+
+impl CCat {
+    #[must_use]
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    #[must_use]
+    pub fn productions(&self) -> &[Production] {
+        &self.productions
+    }
+}
+
+impl Alt {
+    #[must_use]
+    pub fn new(tokens: Vec<Symbol>, prefixes: Vec<String>) -> Self {
+        Alt { tokens, prefixes }
+    }
+
+    #[must_use]
+    pub fn tokens(&self) -> &[Symbol] {
+        &self.tokens
+    }
+
+    /// The prefixes of the next token that select this alternative.
+    #[must_use]
+    pub fn prefixes(&self) -> &[String] {
+        &self.prefixes
+    }
+}
+
+impl Equation {
+    #[must_use]
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    #[must_use]
+    pub fn result(&self) -> &Expr {
+        &self.result
+    }
+}
+
+/// Builds a [`Pgf`] in code.
+///
+/// Categories and functions are declared on the abstract syntax, concrete
+/// syntaxes are described with [`ConcreteBuilder`]. Index consistency is
+/// checked by [`PgfBuilder::build`].
+///
+/// ```
+/// use pgf2json::{cid, types, ConcreteBuilder, PgfBuilder, Production, Symbol};
+///
+/// let mut cnc = ConcreteBuilder::new();
+/// let hello = cnc.add_sequence(vec![Symbol::SymKS("hello".to_string())]);
+/// let fun = cnc.add_function("Hello", vec![hello]);
+/// cnc.category("Greeting", 0, 0, vec!["s".to_string()])
+///     .production(0, Production::Apply { fid: fun, args: vec![] });
+///
+/// let pgf = PgfBuilder::new("Hello")
+///     .cat("Greeting", vec![])
+///     .fun("Hello", types::mk_type(vec![], cid::mk_cid("Greeting"), vec![]))
+///     .start_cat("Greeting")
+///     .concrete("HelloEng", cnc)
+///     .build()
+///     .expect("invalid grammar");
+/// assert_eq!(pgf.start_cat().as_str(), "Greeting");
+/// ```
+#[derive(Debug, Clone)]
+pub struct PgfBuilder {
+    absname: CId,
+    flags: HashMap<CId, Literal>,
+    aflags: HashMap<CId, Literal>,
+    cats: Vec<(CId, Vec<Hypo>)>,
+    funs: Vec<(CId, Type, Option<f64>)>,
+    concretes: Vec<(Language, ConcreteBuilder)>,
+}
+
+impl PgfBuilder {
+    #[must_use]
+    pub fn new(absname: &str) -> Self {
+        PgfBuilder {
+            absname: cid::mk_cid(absname),
+            flags: HashMap::new(),
+            aflags: HashMap::new(),
+            cats: vec![],
+            funs: vec![],
+            concretes: vec![],
+        }
+    }
+
+    /// Set a global flag.
+    pub fn flag(&mut self, name: &str, value: Literal) -> &mut Self {
+        self.flags.insert(cid::mk_cid(name), value);
+        self
+    }
+
+    /// Set a flag of the abstract syntax.
+    pub fn abstract_flag(&mut self, name: &str, value: Literal) -> &mut Self {
+        self.aflags.insert(cid::mk_cid(name), value);
+        self
+    }
+
+    /// Set the `startcat` flag.
+    pub fn start_cat(&mut self, name: &str) -> &mut Self {
+        self.abstract_flag("startcat", Literal::Str(name.to_string()))
+    }
+
+    /// Declare a category with the given context.
+    pub fn cat(&mut self, name: &str, hypos: Vec<Hypo>) -> &mut Self {
+        self.cats.push((cid::mk_cid(name), hypos));
+        self
+    }
+
+    /// Declare a function. Functions without an explicit probability share
+    /// the probability left over in their category equally.
+    pub fn fun(&mut self, name: &str, ty: Type) -> &mut Self {
+        self.funs.push((cid::mk_cid(name), ty, None));
+        self
+    }
+
+    pub fn fun_with_probability(&mut self, name: &str, ty: Type, prob: f64) -> &mut Self {
+        self.funs.push((cid::mk_cid(name), ty, Some(prob)));
+        self
+    }
+
+    /// Add a concrete syntax for the language `lang`.
+    pub fn concrete(&mut self, lang: &str, concrete: ConcreteBuilder) -> &mut Self {
+        self.concretes.push((Language(cid::mk_cid(lang)), concrete));
+        self
+    }
+
+    /// Build the grammar.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PgfError::InvalidGrammar`] if a name is declared twice, a
+    /// type refers to an undeclared category, or a concrete syntax refers to
+    /// a sequence, function or category that does not exist.
+    pub fn build(&self) -> Result<Pgf, PgfError> {
+        fn invalid<T>(message: String) -> Result<T, PgfError> {
+            Err(PgfError::InvalidGrammar(message))
+        }
+
+        let mut cats = HashMap::new();
+        for (name, hypos) in &self.cats {
+            let cat = Category { hypos: hypos.clone(), funs: vec![], prob: 0.0 };
+            if cats.insert(name.clone(), cat).is_some() {
+                return invalid(format!("category {} is declared twice", name.0));
+            }
+        }
+        let check_type = |owner: &CId, ty: &Type| -> Result<(), PgfError> {
+            let mut pending = vec![ty];
+            while let Some(ty) = pending.pop() {
+                if !cats.contains_key(&ty.category) {
+                    return invalid(format!("{} refers to undeclared category {}", owner.0, ty.category.0));
+                }
+                pending.extend(ty.hypos.iter().map(|hypo| &hypo.ty));
+            }
+            Ok(())
+        };
+        for (name, cat) in &cats {
+            for hypo in &cat.hypos {
+                check_type(name, &hypo.ty)?;
+            }
+        }
+
+        let mut funs = HashMap::new();
+        for (name, ty, prob) in &self.funs {
+            check_type(name, ty)?;
+            // Compiled the way GF compiles a `fun` judgement without `def` rules.
+            let fun = Function {
+                ty: ty.clone(),
+                weight: 1,
+                equations: Some(vec![]),
+                arity: 0,
+                is_constructor: false,
+                prob: prob.unwrap_or(0.0),
+            };
+            if funs.insert(name.clone(), fun).is_some() {
+                return invalid(format!("function {} is declared twice", name.0));
+            }
+        }
+        for (name, cat) in &mut cats {
+            let mut members: Vec<&(CId, Type, Option<f64>)> = self.funs.iter()
+                .filter(|(_, ty, _)| &ty.category == name)
+                .collect();
+            members.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+            let explicit: f64 = members.iter().filter_map(|(_, _, prob)| *prob).sum();
+            let unspecified = members.iter().filter(|(_, _, prob)| prob.is_none()).count();
+            #[allow(clippy::cast_precision_loss)]
+            let share = if unspecified == 0 { 0.0 } else { (1.0 - explicit).max(0.0) / unspecified as f64 };
+            for (fun_name, _, prob) in members {
+                let prob = prob.unwrap_or(share);
+                if let Some(fun) = funs.get_mut(fun_name) {
+                    fun.prob = prob;
+                }
+                cat.funs.push((prob, fun_name.clone()));
+            }
+        }
+        let r#abstract = Abstract { aflags: self.aflags.clone(), funs, cats };
+
+        let startcat = match r#abstract.aflags.get(&cid::mk_cid("startcat")) {
+            Some(Literal::Str(name)) if r#abstract.cats.contains_key(&cid::mk_cid(name)) => cid::mk_cid(name),
+            Some(Literal::Str(name)) => return invalid(format!("start category {name} is not declared")),
+            _ => self.cats.first().map_or_else(|| cid::mk_cid("S"), |(name, _)| name.clone()),
+        };
+
+        let mut concretes = HashMap::new();
+        for (lang, builder) in &self.concretes {
+            let concrete = builder.build(&r#abstract)
+                .map_err(|message| PgfError::InvalidGrammar(format!("concrete {}: {message}", lang.0.0)))?;
+            if concretes.insert(lang.clone(), concrete).is_some() {
+                return invalid(format!("concrete {} is declared twice", lang.0.0));
+            }
+        }
+
+        Ok(Pgf { absname: self.absname.clone(), concretes, r#abstract, startcat, flags: self.flags.clone(), unloaded: HashMap::new(), options: LoadOptions::default(), source: None })
+    }
+}
+
+/// Builds the concrete syntax of one language for a [`PgfBuilder`].
+///
+/// Sequences and functions are numbered in the order they are added;
+/// identical sequences are shared.
+#[derive(Debug, Clone, Default)]
+pub struct ConcreteBuilder {
+    cflags: HashMap<CId, Literal>,
+    printnames: Vec<PrintName>,
+    sequences: Vec<Vec<Symbol>>,
+    cncfuns: Vec<CncFun>,
+    cnccats: Vec<CncCat>,
+    productions: BTreeMap<i32, Vec<Production>>,
+    lindefs: BTreeMap<i32, Vec<i32>>,
+    linrefs: BTreeMap<i32, Vec<i32>>,
+}
+
+impl ConcreteBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        ConcreteBuilder::default()
+    }
+
+    pub fn flag(&mut self, name: &str, value: Literal) -> &mut Self {
+        self.cflags.insert(cid::mk_cid(name), value);
+        self
+    }
+
+    /// Set the display name of an abstract function or category.
+    pub fn printname(&mut self, name: &str, printname: &str) -> &mut Self {
+        self.printnames.push(PrintName { name: cid::mk_cid(name), printname: printname.to_string() });
+        self
+    }
+
+    /// Add a sequence and return its index, reusing an identical sequence.
+    pub fn add_sequence(&mut self, symbols: Vec<Symbol>) -> i32 {
+        let index = self.sequences.iter().position(|seq| seq == &symbols).unwrap_or_else(|| {
+            self.sequences.push(symbols);
+            self.sequences.len() - 1
+        });
+        i32::try_from(index).unwrap_or(i32::MAX)
+    }
+
+    /// Add a concrete function with one sequence index per field and return
+    /// its index, for use in [`Production::Apply`].
+    pub fn add_function(&mut self, name: &str, lins: Vec<i32>) -> i32 {
+        self.cncfuns.push(CncFun { name: cid::mk_cid(name), lins });
+        i32::try_from(self.cncfuns.len() - 1).unwrap_or(i32::MAX)
+    }
+
+    /// Map the abstract category `name` to the concrete categories
+    /// `start..=end`, whose fields are named by `labels`.
+    pub fn category(&mut self, name: &str, start: i32, end: i32, labels: Vec<String>) -> &mut Self {
+        self.cnccats.push(CncCat { name: cid::mk_cid(name), start, end, labels });
+        self
+    }
+
+    /// Add a production for the concrete category `cat`.
+    pub fn production(&mut self, cat: i32, production: Production) -> &mut Self {
+        self.productions.entry(cat).or_default().push(production);
+        self
+    }
+
+    /// Add the function `fun` as a default linearization of `cat`.
+    pub fn lindef(&mut self, cat: i32, fun: i32) -> &mut Self {
+        self.lindefs.entry(cat).or_default().push(fun);
+        self
+    }
+
+    /// Add the function `fun` as a linearization reference of `cat`.
+    pub fn linref(&mut self, cat: i32, fun: i32) -> &mut Self {
+        self.linrefs.entry(cat).or_default().push(fun);
+        self
+    }
+
+    /// The concrete categories a production may refer to: those in a
+    /// declared range, the literal categories and the coercion categories
+    /// that GF numbers after all ranges.
+    fn known_cats(&self) -> std::collections::HashSet<i32> {
+        // Literal categories (String, Int, Float) use the fixed ids -1..-3.
+        const LITERAL_FIDS: std::ops::RangeInclusive<i32> = -3..=-1;
+
+        let mut known: std::collections::HashSet<i32> = self.cnccats.iter()
+            .flat_map(CncCat::fids)
+            .chain(LITERAL_FIDS)
+            .collect();
+        let coercions: Vec<i32> = self.productions.iter()
+            .filter(|(cat, prods)| {
+                !known.contains(cat) && !prods.is_empty()
+                    && prods.iter().all(|prod| matches!(prod, Production::Coerce { arg } if known.contains(arg)))
+            })
+            .map(|(&cat, _)| cat)
+            .collect();
+        known.extend(coercions);
+        known
+    }
+
+    fn build(&self, abs: &Abstract) -> Result<Concrete, String> {
+
+        let total_cats = self.cnccats.iter().map(|cat| cat.end)
+            .chain(self.productions.keys().copied())
+            .chain(self.lindefs.keys().copied())
+            .chain(self.linrefs.keys().copied())
+            .max()
+            .map_or(0, |max| max.max(-1) + 1);
+        let known_cats = self.known_cats();
+        let check_cat = |what: &str, fid: i32| {
+            if known_cats.contains(&fid) {
+                Ok(())
+            } else {
+                Err(format!("{what} refers to unknown concrete category {fid}"))
+            }
+        };
+        let check_fun = |what: &str, fun: i32| {
+            if usize::try_from(fun).is_ok_and(|i| i < self.cncfuns.len()) {
+                Ok(())
+            } else {
+                Err(format!("{what} refers to unknown concrete function {fun}"))
+            }
+        };
+
+        for (index, fun) in self.cncfuns.iter().enumerate() {
+            let helper = fun.name.0.starts_with("lindef ") || fun.name.0.starts_with("linref ");
+            if !helper && !abs.funs.contains_key(&fun.name) {
+                return Err(format!("function {index} ({}) is not an abstract function", fun.name.0));
+            }
+            if let Some(lin) = fun.lins.iter().find(|&&lin| usize::try_from(lin).map_or(true, |i| i >= self.sequences.len())) {
+                return Err(format!("function {index} ({}) refers to unknown sequence {lin}", fun.name.0));
+            }
+        }
+        for cat in &self.cnccats {
+            if !abs.cats.contains_key(&cat.name) {
+                return Err(format!("category {} is not an abstract category", cat.name.0));
+            }
+            if cat.start > cat.end {
+                return Err(format!("category {} has an empty range {}..={}", cat.name.0, cat.start, cat.end));
+            }
+        }
+        for (&cat, prods) in &self.productions {
+            if cat < 0 {
+                return Err(format!("productions for negative category {cat}"));
+            }
+            check_cat("a production", cat)?;
+            for prod in prods {
+                let what = format!("a production of category {cat}");
+                match prod {
+                    Production::Apply { fid, args } => {
+                        check_fun(&what, *fid)?;
+                        let fun = &self.cncfuns[usize::try_from(*fid).unwrap_or_default()];
+                        if let Some(abs_fun) = abs.funs.get(&fun.name) {
+                            if abs_fun.ty.hypos.len() != args.len() {
+                                return Err(format!("{what} applies {} to {} arguments, expected {}", fun.name.0, args.len(), abs_fun.ty.hypos.len()));
+                            }
+                        }
+                        for arg in args {
+                            check_cat(&what, arg.fid)?;
+                            for &hypo in &arg.hypos {
+                                check_cat(&what, hypo)?;
+                            }
+                        }
+                    }
+                    Production::Coerce { arg } => check_cat(&what, *arg)?,
+                    Production::Const { .. } => {}
+                }
+            }
+        }
+        for (kind, defs) in [("lindef", &self.lindefs), ("linref", &self.linrefs)] {
+            for (&cat, funs) in defs {
+                check_cat(&format!("a {kind}"), cat)?;
+                for &fun in funs {
+                    check_fun(&format!("the {kind} of category {cat}"), fun)?;
+                }
+            }
+        }
+
+        let mut ccat_ids: Vec<i32> = self.productions.keys()
+            .chain(self.lindefs.keys())
+            .chain(self.linrefs.keys())
+            .copied()
+            .collect();
+        ccat_ids.sort_unstable();
+        ccat_ids.dedup();
+        let ccats: Vec<CCat> = ccat_ids.into_iter()
+            .map(|id| CCat { id, productions: self.productions.get(&id).cloned().unwrap_or_default() })
+            .collect();
+
+        Ok(Concrete {
+            cflags: self.cflags.clone(),
+            productions: ccats.iter().map(|ccat| (ccat.id, ccat.productions.clone())).collect(),
+            cncfuns: self.cncfuns.clone(),
+            sequences: self.sequences.clone(),
+            cnccats: self.cnccats.iter().map(|cat| (cat.name.clone(), cat.clone())).collect(),
+            printnames: self.printnames.clone(),
+            lindefs: self.lindefs.iter().map(|(&cat, funs)| LinDef { cat, funs: funs.clone() }).collect(),
+            linrefs: self.linrefs.iter().map(|(&cat, funs)| LinRef { cat, funs: funs.clone() }).collect(),
+            ccats,
+            total_cats,
+        })
+    }
+}
+
+/// How serious a [`ValidationIssue`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The grammar is usable, but some trees cannot be linearized.
+    Warning,
+    /// A reference points at something that does not exist.
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A referential-integrity problem found by [`validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    /// The concrete syntax the problem was found in.
+    pub language: Language,
+    /// The offending structure, e.g. `concrete[FoodEng].cncfuns[3].lins[0]`.
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.path, self.message)
+    }
+}
+
+/// Check that the indices inside each concrete syntax of `pgf` point at
+/// things that exist.
+///
+/// Reports function linearizations that refer to missing sequences,
+/// productions that apply missing functions, sequences that use argument
+/// positions beyond the arity of a production that applies them or fields
+/// beyond the field count of an argument's category, and abstract functions
+/// without a concrete function. Issues are ordered by
+/// language and then by position in the grammar.
+#[must_use]
+pub fn validate(pgf: &Pgf) -> Vec<ValidationIssue> {
+    let mut concretes: Vec<(&Language, &Concrete)> = pgf.concretes.iter().collect();
+    concretes.sort_by(|(a, _), (b, _)| a.0.0.cmp(&b.0.0));
+    let mut issues = Vec::new();
+    for (lang, cnc) in concretes {
+        validate_concrete(&pgf.r#abstract, lang, cnc, &mut issues);
+    }
+    issues
+}
+
+fn validate_concrete(abs: &Abstract, lang: &Language, cnc: &Concrete, issues: &mut Vec<ValidationIssue>) {
+    let mut report = |severity, path: String, message: String| {
+        issues.push(ValidationIssue { severity, language: lang.clone(), path, message });
+    };
+    let prefix = format!("concrete[{}]", lang.0.0);
+    let sequence = |index: i32| usize::try_from(index).ok().and_then(|i| cnc.sequences.get(i));
+    let function = |fid: i32| usize::try_from(fid).ok().and_then(|i| cnc.cncfuns.get(i));
+    // The number of fields of a concrete category, looking through coercions.
+    let field_count = |fid: i32| -> Option<usize> {
+        let labels = |fid: i32| cnc.cnccats.values()
+            .find(|cat| cat.fids().contains(&fid))
+            .map(|cat| cat.labels.len())
+            .filter(|&count| count > 0);
+        if (-3..=-1).contains(&fid) {
+            return Some(1);
+        }
+        labels(fid).or_else(|| match cnc.productions.get(&fid)?.first()? {
+            Production::Coerce { arg } => labels(*arg),
+            _ => None,
+        })
+    };
+
+    for (index, fun) in cnc.cncfuns.iter().enumerate() {
+        for (field, &lin) in fun.lins.iter().enumerate() {
+            if sequence(lin).is_none() {
+                report(
+                    Severity::Error,
+                    format!("{prefix}.cncfuns[{index}].lins[{field}]"),
+                    format!("{} refers to unknown sequence {lin}", fun.name.0),
+                );
+            }
+        }
+    }
+
+    let mut productions: Vec<(&i32, &Vec<Production>)> = cnc.productions.iter().collect();
+    productions.sort_by_key(|(fid, _)| **fid);
+    for (cat, prods) in productions {
+        for (index, prod) in prods.iter().enumerate() {
+            let Production::Apply { fid, args } = prod else { continue };
+            let path = format!("{prefix}.productions[{cat}][{index}]");
+            let Some(fun) = function(*fid) else {
+                report(Severity::Error, path, format!("applies unknown concrete function {fid}"));
+                continue;
+            };
+            // Only the first out-of-range argument or field of each sequence is reported.
+            for &lin in &fun.lins {
+                let Some(symbols) = sequence(lin) else { continue };
+                if let Some(arg) = symbols.iter().find_map(|sym| out_of_range_arg(sym, args.len())) {
+                    report(
+                        Severity::Error,
+                        path.clone(),
+                        format!("sequence {lin} of {} uses argument {arg}, but the production has {} arguments", fun.name.0, args.len()),
+                    );
+                    continue;
+                }
+                let out_of_range_field = |&(arg, field): &(i32, i32)| {
+                    let cat = args[usize::try_from(arg).ok()?].fid;
+                    let count = field_count(cat)?;
+                    usize::try_from(field).map_or(true, |f| f >= count).then_some((arg, field, cat, count))
+                };
+                if let Some((arg, field, cat, count)) = symbols.iter().flat_map(field_refs).find_map(|r| out_of_range_field(&r)) {
+                    report(
+                        Severity::Error,
+                        path.clone(),
+                        format!("sequence {lin} of {} uses field {field} of argument {arg}, but its category {cat} has {count} fields", fun.name.0),
+                    );
+                }
+            }
+        }
+    }
+
+    let implemented: std::collections::HashSet<&CId> = cnc.cncfuns.iter().map(|fun| &fun.name).collect();
+    for (name, _) in sorted_by_cid(&abs.funs) {
+        if !implemented.contains(name) {
+            report(
+                Severity::Warning,
+                format!("{prefix}.cncfuns"),
+                format!("abstract function {} has no linearization", name.0),
+            );
+        }
+    }
+}
+
+/// The argument index a symbol refers to, if it is not below `arity`.
+fn out_of_range_arg(sym: &Symbol, arity: usize) -> Option<i32> {
+    match sym {
+        Symbol::SymCat(arg, _) | Symbol::SymLit(arg, _) | Symbol::SymVar(arg, _) => {
+            usize::try_from(*arg).map_or(true, |i| i >= arity).then_some(*arg)
+        }
+        Symbol::SymKP(tokens, alts) => tokens.iter()
+            .chain(alts.iter().flat_map(|alt| &alt.tokens))
+            .find_map(|sym| out_of_range_arg(sym, arity)),
+        _ => None,
+    }
+}
+
+/// The `(argument, field)` pairs a symbol reads, including inside `pre`.
+fn field_refs(sym: &Symbol) -> Vec<(i32, i32)> {
+    match sym {
+        Symbol::SymCat(arg, field) | Symbol::SymLit(arg, field) => vec![(*arg, *field)],
+        Symbol::SymKP(tokens, alts) => tokens.iter()
+            .chain(alts.iter().flat_map(|alt| &alt.tokens))
+            .flat_map(field_refs)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+pub mod cid {
+    use super::CId;
+
+    #[must_use]
+    pub fn mk_cid(s: &str) -> CId {
+        CId(s.into())
+    }
+
+    #[must_use]
+    pub fn wild_cid() -> CId {
+        CId("*".into())
+    }
+
+    #[must_use]
+    pub fn show_cid(cid: &CId) -> String {
+        cid.0.to_string()
+    }
+
+    #[must_use]
+    pub fn read_cid(s: &str) -> Option<CId> {
+        if s.is_empty() {
+            None
+        } else {
+            Some(CId(s.into()))
+        }
+    }
+}
+
+pub mod language {
+    use super::{CId, Language, Pgf, Literal};
+
+    #[must_use]
+    pub fn show_language(lang: &Language) -> String {
+        super::cid::show_cid(&lang.0)
+    }
+
+    #[must_use]
+    pub fn read_language(s: &str) -> Option<Language> {
+        super::cid::read_cid(s).map(Language)
+    }
+
+    #[must_use]
+    pub fn languages(pgf: &Pgf) -> Vec<Language> {
+        pgf.concretes.keys().cloned().collect()
+    }
+
+    #[must_use]
+    pub fn language_code(pgf: &Pgf, lang: &Language) -> Option<String> {
+        pgf.concretes.get(lang).and_then(|cnc| {
+            cnc.cflags.get(&super::cid::mk_cid("language")).and_then(|lit| {
+                match lit {
+                    Literal::Str(s) => Some(s.replace('_', "-")),
+                    _ => None,
+                }
+            })
+        })
+    }
+
+    #[must_use]
+    pub fn abstract_name(pgf: &Pgf) -> Language {
+        Language(pgf.absname.clone())
+    }
+}
+
+pub mod types {
+    use super::{CId, Hypo, Type, Pgf};
+
+    #[must_use]
+    pub fn mk_type(hypos: Vec<Hypo>, cat: CId, exprs: Vec<super::Expr>) -> Type {
+        Type {
+            hypos,
+            category: cat,
+            exprs,
+        }
+    }
+
+    #[must_use]
+    pub fn mk_hypo(binding: super::Binding, ty: Type) -> Hypo {
+        Hypo { binding, ty }
+    }
+
+    #[must_use]
+    pub fn start_cat(pgf: &Pgf) -> Type {
+        Type {
+            hypos: vec![],
+            category: pgf.startcat.clone(),
+            exprs: vec![],
+        }
+    }
+}
+
+pub mod parse {
+    use super::{Pgf, Language, Type, Expr, Production, Symbol, PgfError, CncFun, BracketedString, cid};
+    use std::collections::HashMap;
+
+    #[derive(Debug, Clone)]
+    pub struct ParseState {
+        pgf: Pgf,
+        lang: Language,
+        typ: Type,
+        active_items: HashMap<i32, Vec<Item>>,
+        passive_items: HashMap<i32, Vec<Item>>,
+        tokens: Vec<String>,
+        current_pos: usize,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct Item {
+        fid: i32,
+        seqid: i32,
+        dot: usize,
+        args: Vec<(i32, Expr)>,
+        tree: Option<Expr>,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct ParseInput {
+        pub token: String,
+    }
+
+    #[derive(Debug, Clone)]
+    pub enum ParseOutput {
+        ParseOk(Vec<Expr>),
+        ParseFail,
+    }
+
+    /// Initialize a parsing state for the given grammar, language, and type.
+    /// 
+    /// # Errors
+    /// Returns `PgfError::UnknownLanguage` if the language is not found in the PGF.
+    /// Returns `PgfError::ParseError` if the category is not found in the concrete syntax.
+    pub fn init_state(pgf: &Pgf, lang: &Language, typ: &Type) -> Result<ParseState, PgfError> {
+        let cnc = pgf.concretes.get(lang).ok_or_else(|| PgfError::UnknownLanguage(cid::show_cid(&lang.0)))?;
+        let cat_id = cnc.cnccats.get(&typ.category)
+            .map(|cat| cat.start)
+            .ok_or_else(|| PgfError::ParseError(format!("Category not found: {}", cid::show_cid(&typ.category))))?;
+        let mut active_items = HashMap::new();
+        if let Some(prods) = cnc.productions.get(&cat_id) {
+            for prod in prods {
+                if let Production::Apply { fid, args: _ } = prod {
+                    let item = Item {
+                        fid: *fid,
+                        seqid: cnc.cncfuns.get(usize::try_from(*fid).map_err(|_| negative_id(lang, "productions", "a non-negative function id", *fid))?).map_or(0, |f| f.lins.first().copied().unwrap_or(0)),
+                        dot: 0,
+                        args: vec![],
+                        tree: None,
+                    };
+                    active_items.entry(cat_id).or_insert_with(Vec::new).push(item);
+                }
+            }
+        }
+        Ok(ParseState {
+            pgf: pgf.clone(),
+            lang: lang.clone(),
+            typ: typ.clone(),
+            active_items,
+            passive_items: HashMap::new(),
+            tokens: vec![],
+            current_pos: 0,
+        })
+    }
+
+    /// Advance the parsing state with the next input token.
+    /// 
+    /// # Errors
+    /// Returns `PgfError::ParseError` if the language is not found or parsing fails.
+    #[allow(clippy::too_many_lines)]
+    pub fn next_state(state: &mut ParseState, input: &ParseInput) -> Result<(), PgfError> {
+        state.tokens.push(input.token.clone());
+        let cnc = state.pgf.concretes.get(&state.lang)
+            .ok_or_else(|| PgfError::ParseError("Language not found".to_string()))?;
+
+        let mut new_active = HashMap::new();
+        let mut new_passive = state.passive_items.clone();
+
+        for (cat_id, items) in &state.active_items {
+            for item in items {
+                if let Some(seq) = cnc.sequences.get(usize::try_from(item.seqid).map_err(|_| negative_id(&state.lang, "cncfuns", "a non-negative sequence id", item.seqid))?) {
+                    if item.dot < seq.len() {
+                        match &seq[item.dot] {
+                            Symbol::SymKS(token) => {
+                                if token == &input.token {
+                                    let new_item = Item {
+                                        dot: item.dot + 1,
+                                        ..item.clone()
+                                    };
+                                    new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
+                                }
+                            }
+                            Symbol::SymKP(tokens, alts) => {
+                                let matches = tokens.iter().any(|t| match t {
+                                    Symbol::SymKS(s) => s == &input.token,
+                                    _ => false
+                                }) ||
+                                    alts.iter().any(|alt| alt.tokens.iter().any(|t| match t {
+                                        Symbol::SymKS(s) => s == &input.token,
+                                        _ => false
+                                    }) &&
+                                        alt.prefixes.iter().any(|p| input.token.starts_with(p)));
+                                if matches {
+                                    let new_item = Item {
+                                        dot: item.dot + 1,
+                                        ..item.clone()
+                                    };
+                                    new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
+                                }
+                            }
+                            Symbol::SymCat(_, next_fid) | Symbol::SymLit(_, next_fid) => {
+                                if let Some(passive) = new_passive.get(next_fid) {
+                                    for pitem in passive {
+                                        if let Some(tree) = &pitem.tree {
+                                            let mut new_args = item.args.clone();
+                                            new_args.push((*next_fid, tree.clone()));
+                                            let new_item = Item {
+                                                dot: item.dot + 1,
+                                                args: new_args,
+                                                ..item.clone()
+                                            };
+                                            new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
+                                        }
+                                    }
+                                }
+                            }
+                            Symbol::SymVar(_, next_fid) => {
+                                // Handle variable symbols (e.g., high-order arguments)
+                                let new_item = Item {
+                                    dot: item.dot + 1,
+                                    ..item.clone()
+                                };
+                                new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
+                            }
+                            // Handle all the new symbol types - most don't affect parsing directly
+                            Symbol::SymBind | Symbol::SymSoftBind | Symbol::SymNE | 
+                            Symbol::SymSoftSpace | Symbol::SymCapital | Symbol::SymAllCapital => {
+                                // These symbols generally don't consume input tokens, just advance
+                                let new_item = Item {
+                                    dot: item.dot + 1,
+                                    ..item.clone()
+                                };
+                                new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
+                            }
+                        }
+                    } else {
+                        let tree = build_tree(&cnc.cncfuns[usize::try_from(item.fid).map_err(|_| negative_id(&state.lang, "productions", "a non-negative function id", item.fid))?], &item.args);
+                        let passive_item = Item {
+                            tree: Some(tree),
+                            ..item.clone()
+                        };
+                        new_passive.entry(*cat_id).or_default().push(passive_item);
+                    }
+                }
+            }
+        }
+
+        for (cat_id, prods) in &cnc.productions {
+            for prod in prods {
+                if let Production::Coerce { arg } = prod {
+                    if let Some(passive) = new_passive.get(arg) {
+                        for pitem in passive {
+                            if let Some(tree) = &pitem.tree {
+                                let new_item = Item {
+                                    fid: *cat_id,
+                                    seqid: 0,
+                                    dot: 0,
+                                    args: vec![(*arg, tree.clone())],
+                                    tree: None,
+                                };
+                                new_active.entry(*cat_id).or_insert_with(Vec::new).push(new_item);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        state.active_items = new_active;
+        state.passive_items = new_passive;
+        state.current_pos += 1;
+        Ok(())
+    }
+
+    fn negative_id(lang: &Language, section: &str, expected: &'static str, id: i32) -> PgfError {
+        PgfError::InvalidReference { path: format!("concrete[{}].{section}", lang.0.as_str()), expected, found: i64::from(id) }
+    }
+
+    fn build_tree(cnc_fun: &CncFun, args: &[(i32, Expr)]) -> Expr {
+        let mut tree = Expr::Fun(cnc_fun.name.clone());
+        for (_, arg) in args {
+            tree = Expr::App(Box::new(tree), Box::new(arg.clone()));
+        }
+        tree
+    }
+
+    /// Get the parse output and bracketed string from the parsing state.
+    /// 
+    /// # Panics
+    /// Panics if the language is not found in the PGF concrete syntaxes.
+    #[must_use]
+    pub fn get_parse_output(state: &ParseState, typ: &Type, depth: Option<i32>) -> (ParseOutput, BracketedString) {
+        let max_depth = depth.unwrap_or(i32::MAX);
+        let cnc = state.pgf.concretes.get(&state.lang).expect("Language not found");
+        let cat_id = cnc.cnccats.get(&typ.category).map_or(0, |cat| cat.start);
+
+        let mut trees = vec![];
+        if let Some(items) = state.passive_items.get(&cat_id) {
+            for item in items {
+                if let Some(tree) = &item.tree {
+                    if let Ok(seqid_usize) = usize::try_from(item.seqid) {
+                            if item.dot == cnc.sequences.get(seqid_usize).map_or(0, std::vec::Vec::len) {
+                                trees.push(tree.clone());
+                            }
+                        }
+                    }
+            }
+        }
+
+        let bracketed = if trees.is_empty() {
+            BracketedString::Leaf(String::new())
+        } else {
+            BracketedString::Branch(typ.category.clone(), trees.iter().map(expr_to_bracketed).collect())
+        };
+
+        if trees.is_empty() {
+            (ParseOutput::ParseFail, bracketed)
+        } else {
+            (ParseOutput::ParseOk(trees), bracketed)
+        }
+    }
+
+    fn expr_to_bracketed(expr: &Expr) -> BracketedString {
+        match expr {
+            Expr::Fun(cid) => BracketedString::Leaf(cid::show_cid(cid)),
+            Expr::App(e1, e2) => {
+                let mut children = vec![expr_to_bracketed(e1)];
+                children.push(expr_to_bracketed(e2));
+                BracketedString::Branch(cid::wild_cid(), children)
+            }
+            _ => BracketedString::Leaf(String::new()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BracketedString {
+    Leaf(String),
+    Branch(CId, Vec<BracketedString>),
+}
+
+/// Read a PGF file from the given path.
+///
+/// Compressed files are read as described for [`read_pgf_from`]. Files of any
+/// size are accepted; use [`read_pgf_with`] to bound them.
+///
+/// # Errors
+/// Returns `PgfError::IoError` if the file cannot be read.
+/// Returns other `PgfError` variants if parsing fails.
+pub fn read_pgf(path: &str) -> Result<Pgf, PgfError> {
+    read_pgf_from(File::open(path)?)
+}
+
+/// Read a PGF file from the given path using the given load options.
+///
+/// Returns the grammar together with the problems that were recovered from;
+/// the list is always empty in strict mode.
+///
+/// # Errors
+/// Returns `PgfError::Io` if the file cannot be read.
+/// Returns `PgfError::DeserializeError` if parsing fails, or in strict mode if
+/// any structure is malformed.
+pub fn read_pgf_with(path: &str, options: &LoadOptions) -> Result<(Pgf, Vec<LoadWarning>), PgfError> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    if size > options.limits.max_bytes as u64 {
+        return Err(PgfError::LimitExceeded { offset: 0, limit: Limit::Bytes, value: size, max: options.limits.max_bytes as u64 });
+    }
+    read_pgf_from_with(file, options)
+}
+
+/// Read a PGF grammar from a stream, such as stdin, an archive entry or a
+/// decompressor.
+///
+/// Gzip and zstd compressed input is recognized by its magic number and
+/// decompressed when the `gzip` or `zstd` feature is enabled.
+///
+/// The stream is read to its end whatever its size, as [`read_pgf`] always
+/// did; the other [`Limits`] keep their defaults. Use [`read_pgf_from_with`]
+/// for untrusted input.
+///
+/// # Errors
+/// Returns `PgfError::Io` if the stream cannot be read or decompressed.
+/// Returns `PgfError::DeserializeError` if the data is compressed with a
+/// format whose feature is disabled, or if parsing fails.
+pub fn read_pgf_from<R: Read>(reader: R) -> Result<Pgf, PgfError> {
+    let options = LoadOptions { limits: Limits { max_bytes: usize::MAX, ..Limits::default() }, ..LoadOptions::default() };
+    read_pgf_from_with(reader, &options).map(|(pgf, _)| pgf)
+}
+
+/// Like [`read_pgf_from`], using the given load options.
+///
+/// At most [`Limits::max_bytes`] are read from the stream, and at most as
+/// many bytes are produced when decompressing it.
+///
+/// # Errors
+/// Returns `PgfError::LimitExceeded` if the stream or its decompressed
+/// contents are larger than [`Limits::max_bytes`], and otherwise the errors
+/// of [`read_pgf_from`].
+pub fn read_pgf_from_with<R: Read>(reader: R, options: &LoadOptions) -> Result<(Pgf, Vec<LoadWarning>), PgfError> {
+    let data = read_input(reader, &options.limits)?;
+    parse_pgf_with(&data, options)
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Read a whole stream, decompressing it if it starts with a known magic number.
+fn read_input<R: Read>(reader: R, limits: &Limits) -> Result<Bytes, PgfError> {
+    let data = read_limited(reader, limits.max_bytes)?;
+    if data.starts_with(GZIP_MAGIC) {
+        #[cfg(feature = "gzip")]
+        return read_limited(flate2::read::MultiGzDecoder::new(&data[..]), limits.max_bytes).map(Bytes::from);
+        #[cfg(not(feature = "gzip"))]
+        return Err(PgfError::decode(0, DecodeErrorKind::UnsupportedCompression { format: "gzip", feature: "gzip" }));
+    }
+    if data.starts_with(ZSTD_MAGIC) {
+        #[cfg(feature = "zstd")]
+        return read_limited(zstd::stream::read::Decoder::new(&data[..])?, limits.max_bytes).map(Bytes::from);
+        #[cfg(not(feature = "zstd"))]
+        return Err(PgfError::decode(0, DecodeErrorKind::UnsupportedCompression { format: "zstd", feature: "zstd" }));
+    }
+    Ok(Bytes::from(data))
+}
+
+fn read_limited<R: Read>(reader: R, max: usize) -> Result<Vec<u8>, PgfError> {
+    let mut bytes = Vec::new();
+    reader.take((max as u64).saturating_add(1)).read_to_end(&mut bytes)?;
+    if bytes.len() > max {
+        return Err(PgfError::LimitExceeded { offset: max as u64, limit: Limit::Bytes, value: bytes.len() as u64, max: max as u64 });
+    }
+    Ok(bytes)
+}
+
+/// Read a PGF file by memory-mapping it instead of copying it into memory.
+///
+/// Identifiers of the returned grammar are slices of the mapping,
+/// which stays alive for as long as any of them does.
+///
+/// # Safety
+///
+/// The file must not be modified or truncated, by this or any other process,
+/// for as long as the grammar or any string taken from it is alive. Strings
+/// are checked to be UTF-8 once, while parsing, and are read without further
+/// checks afterwards.
+///
+/// # Errors
+/// Returns `PgfError::Io` if the file cannot be opened or mapped.
+/// Returns other `PgfError` variants if parsing fails.
+#[cfg(feature = "mmap")]
+pub unsafe fn read_pgf_mmap(path: &str) -> Result<Pgf, PgfError> {
+    let file = File::open(path)?;
+    // SAFETY: the caller guarantees the file stays unchanged while it is mapped.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    parse_pgf(&Bytes::from_owner(map))
+}
+
+/// Parses a PGF binary data structure from bytes.
+///
+/// # Errors
+///
+/// Returns [`PgfError::DeserializeError`] if:
+/// - The binary data is malformed or truncated
+/// - Version numbers cannot be read from the data
+/// - Any binary field parsing fails during deserialization
+pub fn parse_pgf(data: &Bytes) -> Result<Pgf, PgfError> {
+    parse_pgf_with(data, &LoadOptions::default()).map(|(pgf, _)| pgf)
+}
+
+/// Parses a PGF binary data structure from bytes using the given load options.
+///
+/// # Errors
+///
+/// Returns [`PgfError::DeserializeError`] if the data cannot be parsed. In
+/// strict mode the error names the structure that failed, e.g.
+/// `concrete[FoodEng].cncfuns`, and the offset at which it failed.
+pub fn parse_pgf_with(data: &Bytes, options: &LoadOptions) -> Result<(Pgf, Vec<LoadWarning>), PgfError> {
+    let mut cursor = Input::new(data, options);
+    cursor.check(Limit::Bytes, 0, data.len())?;
+    let mut state = LoadState::new(options);
+    let mut pgf = parse_pgf_binary(&mut cursor, &mut state)?;
+    pgf.options = LoadOptions { languages: None, ..options.clone() };
+    if !pgf.unloaded.is_empty() {
+        pgf.source = Some((data.len(), fnv1a(data)));
+    }
+    Ok((pgf, state.warnings))
+}
+
+/// Options controlling how a PGF file is loaded.
+#[derive(Clone, Default)]
+pub struct LoadOptions {
+    /// Fail on the first malformed structure instead of recovering from it.
+    pub strict: bool,
+    /// Receives structured events while the grammar is loaded.
+    pub observer: Option<Arc<dyn LoadObserver>>,
+    /// Only decode these concrete syntaxes; `None` loads all of them. The
+    /// others are skipped and can be loaded later with [`Pgf::load_language`].
+    /// Naming a language the file does not have fails with
+    /// [`PgfError::UnknownLanguage`].
+    pub languages: Option<Vec<Language>>,
+    /// Bounds on what the file may make the loader allocate.
+    pub limits: Limits,
+}
+
+/// Resource limits for loading untrusted files. Exceeding any of them fails
+/// with [`PgfError::LimitExceeded`], in lenient mode too.
+///
+/// The defaults accept any grammar the GF compiler produces in practice; lower
+/// them when loading files uploaded by users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Largest accepted input, in bytes.
+    pub max_bytes: usize,
+    /// Most elements in any one list, e.g. sequences or symbols.
+    pub max_list_len: usize,
+    /// Longest identifier or token, in bytes.
+    pub max_string_len: usize,
+    /// Deepest nesting of types, expressions, patterns and pre blocks.
+    pub max_depth: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_bytes: 1 << 30,
+            max_list_len: 1_000_000,
+            max_string_len: 1_000_000,
+            max_depth: 100,
+        }
+    }
+}
+
+/// The data being decoded, together with the limits it is decoded under.
+struct Input<'a> {
+    cursor: Cursor<&'a Bytes>,
+    limits: Limits,
+    /// Whether data that ends where a list would start is an error.
+    strict: bool,
+}
+
+impl<'a> Input<'a> {
+    fn new(data: &'a Bytes, options: &LoadOptions) -> Self {
+        Input { cursor: Cursor::new(data), limits: options.limits, strict: options.strict }
+    }
+
+    fn position(&self) -> u64 {
+        self.cursor.position()
+    }
+
+    fn set_position(&mut self, pos: u64) {
+        self.cursor.set_position(pos);
+    }
+
+    fn get_ref(&self) -> &'a Bytes {
+        self.cursor.get_ref()
+    }
+
+    /// Fail if `value` is over the configured maximum for `limit`.
+    fn check(&self, limit: Limit, offset: u64, value: usize) -> Result<(), PgfError> {
+        let max = match limit {
+            Limit::Bytes => self.limits.max_bytes,
+            Limit::ListLength => self.limits.max_list_len,
+            Limit::StringLength => self.limits.max_string_len,
+            Limit::Depth => usize::try_from(self.limits.max_depth).unwrap_or(usize::MAX),
+        };
+        if value > max {
+            return Err(PgfError::LimitExceeded { offset, limit, value: value as u64, max: max as u64 });
+        }
+        Ok(())
+    }
+
+    /// Read a list length, checked against the list limit.
+    fn list_len(&mut self) -> Result<usize, PgfError> {
+        let offset = self.position();
+        let len = read_int(self)?;
+        let len = usize::try_from(len).map_err(|_| {
+            PgfError::decode(offset, DecodeErrorKind::NegativeLength { what: "list", found: len })
+        })?;
+        self.check(Limit::ListLength, offset, len)?;
+        Ok(len)
+    }
+
+    /// Capacity to reserve for `len` elements: every element takes at least
+    /// one byte, so a list can never be longer than the remaining input.
+    fn capacity(&self, len: usize) -> usize {
+        let remaining = self.get_ref().len().saturating_sub(usize::try_from(self.position()).unwrap_or(usize::MAX));
+        len.min(remaining)
+    }
+}
+
+impl Read for Input<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.cursor.read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.cursor.read_exact(buf)
+    }
+}
+
+impl LoadOptions {
+    /// Options that load only the named concrete syntaxes.
+    #[must_use]
+    pub fn languages(names: &[&str]) -> Self {
+        LoadOptions {
+            languages: Some(names.iter().map(|name| Language(cid::mk_cid(name))).collect()),
+            ..LoadOptions::default()
+        }
+    }
+}
+
+impl std::fmt::Debug for LoadOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoadOptions")
+            .field("strict", &self.strict)
+            .field("observer", &self.observer.as_ref().map(|_| "LoadObserver"))
+            .field("languages", &self.languages)
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+/// A structured diagnostic emitted by the loader.
+///
+/// Section names use the same paths as [`LoadWarning::structure`], e.g.
+/// `abstract.funs` or `concrete[FoodEng].sequences`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadEvent<'a> {
+    /// The version header was read.
+    Version { major: i16, minor: i16 },
+    /// Reading a section started at the given byte offset.
+    SectionStart { section: &'a str, offset: u64 },
+    /// A section was read, ending at the given byte offset. `count` is the
+    /// number of entries for list sections.
+    SectionEnd { section: &'a str, offset: u64, count: Option<usize> },
+    /// The start category was chosen, either from the `startcat` flag or by
+    /// falling back to a well-known or the first category.
+    StartCat { name: &'a CId, from_flag: bool },
+    /// A malformed structure was skipped in lenient mode.
+    Recovered(&'a LoadWarning),
+}
+
+/// A sink for [`LoadEvent`]s, set through [`LoadOptions::observer`].
+pub trait LoadObserver: Send + Sync {
+    fn on_event(&self, event: &LoadEvent<'_>);
+}
+
+/// Forwards loader events to the `log` facade: recovered errors at `warn`,
+/// everything else at `debug`.
+#[cfg(feature = "log")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogObserver;
+
+#[cfg(feature = "log")]
+impl LoadObserver for LogObserver {
+    fn on_event(&self, event: &LoadEvent<'_>) {
+        match event {
+            LoadEvent::Recovered(warning) => log::warn!(target: "pgf2json", "recovered: {warning}"),
+            other => log::debug!(target: "pgf2json", "{other:?}"),
+        }
+    }
+}
+
+/// A malformed structure the lenient loader skipped over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadWarning {
+    /// Byte offset at which reading the structure failed.
+    pub offset: u64,
+    /// The structure being read, e.g. `concrete[FoodEng].cncfuns`.
+    pub structure: String,
+    /// The underlying error message.
+    pub message: String,
+}
+
+impl std::fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at offset {}: {}", self.structure, self.offset, self.message)
+    }
+}
+
+struct LoadState {
+    strict: bool,
+    warnings: Vec<LoadWarning>,
+    observer: Option<Arc<dyn LoadObserver>>,
+    languages: Option<Vec<Language>>,
+    unloaded: HashMap<Language, u64>,
+}
+
+impl LoadState {
+    fn new(options: &LoadOptions) -> Self {
+        LoadState {
+            strict: options.strict,
+            warnings: Vec::new(),
+            observer: options.observer.clone(),
+            languages: options.languages.clone(),
+            unloaded: HashMap::new(),
+        }
+    }
+
+    fn emit(&self, event: &LoadEvent<'_>) {
+        if let Some(observer) = &self.observer {
+            observer.on_event(event);
+        }
+    }
+
+    fn start(&self, section: &str, offset: u64) {
+        self.emit(&LoadEvent::SectionStart { section, offset });
+    }
+
+    fn end(&self, section: &str, offset: u64, count: Option<usize>) {
+        self.emit(&LoadEvent::SectionEnd { section, offset, count });
+    }
+
+    /// Fail with `err` in strict mode, otherwise record it as a warning so the
+    /// caller can continue with a default value.
+    fn recover(&mut self, offset: u64, structure: &str, err: PgfError) -> Result<(), PgfError> {
+        if self.strict || matches!(err, PgfError::LimitExceeded { .. }) {
+            return Err(in_structure(structure, err));
+        }
+        let (offset, structure, message) = match err {
+            PgfError::DeserializeError { offset, path, kind, .. } if !path.is_empty() => (offset, path, kind.to_string()),
+            PgfError::DeserializeError { offset, kind, .. } => (offset, structure.to_string(), kind.to_string()),
+            other => (offset, structure.to_string(), other.to_string()),
+        };
+        let warning = LoadWarning { offset, structure, message };
+        self.emit(&LoadEvent::Recovered(&warning));
+        self.warnings.push(warning);
+        Ok(())
+    }
+}
+
+/// Attach the path of the structure that was being read to a deserialization
+/// error, unless a more specific one was already attached further down.
+fn in_structure(structure: &str, err: PgfError) -> PgfError {
+    match err {
+        PgfError::DeserializeError { offset, path, kind, source } if path.is_empty() => {
+            PgfError::DeserializeError { offset, path: structure.to_string(), kind, source }
+        }
+        other => other,
+    }
+}
+
+fn parse_pgf_binary(cursor: &mut Input<'_>, state: &mut LoadState) -> Result<Pgf, PgfError> {
+    let offset = cursor.position();
+    let file_size = cursor.get_ref().len();
+    let major_version = cursor.read_i16::<BigEndian>()
+        .map_err(|e| PgfError::eof(offset, "major version", e))?;
+    let minor_version = cursor.read_i16::<BigEndian>()
+        .map_err(|e| PgfError::eof(offset, "minor version", e))?;
+
+    if !(1..=2).contains(&major_version) {
+        return Err(PgfError::decode(offset, DecodeErrorKind::UnsupportedVersion { major: major_version, minor: minor_version }));
+    }
+
+    // Version 2.x (as written by current GF compilers) changed the layout of
+    // strings, categories, symbols and the concrete header; 1.x is the layout
+    // described in theory/pgf_spec.pdf.
+    let is_pgf_2_1 = major_version >= 2;
+    state.emit(&LoadEvent::Version { major: major_version, minor: minor_version });
+
+    // Pass is_pgf_2_1 to functions that call read_string
+    state.start("flags", cursor.position());
+    let flags = read_flags(cursor, is_pgf_2_1)?;
+    state.end("flags", cursor.position(), Some(flags.len()));
+    state.start("abstract", cursor.position());
+    let (absname, r#abstract) = read_abstract(cursor, is_pgf_2_1, state)?;
+    state.end("abstract", cursor.position(), None);
+    let concretes = read_concretes(cursor, is_pgf_2_1, state)?;
+
+    // The compiler stores startcat among the abstract flags; older files may
+    // still carry it globally.
+    let startcat = r#abstract.aflags.get(&cid::mk_cid("startcat"))
+        .or_else(|| flags.get(&cid::mk_cid("startcat")))
+        .and_then(|lit| match lit {
+            Literal::Str(s) => Some(cid::mk_cid(s)),
+            _ => None,
+        });
+    let from_flag = startcat.is_some();
+    let startcat = startcat.unwrap_or_else(|| {
+            // Look for common startcat names first, then fall back to alphabetical order
+            let common_startcats = ["Phrase", "Utt", "S", "Sentence"];
+            for candidate in &common_startcats {
+                let candidate_cid = cid::mk_cid(candidate);
+                if r#abstract.cats.contains_key(&candidate_cid) {
+                    return candidate_cid;
+                }
+            }
+            // If no common startcat found, use first alphabetically
+            r#abstract.cats.keys().min_by(|a, b| a.0.cmp(&b.0)).cloned().unwrap_or(cid::mk_cid("S"))
+        });
+    state.emit(&LoadEvent::StartCat { name: &startcat, from_flag });
+
+    Ok(Pgf {
+        absname,
+        concretes,
+        r#abstract,
+        startcat,
+        flags,
+        unloaded: std::mem::take(&mut state.unloaded),
+        options: LoadOptions::default(),
+        source: None,
+    })
+}
+/* fn parse_pgf_binary(cursor: &mut Cursor<&[u8]>) -> Result<Pgf, PgfError> {
+    let offset = cursor.position();
+    let major_version = cursor.read_i16::<BigEndian>()
+        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read major version: {e}") })?;
+    let minor_version = cursor.read_i16::<BigEndian>()
+        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read minor version: {e}") })?;
+
+    if major_version < 1 || major_version > 2 {
+        return Err(PgfError::DeserializeError {
+            offset,
+            message: format!("Unsupported PGF version: {major_version}.{minor_version}"),
+        });
+    }
+
+    let flags = read_flags(cursor)?;
+    let r#abstract = read_abstract(cursor)?;
+    let concretes = read_concretes(cursor)?;
+
+    let startcat = flags.get(&cid::mk_cid("startcat"))
+        .and_then(|lit| match lit {
+            Literal::Str(s) => Some(cid::mk_cid(s)),
+            _ => None,
+        })
+        .unwrap_or_else(|| r#abstract.cats.keys().next().cloned().unwrap_or(cid::mk_cid("S")));
+
+    let absname = r#abstract.funs.keys().next().map_or(cid::mk_cid("Abstract"), |f| f.clone());
+
+    Ok(Pgf {
+        absname,
+        concretes,
+        r#abstract,
+        startcat,
+        flags,
+    })
+} */
+
+fn read_flags(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<HashMap<CId, Literal>, PgfError> {
+    let count = cursor.list_len()?;
+    let mut flags = HashMap::new();
+    for _ in 0..count {
+        let key = read_string(cursor, is_pgf_2_1)?;
+        let value = read_literal(cursor, is_pgf_2_1)?;
+        flags.insert(key, value);
+    }
+    Ok(flags)
+}
+
+fn read_int(cursor: &mut Input<'_>) -> Result<i32, PgfError> {
+    let offset = cursor.position();
+    let mut result: u32 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = cursor.read_u8().map_err(|e| PgfError::eof(offset, "integer", e))?;
+        let val = u32::from(byte & 0x7F);
+        result |= val << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if shift >= 32 {
+            return Err(PgfError::decode(offset, DecodeErrorKind::IntegerOverflow));
+        }
+    }
+    
+    // Convert unsigned to signed integer using proper two's complement decoding (matching C implementation)
+    decode_2c32(result, offset)
+}
+
+// Two's complement decoder matching the C implementation (GU_DECODE_2C_)
+#[allow(clippy::cast_possible_wrap)]
+fn decode_2c32(u: u32, offset: u64) -> Result<i32, PgfError> {
+    const UINT32_MAX: u32 = 0xffff_ffff;
+    const POSMAX: u32 = 0x7fff_ffff; // INT32_MAX as u32
+    const TMIN: i32 = i32::MIN;
+    
+    
+    if u <= POSMAX {
+        // Positive numbers: direct conversion
+        let result = u as i32;
+        Ok(result)
+    } else {
+        // Negative numbers: two's complement decoding
+        let temp = TMIN.wrapping_add((UINT32_MAX - u) as i32);
+        if temp < 0 {
+            let result = -1 - ((UINT32_MAX - u) as i32);
+            Ok(result)
+        } else {
+            // This should trigger an error in C implementation
+            Err(PgfError::decode(offset, DecodeErrorKind::IntegerOverflow))
+        }
+    }
+}
+
+fn read_literal(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<Literal, PgfError> {
+    let offset = cursor.position();
+    let tag = cursor.read_u8()
+        .map_err(|e| PgfError::eof(offset, "literal tag", e))?;
+    match tag {
+        0 => Ok(Literal::Str(read_text(cursor)?.into())),
+        1 => Ok(Literal::Int(read_int(cursor)?)),
+        2 => Ok(Literal::Flt(cursor.read_f64::<BigEndian>()
+            .map_err(|e| PgfError::eof(offset, "float", e))?)),
+        _ => Err(PgfError::decode(offset, DecodeErrorKind::UnknownTag { what: "literal", expected: "0..=2", found: tag })),
+    }
+}
+
+fn read_string(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<CId, PgfError> {
+    // Version 1.0 stores identifiers like any other `String`.
+    if !is_pgf_2_1 {
+        return Ok(CId(read_text(cursor)?));
+    }
+
+    let offset = cursor.position();
+    let len_raw = read_int(cursor)?;
+    let len = usize::try_from(len_raw).map_err(|_| {
+        PgfError::decode(offset, DecodeErrorKind::NegativeLength { what: "identifier", found: len_raw })
+    })?;
+    let result = read_string_with_length(cursor, len)?;
+    Ok(CId(result))
+}
+
+// Since version 2.0 identifiers are byte strings: the length prefix counts
+// bytes. They are UTF-8 in practice, but the C runtime reads them as Latin-1,
+// so anything that is not valid UTF-8 is decoded that way.
+fn read_string_with_length(cursor: &mut Input<'_>, len: usize) -> Result<BytesStr, PgfError> {
+    let start_pos = cursor.position();
+
+    cursor.check(Limit::StringLength, start_pos, len)?;
+
+    let buf = slice_bytes(cursor, len)
+        .ok_or_else(|| PgfError::decode(start_pos, DecodeErrorKind::UnexpectedEof { what: "identifier" }))?;
+
+    Ok(match BytesStr::from_utf8(buf) {
+        Ok(s) => s,
+        Err((bytes, _)) => bytes.iter().map(|&b| char::from(b)).collect::<String>().into(),
+    })
+}
+
+// Takes the next `len` bytes as a slice sharing the cursor's buffer.
+fn slice_bytes(cursor: &mut Input<'_>, len: usize) -> Option<Bytes> {
+    let start = usize::try_from(cursor.position()).ok()?;
+    let end = start.checked_add(len)?;
+    if end > cursor.get_ref().len() {
+        return None;
+    }
+    cursor.set_position(end as u64);
+    Some(cursor.get_ref().slice(start..end))
+}
+
+// Tokens, labels, print names and string literals are `String`s in the spec:
+// their length prefix counts Unicode characters, not bytes.
+fn read_text(cursor: &mut Input<'_>) -> Result<BytesStr, PgfError> {
+    let offset = cursor.position();
+    let n_chars = read_int(cursor)?;
+    if n_chars < 0 {
+        return Err(PgfError::decode(offset, DecodeErrorKind::NegativeLength { what: "string", found: n_chars }));
+    }
+    cursor.check(Limit::StringLength, offset, usize::try_from(n_chars).unwrap_or(usize::MAX))?;
+
+    let data = cursor.get_ref();
+    let start = usize::try_from(cursor.position()).unwrap_or(usize::MAX);
+    let mut end = start;
+    for _ in 0..n_chars {
+        let lead = *data.get(end)
+            .ok_or_else(|| PgfError::decode(offset, DecodeErrorKind::UnexpectedEof { what: "string" }))?;
+        end += match lead {
+            0xF0.. => 4,
+            0xE0.. => 3,
+            0xC0.. => 2,
+            _ => 1,
+        };
+    }
+    cursor.check(Limit::StringLength, offset, end - start)?;
+    let buf = slice_bytes(cursor, end - start)
+        .ok_or_else(|| PgfError::decode(offset, DecodeErrorKind::UnexpectedEof { what: "string" }))?;
+    BytesStr::from_utf8(buf).map_err(|(_, e)| PgfError::DeserializeError {
+        offset,
+        path: String::new(),
+        kind: DecodeErrorKind::InvalidUtf8 { what: "string" },
+        source: Some(Box::new(e)),
+    })
+}
+
+fn read_abstract(cursor: &mut Input<'_>, is_pgf_2_1: bool, state: &LoadState) -> Result<(CId, Abstract), PgfError> {
+    let offset = cursor.position();
+    let name = read_string(cursor, is_pgf_2_1)?;
+    let aflags = read_flags(cursor, is_pgf_2_1)?;
+    state.start("abstract.funs", cursor.position());
+    let fun_count = cursor.list_len()?;
+    let mut funs = HashMap::new();
+    let mut cats = HashMap::new();
+
+    for _ in 0..fun_count {
+        let fun_name = read_string(cursor, is_pgf_2_1)?;
+        let ty = read_type(cursor, 0, is_pgf_2_1)?;
+        let arity = read_int(cursor)?;
+        let tag = cursor.read_u8()
+            .map_err(|e| PgfError::eof(offset, "function tag", e))?;
+        let is_constructor = tag == 0;
+        let equations = if tag == 1 {
+            Some(read_list(cursor, |c| read_equation(c, is_pgf_2_1))?)
+        } else {
+            None
+        };
+        let prob = cursor.read_f64::<BigEndian>()
+            .map_err(|e| PgfError::eof(offset, "probability", e))?;
+
+        funs.insert(fun_name.clone(), Function {
+            ty: ty.clone(),
+            weight: 1, // Default weight
+            equations,
+            arity,
+            is_constructor,
+            prob,
+        });
+
+        cats.entry(ty.category.clone())
+            .or_insert_with(|| Category { hypos: vec![], funs: vec![], prob: 0.0 })
+            .funs.push((prob, fun_name));
+    }
+
+    state.end("abstract.funs", cursor.position(), Some(funs.len()));
+
+    state.start("abstract.cats", cursor.position());
+    let cat_count = cursor.list_len()?;
+    for _ in 0..cat_count {
+        let cat_name = read_string(cursor, is_pgf_2_1)?;
+        let hypos = read_list(cursor, |c| read_hypo(c, 0, is_pgf_2_1))?;
+        // Version 1.0 stores (name, prob) pairs and no category probability.
+        let cat_funs = read_list(cursor, |cursor| {
+            if is_pgf_2_1 {
+                let prob = cursor.read_f64::<BigEndian>()?;  // Read prob first (negated log)
+                let name = read_string(cursor, is_pgf_2_1)?;
+                Ok((prob, name))
+            } else {
+                let name = read_string(cursor, is_pgf_2_1)?;
+                let prob = cursor.read_f64::<BigEndian>()?;
+                Ok((prob, name))
+            }
+        })?;
+        let prob = if is_pgf_2_1 { cursor.read_f64::<BigEndian>()? } else { 0.0 };
+
+        cats.insert(cat_name, Category { hypos, funs: cat_funs, prob });
+    }
+    state.end("abstract.cats", cursor.position(), Some(cat_count));
+
+    Ok((name, Abstract { aflags, funs, cats }))
+}
+
+fn read_type(cursor: &mut Input<'_>, depth: u32, is_pgf_2_1: bool) -> Result<Type, PgfError> {
+    let offset = cursor.position();
+    cursor.check(Limit::Depth, offset, depth as usize)?;
+    let hypos = read_list(cursor, |c| read_hypo(c, depth + 1, is_pgf_2_1))?;
+    let category = read_string(cursor, is_pgf_2_1)?;
+    let exprs = read_list(cursor, |c| read_expr(c, depth + 1, is_pgf_2_1))?;
+    Ok(Type { hypos, category, exprs })
+}
+
+fn read_hypo(cursor: &mut Input<'_>, depth: u32, is_pgf_2_1: bool) -> Result<Hypo, PgfError> {
+    let offset = cursor.position();
+    let binding = read_binding(cursor, is_pgf_2_1)?;
+    let ty = read_type(cursor, depth, is_pgf_2_1)?;
+    Ok(Hypo { binding, ty })
+}
+
+fn read_binding(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<Binding, PgfError> {
+    let offset = cursor.position();
+    let tag = cursor.read_u8()
+        .map_err(|e| PgfError::eof(offset, "binding tag", e))?;
+    let name = read_string(cursor, is_pgf_2_1)?;
+    match tag {
+        0 => Ok(Binding::Explicit(cid::show_cid(&name))),
+        1 => Ok(Binding::Implicit(cid::show_cid(&name))),
+        _ => Err(PgfError::decode(offset, DecodeErrorKind::UnknownTag { what: "binding", expected: "0..=1", found: tag })),
+    }
+}
+
+fn read_expr(cursor: &mut Input<'_>, depth: u32, is_pgf_2_1: bool) -> Result<Expr, PgfError> {
+    let offset = cursor.position();
+    cursor.check(Limit::Depth, offset, depth as usize)?;
+    let tag = cursor.read_u8()
+        .map_err(|e| PgfError::eof(offset, "expr tag", e))?;
+    match tag {
+        0 => {
+            // The bind type tag is followed by the bound variable itself.
+            let binding = read_binding(cursor, is_pgf_2_1)?;
+            let var = match &binding {
+                Binding::Explicit(name) | Binding::Implicit(name) => cid::mk_cid(name),
+            };
+            let body = read_expr(cursor, depth + 1, is_pgf_2_1)?;
+            Ok(Expr::Abs(binding, var, Box::new(body)))
+        }
+        1 => {
+            let lhs = read_expr(cursor, depth + 1, is_pgf_2_1)?;
+            let rhs = read_expr(cursor, depth + 1, is_pgf_2_1)?;
+            Ok(Expr::App(Box::new(lhs), Box::new(rhs)))
+        }
+        2 => Ok(Expr::Lit(read_literal(cursor, is_pgf_2_1)?)),
+        3 => Ok(Expr::Meta(read_int(cursor)?)),
+        4 => Ok(Expr::Fun(read_string(cursor, is_pgf_2_1)?)),
+        5 => Ok(Expr::Var(read_int(cursor)?)),
+        6 => {
+            let expr = read_expr(cursor, depth + 1, is_pgf_2_1)?;
+            let ty = read_type(cursor, depth + 1, is_pgf_2_1)?;
+            Ok(Expr::Typed(Box::new(expr), ty))
+        }
+        7 => {
+            let expr = read_expr(cursor, depth + 1, is_pgf_2_1)?;
+            Ok(Expr::ImplArg(Box::new(expr)))
+        }
+        _ => Err(PgfError::decode(offset, DecodeErrorKind::UnknownTag { what: "expr", expected: "0..=7", found: tag })),
+    }
+}
+
+fn read_equation(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<Equation, PgfError> {
+    let patterns = read_list(cursor, |c| read_pattern(c, 0, is_pgf_2_1))?;
+    let result = read_expr(cursor, 0, is_pgf_2_1)?;
+    Ok(Equation { patterns, result })
+}
+
+fn read_pattern(cursor: &mut Input<'_>, depth: u32, is_pgf_2_1: bool) -> Result<Pattern, PgfError> {
+    let offset = cursor.position();
+    cursor.check(Limit::Depth, offset, depth as usize)?;
+    let tag = cursor.read_u8()
+        .map_err(|e| PgfError::eof(offset, "pattern tag", e))?;
+    match tag {
+        0 => {
+            let constr = read_string(cursor, is_pgf_2_1)?;
+            let patterns = read_list(cursor, |c| read_pattern(c, depth + 1, is_pgf_2_1))?;
+            Ok(Pattern::PApp(constr, patterns))
+        }
+        1 => Ok(Pattern::PVar(read_string(cursor, is_pgf_2_1)?)),
+        2 => {
+            let var = read_string(cursor, is_pgf_2_1)?;
+            let pattern = read_pattern(cursor, depth + 1, is_pgf_2_1)?;
+            Ok(Pattern::PBind(var, Box::new(pattern)))
+        }
+        3 => Ok(Pattern::PWildcard),
+        4 => Ok(Pattern::PLit(read_literal(cursor, is_pgf_2_1)?)),
+        5 => Ok(Pattern::PImplicit(read_list(cursor, |c| read_pattern(c, depth + 1, is_pgf_2_1))?)),
+        6 => Ok(Pattern::PInaccessible(read_expr(cursor, depth + 1, is_pgf_2_1)?)),
+        _ => Err(PgfError::decode(offset, DecodeErrorKind::UnknownTag { what: "pattern", expected: "0..=6", found: tag })),
+    }
+}
+
+fn read_concretes(cursor: &mut Input<'_>, is_pgf_2_1: bool, state: &mut LoadState) -> Result<HashMap<Language, Concrete>, PgfError> {
+    state.start("concretes", cursor.position());
+    
+    // Manual parsing approach to handle partial success
+    let mut concretes = HashMap::new();
+    
+    // Read the count of concretes
+    let offset = cursor.position();
+    let count = match cursor.list_len() {
+        Ok(c) => c,
+        Err(e) => {
+            state.recover(offset, "concretes", e)?;
+            return Ok(HashMap::new());
+        }
+    };
+    
+    for i in 0..count {
+        // Read language name
+        let offset = cursor.position();
+        let lang_name = match read_string(cursor, is_pgf_2_1) {
+            Ok(name) => {
+                // If we get an empty language name, this might indicate end of valid data
+                if name.0.is_empty() {
+                    let err = PgfError::decode(offset, DecodeErrorKind::InvalidValue {
+                        what: "language name",
+                        expected: "a non-empty identifier",
+                        found: String::from("\"\""),
+                    });
+                    state.recover(offset, &format!("concretes[{i}]"), err)?;
+                    break;
+                }
+                name
+            },
+            Err(e) => {
+                state.recover(offset, &format!("concretes[{i}]"), e)?;
+                break; // Stop processing, but return what we have
+            }
+        };
+        
+        // Try to read the concrete syntax with error handling
+        let section = format!("concrete[{}]", lang_name.0);
+        let offset = cursor.position();
+        let lang = Language(lang_name.clone());
+        if state.languages.as_ref().is_some_and(|wanted| !wanted.contains(&lang)) {
+            if let Err(e) = skip_concrete(cursor, is_pgf_2_1) {
+                state.recover(offset, &section, e)?;
+                break;
+            }
+            state.unloaded.insert(lang, offset);
+            continue;
+        }
+        state.start(&section, offset);
+        match read_concrete(cursor, is_pgf_2_1, &lang_name, state) {
+            Ok(concrete) => {
+                state.end(&section, cursor.position(), None);
+                concretes.insert(Language(lang_name), concrete);
+            }
+            Err(e) => {
+                // Continue processing or break depending on error type
+                let recoverable = matches!(e.decode_kind(), Some(
+                    DecodeErrorKind::UnexpectedEof { .. }
+                    | DecodeErrorKind::UnknownTag { what: "literal", .. }
+                    | DecodeErrorKind::NegativeLength { what: "list", .. }
+                ));
+                if !state.strict && recoverable {
+                    state.recover(offset, &section, e)?;
+                    break;
+                }
+                return Err(e);
+            }
+        }
+    }
+    
+    // A requested name that is not in the file is a mistake in the options,
+    // unless recovery stopped before the whole list was read.
+    let read_all = concretes.len() + state.unloaded.len() == count;
+    if let Some(missing) = state.languages.iter().flatten().find(|lang| read_all && !concretes.contains_key(*lang)) {
+        return Err(PgfError::UnknownLanguage(cid::show_cid(&missing.0)));
+    }
+    state.end("concretes", cursor.position(), Some(concretes.len()));
+    Ok(concretes)
+}
+
+#[allow(clippy::too_many_lines, clippy::similar_names)]
+fn read_concrete(cursor: &mut Input<'_>, is_pgf_2_1: bool, name: &CId, state: &mut LoadState) -> Result<Concrete, PgfError> {
+    let path = |section: &str| format!("concrete[{}].{section}", name.0);
+    
+    let offset = cursor.position();
+    let cflags = read_flags(cursor, is_pgf_2_1).map_err(|e| in_structure(&path("flags"), e))?;
+    let offset = cursor.position();
+    let printnames = read_list(cursor, |c| read_printname(c, is_pgf_2_1))
+        .map_err(|e| in_structure(&path("printnames"), e))?;
+    
+    // Read sequences normally without hardcoded positions
+    let offset = cursor.position();
+    state.start(&path("sequences"), offset);
+    let sequences_len = match read_int(cursor) {
+        Ok(len) => usize::try_from(len).map_err(|_| {
+            in_structure(&path("sequences"), PgfError::decode(offset, DecodeErrorKind::NegativeLength { what: "list", found: len }))
+        })?,
+        Err(e @ PgfError::DeserializeError { .. }) if is_eof_error(&e) => {
+            state.recover(offset, &path("sequences"), e)?;
+            0
+        }
+        Err(e) => return Err(in_structure(&path("sequences"), e)),
+    };
+    cursor.check(Limit::ListLength, offset, sequences_len)?;
+    
+    let mut sequences = Vec::with_capacity(cursor.capacity(sequences_len));
+    for i in 0..sequences_len {
+        let seq_pos = cursor.position();
+        let syms_len = match read_int(cursor) {
+            Ok(len) => usize::try_from(len).map_err(|_| {
+                let err = PgfError::decode(seq_pos, DecodeErrorKind::NegativeLength { what: "list", found: len });
+                in_structure(&path(&format!("sequences[{i}]")), err)
+            })?,
+            Err(e @ PgfError::DeserializeError { .. }) if is_eof_error(&e) => {
+                state.recover(seq_pos, &path(&format!("sequences[{i}]")), e)?;
+                break;
+            }
+            Err(e) => return Err(in_structure(&path(&format!("sequences[{i}]")), e)),
+        };
+        cursor.check(Limit::ListLength, seq_pos, syms_len)?;
+
+        let mut symbols = Vec::with_capacity(cursor.capacity(syms_len));
+        
+        for j in 0..syms_len {
+            let sym_pos = cursor.position();
+            match read_sequence_symbols(cursor, is_pgf_2_1) {
+                Ok(symbol) => symbols.extend(symbol),
+                Err(e @ PgfError::DeserializeError { .. }) if is_eof_error(&e) => {
+                    state.recover(sym_pos, &path(&format!("sequences[{i}].symbol[{j}]")), e)?;
+                    break;
+                }
+                Err(e) => return Err(in_structure(&path(&format!("sequences[{i}].symbol[{j}]")), e)),
+            }
+        }
+        sequences.push(symbols);
+    }
+    state.end(&path("sequences"), cursor.position(), Some(sequences.len()));
+    
+    // Read concrete functions normally
+    let offset = cursor.position();
+    state.start(&path("cncfuns"), offset);
+    let cncfuns = match read_list(cursor, |c| read_cncfun(c, is_pgf_2_1)) {
+        Ok(funs) => funs,
+        Err(e) => {
+            state.recover(offset, &path("cncfuns"), e)?;
+            Vec::new() // Use empty list instead of failing
+        }
+    };
+    state.end(&path("cncfuns"), cursor.position(), Some(cncfuns.len()));
+    
+    // Initialize empty CCat map (like C code line 1186-1187)
+    let mut ccat_map: std::collections::HashMap<i32, CCat> = std::collections::HashMap::new();
+    
+    // Read lindefs (following C code sequence)
+    let offset = cursor.position();
+    state.start(&path("lindefs"), offset);
+    let lindefs = match read_lindefs(cursor, &mut ccat_map) {
+        Ok(ld) => ld,
+        Err(e) => {
+            state.recover(offset, &path("lindefs"), e)?;
+            Vec::new()
+        }
+    };
+    state.end(&path("lindefs"), cursor.position(), Some(lindefs.len()));
+    
+    // Read linrefs (following C code sequence); version 1.0 has no linrefs section
+    let offset = cursor.position();
+    state.start(&path("linrefs"), offset);
+    let lin_refs = if is_pgf_2_1 {
+        match read_linrefs(cursor, &mut ccat_map) {
+            Ok(lr) => lr,
+            Err(e) => {
+                state.recover(offset, &path("linrefs"), e)?;
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+    state.end(&path("linrefs"), cursor.position(), Some(lin_refs.len()));
+    
+    // Read CCats productions (following C code sequence)
+    let offset = cursor.position();
+    state.start(&path("productions"), offset);
+    let ccats = match read_ccats_productions(cursor, &mut ccat_map, is_pgf_2_1) {
+        Ok(()) => {
+            let mut ccats: Vec<CCat> = ccat_map.values().cloned().collect();
+            ccats.sort_by_key(|ccat| ccat.id);
+            ccats
+        }
+        Err(e) => {
+            state.recover(offset, &path("productions"), e)?;
+            Vec::new()
+        }
+    };
+    state.end(&path("productions"), cursor.position(), Some(ccats.len()));
+    
+    // Read categories using standard list parsing (following C implementation)
+    let offset = cursor.position();
+    state.start(&path("cnccats"), offset);
+    let cnccats: HashMap<CId, CncCat> = match read_list(cursor, |c| read_cnccat(c, is_pgf_2_1)) {
+        Ok(category_names) => category_names.into_iter().map(|c| (c.name.clone(), c)).collect(),
+        Err(e) => {
+            state.recover(offset, &path("cnccats"), e)?;
+            HashMap::new()
+        }
+    };
+    state.end(&path("cnccats"), cursor.position(), Some(cnccats.len()));
+    
+    let offset = cursor.position();
+    let total_cats = match read_int(cursor) {
+        Ok(t) => t,
+        Err(e @ PgfError::DeserializeError { .. }) if is_eof_error(&e) => {
+            state.recover(offset, &path("total_cats"), e)?;
+            0
+        }
+        Err(e) => return Err(in_structure(&path("total_cats"), e)),
+    };
+    let productions = ccats.iter().map(|ccat| (ccat.id, ccat.productions.clone())).collect();
+
+    Ok(Concrete {
+        cflags,
+        productions,
+        cncfuns,
+        sequences,
+        cnccats,
+        printnames,
+        lindefs,
+        linrefs: lin_refs,
+        ccats,
+        total_cats,
     })
 }
-/* fn parse_pgf_binary(cursor: &mut Cursor<&[u8]>) -> Result<Pgf, PgfError> {
-    let offset = cursor.position();
-    let major_version = cursor.read_i16::<BigEndian>()
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read major version: {e}") })?;
-    let minor_version = cursor.read_i16::<BigEndian>()
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read minor version: {e}") })?;
 
-    if major_version < 1 || major_version > 2 {
-        return Err(PgfError::DeserializeError {
-            offset,
-            message: format!("Unsupported PGF version: {major_version}.{minor_version}"),
-        });
+/// Advance past a concrete syntax without keeping any of it. The format has no
+/// length prefixes, so every structure still has to be walked.
+fn skip_concrete(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<(), PgfError> {
+    let skip_ints = |c: &mut Input<'_>| skip_list(c, |c| read_int(c).map(drop));
+    let skip_defs = |c: &mut Input<'_>| skip_list(c, |c| {
+        read_int(c)?;
+        skip_ints(c)
+    });
+
+    skip_list(cursor, |c| {
+        read_string(c, is_pgf_2_1)?;
+        read_literal(c, is_pgf_2_1).map(drop)
+    })?;
+    skip_list(cursor, |c| {
+        read_string(c, is_pgf_2_1)?;
+        read_text(c).map(drop)
+    })?;
+    skip_list(cursor, |c| skip_list(c, |c| read_sequence_symbols(c, is_pgf_2_1).map(drop)))?;
+    skip_list(cursor, |c| {
+        read_string(c, is_pgf_2_1)?;
+        skip_ints(c)
+    })?;
+    skip_defs(cursor)?;
+    if is_pgf_2_1 {
+        skip_defs(cursor)?;
     }
+    skip_list(cursor, |c| {
+        read_int(c)?;
+        skip_list(c, |c| read_production(c, is_pgf_2_1).map(drop))
+    })?;
+    skip_list(cursor, |c| read_cnccat(c, is_pgf_2_1).map(drop))?;
+    read_int(cursor).map(drop)
+}
 
-    let flags = read_flags(cursor)?;
-    let r#abstract = read_abstract(cursor)?;
-    let concretes = read_concretes(cursor)?;
+fn skip_list<F>(cursor: &mut Input<'_>, f: F) -> Result<(), PgfError>
+where
+    F: Fn(&mut Input<'_>) -> Result<(), PgfError>,
+{
+    for _ in 0..cursor.list_len()? {
+        f(cursor)?;
+    }
+    Ok(())
+}
 
-    let startcat = flags.get(&cid::mk_cid("startcat"))
-        .and_then(|lit| match lit {
-            Literal::Str(s) => Some(cid::mk_cid(s)),
-            _ => None,
-        })
-        .unwrap_or_else(|| r#abstract.cats.keys().next().cloned().unwrap_or(cid::mk_cid("S")));
+/// Whether `err` means the data ended before the structure being read.
+fn is_eof_error(err: &PgfError) -> bool {
+    matches!(err.decode_kind(), Some(DecodeErrorKind::UnexpectedEof { .. }))
+}
 
-    let absname = r#abstract.funs.keys().next().map_or(cid::mk_cid("Abstract"), |f| f.clone());
+fn read_printname(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<PrintName, PgfError> {
+    let name = read_string(cursor, is_pgf_2_1)?;
+    let printname = read_text(cursor)?;
+    Ok(PrintName { name, printname: printname.into() })
+}
 
-    Ok(Pgf {
-        absname,
-        concretes,
-        r#abstract,
-        startcat,
-        flags,
-    })
-} */
+fn read_lindef(cursor: &mut Input<'_>) -> Result<LinDef, PgfError> {
+    let cat = read_int(cursor)?;
+    let funs = read_list(cursor, read_int)?;
+    Ok(LinDef { cat, funs })
+}
 
-fn read_flags(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<HashMap<CId, Literal>, PgfError> {
-    let offset = cursor.position();
-    // FIXME: count (below) may need fixed-length for PGF 2.1.
-    let count = read_int(cursor)?;
-    let mut flags = HashMap::new();
-    for _ in 0..count {
-        let key = read_string(cursor, is_pgf_2_1)?;
-        let value = read_literal(cursor, is_pgf_2_1)?;
-        flags.insert(key, value);
-    }
-    Ok(flags)
+fn read_linref(cursor: &mut Input<'_>) -> Result<LinRef, PgfError> {
+    let cat = read_int(cursor)?;
+    let funs = read_list(cursor, read_int)?;
+    Ok(LinRef { cat, funs })
 }
 
-fn read_int(cursor: &mut Cursor<&[u8]>) -> Result<i32, PgfError> {
+fn read_production(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<Production, PgfError> {
     let offset = cursor.position();
-    let file_size = cursor.get_ref().len();
-    let mut result: u32 = 0;
-    let mut shift = 0;
-    let mut bytes_read = Vec::new();
-    loop {
-        let byte = cursor.read_u8()
-            .map_err(|e| PgfError::DeserializeError { 
-                offset, 
-                message: format!("Failed to read int byte at pos {offset} (file size: {file_size} bytes): {e}. File appears to be truncated.") 
-            })?;
-        bytes_read.push(byte);
-        let val = u32::from(byte & 0x7F);
-        result |= val << shift;
-        shift += 7;
-        if byte & 0x80 == 0 {
-            break;
+    let tag = cursor.read_u8()
+        .map_err(|e| PgfError::eof(offset, "production tag", e))?;
+    match tag {
+        0 => {
+            let fid = read_int(cursor)?;
+            let args = read_list(cursor, read_parg)?;
+            Ok(Production::Apply { fid, args })
         }
-        if shift >= 32 {
-            return Err(PgfError::DeserializeError {
-                offset,
-                message: format!("Integer overflow reading at pos {offset}, bytes: {bytes_read:?}")
-            });
+        1 => {
+            let arg = read_int(cursor)?;
+            Ok(Production::Coerce { arg })
         }
+        2 if is_pgf_2_1 => {
+            // PConst: CId Expr [Token]
+            let cid = read_string(cursor, is_pgf_2_1)?;
+            let expr = read_expr(cursor, 0, is_pgf_2_1)?;
+            let tokens = read_list(cursor, read_text)?.into_iter().map(String::from).collect();
+            Ok(Production::Const { cid, expr, tokens })
+        }
+        _ => Err(PgfError::decode(offset, DecodeErrorKind::UnknownTag { what: "production", expected: if is_pgf_2_1 { "0..=2" } else { "0..=1" }, found: tag })),
     }
-    
-    // Convert unsigned to signed integer using proper two's complement decoding (matching C implementation)
-    decode_2c32(result, offset)
 }
 
-// Two's complement decoder matching the C implementation (GU_DECODE_2C_)
-fn decode_2c32(u: u32, offset: u64) -> Result<i32, PgfError> {
-    const UINT32_MAX: u32 = 0xffffffff;
-    const POSMAX: u32 = 0x7fffffff; // INT32_MAX as u32
-    const TMIN: i32 = i32::MIN;
-    
-    debug_println!("DEBUG: decode_2c32: u={} (0x{:x}) at offset {}", u, u, offset);
-    
-    if u <= POSMAX {
-        // Positive numbers: direct conversion
-        let result = u as i32;
-        debug_println!("DEBUG: decode_2c32: positive -> {}", result);
-        Ok(result)
-    } else {
-        // Negative numbers: two's complement decoding
-        let temp = TMIN.wrapping_add((UINT32_MAX - u) as i32);
-        if temp < 0 {
-            let result = -1 - ((UINT32_MAX - u) as i32);
-            debug_println!("DEBUG: decode_2c32: negative -> {}", result);
-            Ok(result)
-        } else {
-            // This should trigger an error in C implementation
-            debug_println!("DEBUG: decode_2c32: out of range error");
-            Err(PgfError::DeserializeError {
-                offset,
-                message: format!("Integer decode error: value {u} out of range")
-            })
-        }
-    }
+fn read_parg(cursor: &mut Input<'_>) -> Result<PArg, PgfError> {
+    let hypos = read_list(cursor, read_int)?;
+    let fid = read_int(cursor)?;
+    Ok(PArg { hypos, fid })
 }
 
-fn read_literal(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<Literal, PgfError> {
-    let offset = cursor.position();
-    let tag = cursor.read_u8()
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read literal tag: {e}") })?;
-    match tag {
-        0 => Ok(Literal::Str(read_string(cursor, is_pgf_2_1)?.0)),
-        1 => Ok(Literal::Int(read_int(cursor)?)),
-        2 => Ok(Literal::Flt(cursor.read_f64::<BigEndian>()
-            .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read float: {e}") })?)),
-        _ => Err(PgfError::DeserializeError { offset, message: format!("Unknown literal tag: {tag}") }),
-    }
+fn read_cncfun(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<CncFun, PgfError> {
+    let name = read_string(cursor, is_pgf_2_1)?;
+    let lins = read_list(cursor, read_int)?;
+    Ok(CncFun { name, lins })
 }
 
-fn read_string(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<CId, PgfError> {
-    let offset = cursor.position();
-    let len_raw = read_int(cursor)?;
-    
-    // Handle negative lengths as special markers (matching C implementation)
-    if len_raw < 0 {
-        debug_println!("DEBUG: read_string: negative length {} at pos {} - treating as empty string", len_raw, offset);
-        return Ok(CId("".to_string()));
-    }
-    
-    let len = len_raw as usize;
-    let result = read_string_with_length(cursor, len, is_pgf_2_1)?;
-    Ok(CId(result))
+fn read_cnccat(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<CncCat, PgfError> {
+    let name = read_string(cursor, is_pgf_2_1)?;
+    let start = read_int(cursor)?;
+    let end = read_int(cursor)?;
+    let labels = read_list(cursor, read_text)?.into_iter().map(String::from).collect();
+    Ok(CncCat { name, start, end, labels })
 }
 
-fn read_string_with_length(cursor: &mut Cursor<&[u8]>, len: usize, is_pgf_2_1: bool) -> Result<String, PgfError> {
-    const MAX_STRING_LEN: usize = 200; // Temporarily increased to debug structural issues
-    
+fn read_symbol(cursor: &mut Input<'_>, depth: u32, is_pgf_2_1: bool) -> Result<Symbol, PgfError> {
     let start_pos = cursor.position();
-    debug_println!("DEBUG: Reading string with length {} at pos {}", len, start_pos);
+    cursor.check(Limit::Depth, start_pos, depth as usize)?;
+    let tag = cursor.read_u8()
+        .map_err(|e| PgfError::eof(start_pos, "symbol tag", e))?;
 
-    // Validate string length - PGF strings are typically short identifiers
-    
-    // Special handling for extreme values that indicate structural issues
-    if len == usize::MAX || len > 1_000_000 {
-        debug_println!("DEBUG: Extreme string length {} at pos {} - likely EOF or structural boundary", len, start_pos);
-        debug_println!("DEBUG: Reached parsing boundary - likely completed main structure");
-        return Err(PgfError::DeserializeError {
-            offset: start_pos,
-            message: format!("Parsing boundary reached at pos {start_pos} ({}% complete) - likely completed main PGF structure", 
-                (start_pos * 100) / u64::try_from(cursor.get_ref().len()).unwrap_or(1)),
-        });
-    }
-    
-    if len > MAX_STRING_LEN {
-        debug_println!("DEBUG: Large string length {} at pos {} - treating as parsing boundary", len, start_pos);
-        return Err(PgfError::DeserializeError {
-            offset: start_pos,
-            message: format!("String length {len} at pos {start_pos} exceeds maximum ({MAX_STRING_LEN}), likely reached parsing boundary"),
-        });
+    match tag {
+        0 => {
+            let d = read_int(cursor)?;
+            let r = read_int(cursor)?;
+            Ok(Symbol::SymCat(d, r))
+        }
+        1 => {
+            let d = read_int(cursor)?;
+            let r = read_int(cursor)?;
+            Ok(Symbol::SymLit(d, r))
+        }
+        2 => {
+            let n = read_int(cursor)?;
+            let l = read_int(cursor)?;  // Read second parameter as per Haskell code
+            Ok(Symbol::SymVar(n, l))
+        }
+        3 if is_pgf_2_1 => Ok(Symbol::SymKS(read_text(cursor)?.into())),
+        4 if is_pgf_2_1 => {
+            let tokens = read_list(cursor, |c| read_symbol(c, depth + 1, is_pgf_2_1))?;
+            let alts = read_list(cursor, |c| read_alt(c, depth + 1, is_pgf_2_1))?;
+            Ok(Symbol::SymKP(tokens, alts))
+        }
+        // Version 1.0 stores the default form as a plain token list.
+        4 => {
+            let tokens = read_list(cursor, read_text)?;
+            let tokens = tokens.into_iter().map(|token| Symbol::SymKS(token.into())).collect();
+            let alts = read_list(cursor, |c| read_alt(c, depth + 1, is_pgf_2_1))?;
+            Ok(Symbol::SymKP(tokens, alts))
+        }
+        5 if is_pgf_2_1 => Ok(Symbol::SymBind),
+        6 if is_pgf_2_1 => Ok(Symbol::SymSoftBind),
+        7 if is_pgf_2_1 => Ok(Symbol::SymNE),
+        8 if is_pgf_2_1 => Ok(Symbol::SymSoftSpace),
+        9 if is_pgf_2_1 => Ok(Symbol::SymCapital),
+        10 if is_pgf_2_1 => Ok(Symbol::SymAllCapital),
+        _ => Err(PgfError::decode(start_pos, DecodeErrorKind::UnknownTag { what: "symbol", expected: if is_pgf_2_1 { "0..=10" } else { "0..=4" }, found: tag })),
     }
-    
-    debug_println!("DEBUG: Reading string at pos {}, length: {}", start_pos, len);
-    let mut buf = vec![0u8; len];
-    cursor.read_exact(&mut buf)
-        .map_err(|e| PgfError::DeserializeError { 
-            offset: start_pos, 
-            message: format!("Failed to read string: {e}") 
-        })?;
-    // Another debug print related to offset 180 and UTF-8. 
-    // debug_println!("Offset {}: Read bytes = {:?}", offset, buf);
-    // Check for float-like bytes (e.g., 253, 255, 255, 255, 127) - detection for cursor misalignment
-    if buf.len() > 4 && buf.starts_with(&[253, 255, 255, 255]) {
-        return Err(PgfError::DeserializeError {
-            offset: start_pos,
-            message: format!("String length {len} at pos {start_pos} looks like a float, possible misalignment"),
-        });
+}
+
+/// Read one entry of a sequence. Version 1.0 stores terminals as token lists,
+/// which are expanded into one `SymKS` per token so both layouts share one model.
+fn read_sequence_symbols(cursor: &mut Input<'_>, is_pgf_2_1: bool) -> Result<Vec<Symbol>, PgfError> {
+    let pos = usize::try_from(cursor.position()).unwrap_or(usize::MAX);
+    if !is_pgf_2_1 && cursor.get_ref().get(pos) == Some(&3) {
+        cursor.set_position(cursor.position() + 1);
+        let tokens = read_list(cursor, read_text)?;
+        return Ok(tokens.into_iter().map(|token| Symbol::SymKS(token.into())).collect());
     }
+    Ok(vec![read_symbol(cursor, 0, is_pgf_2_1)?])
+}
 
-    let string = if is_pgf_2_1 && cursor.position() < 100 { // CIds early in file use Latin-1
-        buf.iter().map(|&b| b as char).collect::<String>()
+fn read_alt(cursor: &mut Input<'_>, depth: u32, is_pgf_2_1: bool) -> Result<Alt, PgfError> {
+    let tokens = if is_pgf_2_1 {
+        read_list(cursor, |c| read_symbol(c, depth, is_pgf_2_1))?
     } else {
-        // Try UTF-8 first, but fall back to binary representation if it fails
-        match std::str::from_utf8(&buf) {
-            Ok(s) => s.to_string(),
-            Err(e) => {
-                debug_println!("DEBUG: UTF-8 decode failed at pos {}, length {}: {}", start_pos, len, e);
-                debug_println!("DEBUG: Invalid bytes: {:?}", &buf[..buf.len().min(50)]);
-                
-                // Check if this looks like binary data (many high-value bytes)
-                let binary_bytes = buf.iter().filter(|&&b| b > 127).count();
-                if binary_bytes > buf.len() / 4 || buf.contains(&253) || buf.contains(&254) {
-                    debug_println!("DEBUG: Treating as binary data - {} high bytes out of {}", binary_bytes, buf.len());
-                    // Create a hex representation for binary data
-                    format!("binary_data_{}_bytes", buf.len())
-                } else {
-                    // Try Latin-1 fallback for text-like data
-                    debug_println!("DEBUG: Trying Latin-1 fallback");
-                    buf.iter().map(|&b| b as char).collect::<String>()
-                }
-            }
-        }
+        read_list(cursor, read_text)?.into_iter().map(|token| Symbol::SymKS(token.into())).collect()
     };
-    
-    Ok(string)
+    let prefixes = read_list(cursor, read_text)?.into_iter().map(String::from).collect();
+    Ok(Alt { tokens, prefixes })
 }
 
-#[allow(clippy::unnecessary_wraps)]
-fn read_string_fallback(cursor: &mut Cursor<&[u8]>, start_pos: u64, is_pgf_2_1: bool, tag: u8) -> Result<String, PgfError> {
-    const MAX_STRING_LEN: usize = 100; // Increased to handle longer strings like "ConfirmFlight"
+fn read_list<T, F>(cursor: &mut Input<'_>, f: F) -> Result<Vec<T>, PgfError>
+where
+    F: Fn(&mut Input<'_>) -> Result<T, PgfError>,
+{
+    let offset = cursor.position();
     
-    debug_println!("DEBUG: Fallback reading string at pos {} for tag {}", start_pos, tag);
-    let mut bytes = Vec::new();
-    let mut len = 0;
-
-    // Read until a valid tag (0–10), EOF, or max length
-    let original_pos = cursor.position();
-    while len < MAX_STRING_LEN {
-        let pos = cursor.position();
-        let byte = cursor.read_u8();
-        match byte {
-            Ok(b) if b <= 10 || b == 0 => {
-                // Valid tag or null byte, rewind and stop
-                cursor.set_position(pos);
-                break;
-            }
-            Ok(b) => {
-                bytes.push(b);
-                len += 1;
-            }
-            Err(_) => {
-                // EOF
-                break;
-            }
+    // Handle termination markers
+    let len = match read_int(cursor) {
+        Ok(l) => {
+            l
+        },
+        // Only treat EOF as normal if the list would start exactly at the end of
+        // the data, and never in strict mode, where that is a truncated file.
+        Err(e) if !cursor.strict && is_eof_error(&e) && offset == cursor.get_ref().len() as u64 => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e);
         }
+    };
+    
+    if len < 0 {
+        return Err(PgfError::decode(offset, DecodeErrorKind::NegativeLength { what: "list", found: len }));
     }
-
-    if len == 0 {
-        debug_println!("DEBUG: Empty string in fallback at pos {}", start_pos);
-        return Ok(String::new());
+    
+    let len = usize::try_from(len).unwrap_or(0);
+    cursor.check(Limit::ListLength, offset, len)?;
+    
+    let mut result = Vec::with_capacity(cursor.capacity(len));
+    for _ in 0..len {
+        result.push(f(cursor)?);
     }
+    Ok(result)
+}
 
-    let string = if is_pgf_2_1 && start_pos < 100 {
-        // Early strings (e.g., category names) may use Latin-1
-        bytes.iter().map(|&b| b as char).collect::<String>()
-    } else {
-        // Try UTF-8 first, but fall back to binary representation if it fails
-        match std::str::from_utf8(&bytes) {
-            Ok(s) => s.to_string(),
-            Err(e) => {
-                debug_println!("DEBUG: UTF-8 decode failed in symbol fallback at pos {}, length {}: {}", start_pos, len, e);
-                debug_println!("DEBUG: Invalid bytes in symbol fallback: {:?}", &bytes[..bytes.len().min(20)]);
-                
-                // Check if this looks like binary data (many high-value bytes)
-                let binary_bytes = bytes.iter().filter(|&&b| b > 127).count();
-                if binary_bytes > bytes.len() / 4 || bytes.contains(&253) || bytes.contains(&254) {
-                    debug_println!("DEBUG: Symbol fallback treating as binary data - {} high bytes out of {}", binary_bytes, bytes.len());
-                    // Create a safe representation for binary data
-                    format!("binary_symbol_tag_{tag}_len_{}", bytes.len())
-                } else {
-                    // Try Latin-1 fallback for text-like data
-                    debug_println!("DEBUG: Symbol fallback trying Latin-1");
-                    bytes.iter().map(|&b| b as char).collect::<String>()
-                }
-            }
-        }
-    };
+/// Write a PGF structure to the given path.
+///
+/// # Errors
+/// Returns `PgfError::Io` if the file cannot be written.
+/// Returns `PgfError::SerializeError` if the grammar cannot be encoded.
+pub fn write_pgf(pgf: &Pgf, path: &str) -> Result<(), PgfError> {
+    let bytes = serialize_pgf(pgf)?;
+    let mut file = File::create(path)?;
+    file.write_all(&bytes)?;
+    Ok(())
+}
 
-    // Accept the string as-is - PGF format allows various character encodings
-    // The fallback logic above already handles UTF-8 vs Latin-1 vs binary data appropriately
-    debug_println!("DEBUG: Fallback read string '{}' (length {}) at pos {}", string, len, start_pos);
-    Ok(string)
+/// Serializes a PGF structure into the binary format read by [`parse_pgf`].
+///
+/// The output uses the PGF 2.1 layout produced by the GF compiler, so that
+/// `parse_pgf(&serialize_pgf(&pgf)?)?` yields a grammar equal to `pgf`.
+///
+/// # Errors
+///
+/// Returns [`PgfError::SerializeError`] if a list or string is too long to be
+/// encoded as a PGF `Int`, or if some concrete syntax was skipped by
+/// [`LoadOptions::languages`] and has not been loaded since.
+pub fn serialize_pgf(pgf: &Pgf) -> Result<Bytes, PgfError> {
+    pgf.check_fully_loaded()?;
+    let mut buf = Vec::new();
+    serialize_pgf_binary(&mut buf, pgf)?;
+    Ok(Bytes::from(buf))
 }
-/* fn read_string(cursor: &mut Cursor<&[u8]>) -> Result<CId, PgfError> {
-    let offset = cursor.position();
-    let len = usize::try_from(read_int(cursor)?).map_err(|_| PgfError::DeserializeError { offset, message: "String length cannot be negative".to_string() })?;
-    let mut buf = vec![0u8; len];
-    cursor.read_exact(&mut buf)
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read string: {e}") })?;
-    let s = String::from_utf8(buf)
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Invalid UTF-8 string: {e}") })?;
-    Ok(cid::mk_cid(&s))
-} */
 
-fn read_abstract(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<(CId, Abstract), PgfError> {
-    let offset = cursor.position();
-    let name = read_string(cursor, is_pgf_2_1)?;
-    let flags = read_flags(cursor, is_pgf_2_1)?;
-    let fun_count = read_int(cursor)?;
-    debug_println!("Abstract: reading {} functions", fun_count);
-    let mut funs = HashMap::new();
-    let mut cats = HashMap::new();
+/// Magic bytes at the start of a grammar cache file.
+const CACHE_MAGIC: &[u8; 4] = b"PGFC";
+/// Bumped whenever the cache layout or the model types change.
+const CACHE_FORMAT_VERSION: u32 = 2;
+
+impl Pgf {
+    /// Store a snapshot of this grammar at `path`, keyed by the bytes of the
+    /// `.pgf` file it was parsed from.
+    ///
+    /// The snapshot is written to a uniquely named temporary file next to
+    /// `path` and then renamed, so readers never see a partial cache and
+    /// concurrent writers do not clobber each other's temporary files.
+    ///
+    /// # Errors
+    /// Returns `PgfError::Io` if the file cannot be written.
+    /// Returns `PgfError::SerializeError` if the grammar cannot be encoded, or
+    /// if some concrete syntax skipped by [`LoadOptions::languages`] has not
+    /// been loaded.
+    pub fn save_cache(&self, path: &str, source: &[u8]) -> Result<(), PgfError> {
+        self.check_fully_loaded()?;
+        let payload = bincode::serialize(self).map_err(|e| PgfError::SerializeError(e.to_string()))?;
+        let version = env!("CARGO_PKG_VERSION").as_bytes();
+        let mut buf = Vec::with_capacity(payload.len() + 48);
+        buf.extend_from_slice(CACHE_MAGIC);
+        buf.write_u32::<LittleEndian>(CACHE_FORMAT_VERSION)?;
+        buf.write_u16::<LittleEndian>(u16::try_from(version.len()).unwrap_or(u16::MAX))?;
+        buf.extend_from_slice(version);
+        buf.write_u64::<LittleEndian>(fnv1a(source))?;
+        buf.write_u64::<LittleEndian>(payload.len() as u64)?;
+        buf.write_u64::<LittleEndian>(fnv1a(&payload))?;
+        buf.extend_from_slice(&payload);
+
+        let dir = std::path::Path::new(path).parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| std::path::Path::new("."));
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+        tmp.write_all(&buf)?;
+        tmp.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
 
-    for i in 0..fun_count {
-        debug_println!("Reading function {}/{}", i+1, fun_count);
-        let fun_name = read_string(cursor, is_pgf_2_1)?;
-        let ty = read_type(cursor, 0, is_pgf_2_1)?;
-        let arity = read_int(cursor)?;
-        let tag = cursor.read_u8()
-            .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read function tag: {e}") })?;
-        let is_constructor = tag == 0;
-        let equations = if tag == 1 {
-            Some(read_list(cursor, |c| read_equation(c, is_pgf_2_1))?)
-        } else {
-            None
+    /// Load a snapshot written by [`Pgf::save_cache`].
+    ///
+    /// Returns `Ok(None)` if the cache is missing or stale: written by another
+    /// version of this crate, for a different `source`, or corrupted. The
+    /// caller should then parse `source` and save a new cache.
+    ///
+    /// # Errors
+    /// Returns `PgfError::Io` if the cache exists but cannot be read.
+    pub fn load_cache(path: &str, source: &[u8]) -> Result<Option<Pgf>, PgfError> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
         };
-        let prob = cursor.read_f64::<BigEndian>()
-            .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read probability: {e}") })?;
-
-        funs.insert(fun_name.clone(), Function {
-            ty: ty.clone(),
-            weight: 1, // Default weight
-            equations,
-            arity,
-            is_constructor,
-            prob,
-        });
+        Ok(decode_cache(&data, source))
+    }
+}
 
-        cats.entry(ty.category.clone())
-            .or_insert_with(|| Category { hypos: vec![], funs: vec![] })
-            .funs.push((0, fun_name));
+fn decode_cache(data: &[u8], source: &[u8]) -> Option<Pgf> {
+    let mut cursor = Cursor::new(data);
+    let mut magic = [0u8; 4];
+    cursor.read_exact(&mut magic).ok()?;
+    if &magic != CACHE_MAGIC || cursor.read_u32::<LittleEndian>().ok()? != CACHE_FORMAT_VERSION {
+        return None;
     }
+    let mut version = vec![0u8; usize::from(cursor.read_u16::<LittleEndian>().ok()?)];
+    cursor.read_exact(&mut version).ok()?;
+    if version != env!("CARGO_PKG_VERSION").as_bytes() || cursor.read_u64::<LittleEndian>().ok()? != fnv1a(source) {
+        return None;
+    }
+    let len = usize::try_from(cursor.read_u64::<LittleEndian>().ok()?).ok()?;
+    let checksum = cursor.read_u64::<LittleEndian>().ok()?;
+    let payload = data.get(usize::try_from(cursor.position()).ok()?..)?;
+    if payload.len() != len || fnv1a(payload) != checksum {
+        return None;
+    }
+    // The same encoding as `bincode::deserialize`, but length prefixes cannot
+    // claim more memory than a grammar file may take.
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(Limits::default().max_bytes as u64)
+        .deserialize(payload)
+        .ok()
+}
 
-    let cat_count = read_int(cursor)?;
-    debug_println!("Abstract: reading {} categories", cat_count);
-    for i in 0..cat_count {
-        debug_println!("Reading category {}/{}", i+1, cat_count);
-        debug_println!("Offset {}: Reading string length = {}", cursor.position(), cursor.clone().read_u8().unwrap_or(0));
-        let cat_name = read_string(cursor, is_pgf_2_1)?;
-        let hypos = read_list(cursor, |c| read_hypo(c, is_pgf_2_1))?;
-        let cat_funs = read_list(cursor, |cursor| {
-            let prob = cursor.read_f64::<BigEndian>()?;  // Read prob first (negated log)
-            let name = read_string(cursor, is_pgf_2_1)?;
-            Ok((0, name))  // Discard prob for now; could store as (prob as usize, name) if needed
-        })?;
-        let _cat_prob = cursor.read_f64::<BigEndian>()?;  // Read and discard category probability
+/// 64-bit FNV-1a, used to key and check cache files. Not a cryptographic
+/// hash: caches must not be shared with untrusted parties.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
 
-        cats.insert(cat_name, Category { hypos, funs: cat_funs });
+/// Read a PGF file through a cache at `cache_path`.
+///
+/// If the cache holds a snapshot of the current contents of `path` it is
+/// loaded instead of parsing the file; otherwise the file is parsed and the
+/// cache is rewritten.
+///
+/// # Errors
+/// Returns `PgfError::Io` if a file cannot be read or the cache cannot be
+/// written.
+/// Returns other `PgfError` variants if parsing fails.
+pub fn read_pgf_cached(path: &str, cache_path: &str) -> Result<Pgf, PgfError> {
+    let source = std::fs::read(path)?;
+    if let Some(pgf) = Pgf::load_cache(cache_path, &source)? {
+        return Ok(pgf);
     }
+    let pgf = parse_pgf(&Bytes::copy_from_slice(&source))?;
+    pgf.save_cache(cache_path, &source)?;
+    Ok(pgf)
+}
 
-    Ok((name, Abstract { funs, cats }))
+fn serialize_pgf_binary(buf: &mut Vec<u8>, pgf: &Pgf) -> Result<(), PgfError> {
+    buf.write_i16::<BigEndian>(2)?;
+    buf.write_i16::<BigEndian>(1)?;
+    write_flags(buf, &pgf.flags)?;
+    write_abstract(buf, &pgf.absname, &pgf.r#abstract)?;
+    write_concretes(buf, &pgf.concretes)
 }
 
-// FIXME: add , is_pgf_2_1: bool to fn sig
-fn read_type(cursor: &mut Cursor<&[u8]>, depth: u32, is_pgf_2_1: bool) -> Result<Type, PgfError> {
-    const MAX_DEPTH: u32 = 100;
-    if depth > MAX_DEPTH {
-        return Err(PgfError::DeserializeError {
-            offset: cursor.position(),
-            message: "Maximum recursion depth exceeded in type parsing".to_string(),
-        });
-    }
-    let offset = cursor.position();
-    let hypos = read_list(cursor, |c| read_hypo(c, is_pgf_2_1))?;
-    let category = read_string(cursor, is_pgf_2_1)?;
-    let exprs = read_list(cursor, |c| read_expr(c, depth + 1, is_pgf_2_1))?;
-    Ok(Type { hypos, category, exprs })
+// Maps are stored sorted by key, which is what GF's binary encoder does.
+fn sorted_by_cid<V>(map: &HashMap<CId, V>) -> Vec<(&CId, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+    entries
 }
 
-fn read_hypo(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<Hypo, PgfError> {
-    let offset = cursor.position();
-    let binding = read_binding(cursor, is_pgf_2_1)?;
-    let ty = read_type(cursor, 0, is_pgf_2_1)?;
-    Ok(Hypo { binding, ty })
+fn write_flags(buf: &mut Vec<u8>, flags: &HashMap<CId, Literal>) -> Result<(), PgfError> {
+    write_len(buf, flags.len())?;
+    for (key, value) in sorted_by_cid(flags) {
+        write_string(buf, key)?;
+        write_literal(buf, value)?;
+    }
+    Ok(())
 }
 
-fn read_binding(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<Binding, PgfError> {
-    let offset = cursor.position();
-    let tag = cursor.read_u8()
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read binding tag: {e}") })?;
-    let name = read_string(cursor, is_pgf_2_1)?;
-    match tag {
-        0 => Ok(Binding::Explicit(cid::show_cid(&name))),
-        1 => Ok(Binding::Implicit(cid::show_cid(&name))),
-        _ => {
-            debug_println!("DEBUG: Unknown binding tag {} at pos {} - treating as Explicit fallback", tag, offset);
-            // Fallback: treat unknown binding tags as Explicit
-            Ok(Binding::Explicit(cid::show_cid(&name)))
+// Inverse of `read_int`. Negative values keep GF's five-byte form, where the
+// last group carries the sign extension.
+#[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+fn write_int(buf: &mut Vec<u8>, value: i32) -> Result<(), PgfError> {
+    if value < 0 {
+        for shift in [0, 7, 14, 21] {
+            buf.write_u8(((value >> shift) & 0x7F) as u8 | 0x80)?;
         }
+        buf.write_u8(((value >> 28) & 0x7F) as u8)?;
+        return Ok(());
     }
+    let mut rest = value as u32;
+    while rest >= 0x80 {
+        buf.write_u8((rest & 0x7F) as u8 | 0x80)?;
+        rest >>= 7;
+    }
+    buf.write_u8(rest as u8)?;
+    Ok(())
 }
 
-// FIXME: check order of arguments in here with , is_pgf_2_1: bool
-fn read_expr(cursor: &mut Cursor<&[u8]>, depth: u32, is_pgf_2_1: bool) -> Result<Expr, PgfError> {
-    const MAX_DEPTH: u32 = 100;
-    if depth > MAX_DEPTH {
-        return Err(PgfError::DeserializeError {
-            offset: cursor.position(),
-            message: "Maximum recursion depth exceeded in expression parsing".to_string(),
-        });
-    }
-    let offset = cursor.position();
-    let tag = cursor.read_u8()
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read expr tag: {e}") })?;
-    match tag {
-        0 => {
-            let binding = read_binding(cursor, is_pgf_2_1)?;
-            let var = read_string(cursor, is_pgf_2_1)?;
-            let body = read_expr(cursor, depth + 1, is_pgf_2_1)?;
-            Ok(Expr::Abs(binding, var, Box::new(body)))
-        }
-        1 => {
-            let lhs = read_expr(cursor, depth + 1, is_pgf_2_1)?;
-            let rhs = read_expr(cursor, depth + 1, is_pgf_2_1)?;
-            Ok(Expr::App(Box::new(lhs), Box::new(rhs)))
-        }
-        2 => Ok(Expr::Lit(read_literal(cursor, is_pgf_2_1)?)),
-        3 => Ok(Expr::Meta(read_int(cursor)?)),
-        4 => Ok(Expr::Fun(read_string(cursor, is_pgf_2_1)?)),
-        5 => Ok(Expr::Var(read_int(cursor)?)),
-        6 => {
-            let expr = read_expr(cursor, depth + 1, is_pgf_2_1)?;
-            let ty = read_type(cursor, depth + 1, is_pgf_2_1)?;
-            Ok(Expr::Typed(Box::new(expr), ty))
+fn write_len(buf: &mut Vec<u8>, len: usize) -> Result<(), PgfError> {
+    let len = i32::try_from(len)
+        .map_err(|_| PgfError::SerializeError(format!("Length {len} does not fit in a PGF Int")))?;
+    write_int(buf, len)
+}
+
+fn write_literal(buf: &mut Vec<u8>, lit: &Literal) -> Result<(), PgfError> {
+    match lit {
+        Literal::Str(s) => {
+            buf.write_u8(0)?;
+            write_text(buf, s)
         }
-        7 => {
-            let expr = read_expr(cursor, depth + 1, is_pgf_2_1)?;
-            Ok(Expr::ImplArg(Box::new(expr)))
+        Literal::Int(n) => {
+            buf.write_u8(1)?;
+            write_int(buf, *n)
         }
-        _ => {
-            debug_println!("DEBUG: Unknown expr tag {} at pos {} - attempting fallback", tag, offset);
-            // High-value tags (>127) likely indicate binary data misalignment
-            if tag > 127 {
-                debug_println!("DEBUG: High expr tag {} suggests binary data - treating as Meta fallback", tag);
-                // Try to read as Meta (integer expression)
-                let meta_value = i32::from(tag); // Use the tag value itself as meta
-                Ok(Expr::Meta(meta_value))
-            } else {
-                // Lower unknown tags - try to parse as Fun (string expression)
-                debug_println!("DEBUG: Low expr tag {} - treating as Fun fallback", tag);
-                let fun_name = format!("unknown_expr_tag_{tag}");
-                Ok(Expr::Fun(CId(fun_name)))
-            }
+        Literal::Flt(d) => {
+            buf.write_u8(2)?;
+            buf.write_f64::<BigEndian>(*d)?;
+            Ok(())
         }
     }
 }
 
-fn read_equation(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<Equation, PgfError> {
-    let patterns = read_list(cursor, |c| read_pattern(c, is_pgf_2_1))?;
-    let result = read_expr(cursor, 0, is_pgf_2_1)?;
-    Ok(Equation { patterns, result })
+// Inverse of `read_string`: identifiers are prefixed with their length in bytes.
+fn write_string(buf: &mut Vec<u8>, cid: &CId) -> Result<(), PgfError> {
+    write_len(buf, cid.0.len())?;
+    buf.extend_from_slice(cid.0.as_bytes());
+    Ok(())
 }
 
-fn read_pattern(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<Pattern, PgfError> {
-    let offset = cursor.position();
-    let tag = cursor.read_u8()
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read pattern tag: {e}") })?;
-    match tag {
-        0 => {
-            let constr = read_string(cursor, is_pgf_2_1)?;
-            let patterns = read_list(cursor, |c| read_pattern(c, is_pgf_2_1))?;
-            Ok(Pattern::PApp(constr, patterns))
-        }
-        1 => Ok(Pattern::PVar(read_string(cursor, is_pgf_2_1)?)),
-        2 => {
-            let var = read_string(cursor, is_pgf_2_1)?;
-            let pattern = read_pattern(cursor, is_pgf_2_1)?;
-            Ok(Pattern::PBind(var, Box::new(pattern)))
-        }
-        3 => Ok(Pattern::PWildcard),
-        4 => Ok(Pattern::PLit(read_literal(cursor, is_pgf_2_1)?)),
-        5 => Ok(Pattern::PImplicit(read_list(cursor, |c| read_pattern(c, is_pgf_2_1))?)),
-        6 => Ok(Pattern::PInaccessible(read_expr(cursor, 0, is_pgf_2_1)?)),
-        _ => Err(PgfError::DeserializeError { offset, message: format!("Unknown pattern tag: {tag}") }),
+// Inverse of `read_text`: the length prefix counts characters.
+fn write_text(buf: &mut Vec<u8>, text: &str) -> Result<(), PgfError> {
+    write_len(buf, text.chars().count())?;
+    buf.extend_from_slice(text.as_bytes());
+    Ok(())
+}
+
+fn write_list<T, F>(buf: &mut Vec<u8>, items: &[T], f: F) -> Result<(), PgfError>
+where
+    F: Fn(&mut Vec<u8>, &T) -> Result<(), PgfError>,
+{
+    write_len(buf, items.len())?;
+    for item in items {
+        f(buf, item)?;
     }
+    Ok(())
 }
 
-fn read_concretes(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<HashMap<Language, Concrete>, PgfError> {
-    println!("PARSER: read_concretes starting at position {}", cursor.position());
-    
-    // Manual parsing approach to handle partial success
-    let mut concretes = HashMap::new();
-    
-    // Read the count of concretes
-    let count = match read_int(cursor) {
-        Ok(c) => c,
-        Err(e) => {
-            println!("PARSER: Failed to read concrete count: {e:?}");
-            return Ok(HashMap::new());
-        }
-    };
-    
-    println!("PARSER: Reading {count} concrete syntaxes");
-    
-    for i in 0..count {
-        println!("PARSER: Processing concrete {} of {}", i + 1, count);
-        
-        // Read language name
-        let lang_name = match read_string(cursor, is_pgf_2_1) {
-            Ok(name) => {
-                // If we get an empty language name, this might indicate end of valid data
-                if name.0.is_empty() {
-                    println!("PARSER: Empty language name for concrete {}, likely end of valid concrete data", i + 1);
-                    break;
-                }
-                name
-            },
-            Err(e) => {
-                println!("PARSER: Failed to read language name for concrete {}: {:?}", i + 1, e);
-                break; // Stop processing, but return what we have
-            }
-        };
-        
-        println!("PARSER: Reading concrete for language: {lang_name:?}");
-        
-        // Try to read the concrete syntax with error handling
-        match read_concrete(cursor, is_pgf_2_1) {
-            Ok(concrete) => {
-                println!("PARSER: Successfully parsed concrete for {lang_name:?}");
-                concretes.insert(Language(lang_name), concrete);
-            }
-            Err(e) => {
-                println!("PARSER: Failed to parse concrete for {lang_name:?}: {e:?}");
-                // Continue processing or break depending on error type
-                if e.to_string().contains("failed to fill whole buffer") || 
-                   e.to_string().contains("Unknown literal tag") ||
-                   e.to_string().contains("List length") ||
-                   e.to_string().contains("Negative list length") ||
-                   e.to_string().contains("parsing error") {
-                    println!("PARSER: Parsing error - stopping concrete parsing but returning what we have");
-                    break;
-                }
-                return Err(e);
+fn write_abstract(buf: &mut Vec<u8>, name: &CId, abs: &Abstract) -> Result<(), PgfError> {
+    write_string(buf, name)?;
+    write_flags(buf, &abs.aflags)?;
+
+    write_len(buf, abs.funs.len())?;
+    for (fun_name, fun) in sorted_by_cid(&abs.funs) {
+        write_string(buf, fun_name)?;
+        write_type(buf, &fun.ty)?;
+        write_int(buf, fun.arity)?;
+        match &fun.equations {
+            Some(equations) => {
+                buf.write_u8(1)?;
+                write_list(buf, equations, write_equation)?;
             }
+            None => buf.write_u8(0)?,
         }
+        buf.write_f64::<BigEndian>(fun.prob)?;
     }
-    
-    println!("PARSER: Completed concrete parsing with {} languages", concretes.len());
-    Ok(concretes)
+
+    write_len(buf, abs.cats.len())?;
+    for (cat_name, cat) in sorted_by_cid(&abs.cats) {
+        write_string(buf, cat_name)?;
+        write_list(buf, &cat.hypos, write_hypo)?;
+        write_list(buf, &cat.funs, |buf, (prob, fun_name)| {
+            buf.write_f64::<BigEndian>(*prob)?;
+            write_string(buf, fun_name)
+        })?;
+        buf.write_f64::<BigEndian>(cat.prob)?;
+    }
+    Ok(())
 }
 
-// Robust version of read_concrete that handles EOF more gracefully  
-#[allow(clippy::similar_names)]
-fn read_concrete_robust(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<Concrete, PgfError> {
-    // Manual concrete parsing with better EOF handling for optional fields
-    println!("PARSER: Starting robust concrete parsing at pos {}", cursor.position());
-    
-    let name = read_string(cursor, is_pgf_2_1)?;
-    println!("PARSER: Read concrete name: {name:?}");
-    
-    let cflags = read_flags(cursor, is_pgf_2_1)?;
-    println!("PARSER: Read {} flags", cflags.len());
-    
-    let printnames = read_list(cursor, |c| read_printname(c, is_pgf_2_1))?;
-    println!("PARSER: Read {} printnames", printnames.len());
-    
-    // Parse sequences with more robust error handling
-    let sequences = match parse_sequences_robust(cursor, is_pgf_2_1) {
-        Ok(seqs) => {
-            println!("PARSER: Successfully parsed {} sequences", seqs.len());
-            seqs
+fn write_type(buf: &mut Vec<u8>, ty: &Type) -> Result<(), PgfError> {
+    write_list(buf, &ty.hypos, write_hypo)?;
+    write_string(buf, &ty.category)?;
+    write_list(buf, &ty.exprs, write_expr)
+}
+
+fn write_hypo(buf: &mut Vec<u8>, hypo: &Hypo) -> Result<(), PgfError> {
+    write_binding(buf, &hypo.binding)?;
+    write_type(buf, &hypo.ty)
+}
+
+fn write_binding(buf: &mut Vec<u8>, binding: &Binding) -> Result<(), PgfError> {
+    let (tag, name) = match binding {
+        Binding::Explicit(name) => (0, name),
+        Binding::Implicit(name) => (1, name),
+    };
+    buf.write_u8(tag)?;
+    write_string(buf, &cid::mk_cid(name))
+}
+
+fn write_expr(buf: &mut Vec<u8>, expr: &Expr) -> Result<(), PgfError> {
+    match expr {
+        Expr::Abs(binding, var, body) => {
+            buf.write_u8(0)?;
+            buf.write_u8(match binding {
+                Binding::Explicit(_) => 0,
+                Binding::Implicit(_) => 1,
+            })?;
+            write_string(buf, var)?;
+            write_expr(buf, body)
         }
-        Err(e) => {
-            println!("PARSER: Failed to parse sequences: {e:?}");
-            return Err(e);
+        Expr::App(lhs, rhs) => {
+            buf.write_u8(1)?;
+            write_expr(buf, lhs)?;
+            write_expr(buf, rhs)
         }
-    };
-    
-    let cncfuns = read_list(cursor, |c| read_cncfun(c, is_pgf_2_1))?;
-    println!("PARSER: Read {} cncfuns", cncfuns.len());
-    
-    // Parse remaining fields with EOF tolerance
-    let ccats = match read_list(cursor, read_ccat) {
-        Ok(cc) => cc,
-        Err(e) if e.to_string().contains("failed to fill whole buffer") => {
-            println!("PARSER: EOF reading ccats - using empty list");
-            Vec::new()
+        Expr::Lit(lit) => {
+            buf.write_u8(2)?;
+            write_literal(buf, lit)
         }
-        Err(e) => return Err(e),
-    };
-    
-    #[allow(clippy::similar_names)]
-    let lindefs = match read_list(cursor, read_lindef) {
-        Ok(ld) => ld,
-        Err(e) if e.to_string().contains("failed to fill whole buffer") => {
-            println!("PARSER: EOF reading lindefs - using empty list");
-            Vec::new()
+        // The binary format only knows literal expressions, so the
+        // convenience variants are stored as the equivalent literal.
+        Expr::Str(s) => write_expr(buf, &Expr::Lit(Literal::Str(s.clone()))),
+        Expr::Int(n) => write_expr(buf, &Expr::Lit(Literal::Int(*n))),
+        Expr::Float(f) => write_expr(buf, &Expr::Lit(Literal::Flt(f64::from(*f)))),
+        Expr::Double(d) => write_expr(buf, &Expr::Lit(Literal::Flt(*d))),
+        Expr::Meta(id) => {
+            buf.write_u8(3)?;
+            write_int(buf, *id)
         }
-        Err(e) => return Err(e),
-    };
-    
-    #[allow(clippy::similar_names)]
-    let linrefs = match read_list(cursor, read_linref) {
-        Ok(lr) => lr,
-        Err(e) if e.to_string().contains("failed to fill whole buffer") => {
-            println!("PARSER: EOF reading linrefs - using empty list");
-            Vec::new()
+        Expr::Fun(name) => {
+            buf.write_u8(4)?;
+            write_string(buf, name)
         }
-        Err(e) => return Err(e),
-    };
-    
-    let cnccats = match read_list(cursor, |c| read_cnccat(c, is_pgf_2_1)) {
-        Ok(cc) => cc.into_iter().map(|c| (c.name.clone(), c)).collect(),
-        Err(e) if e.to_string().contains("failed to fill whole buffer") => {
-            println!("PARSER: EOF reading cnccats - using empty map");
-            HashMap::new()
+        Expr::Var(index) => {
+            buf.write_u8(5)?;
+            write_int(buf, *index)
         }
-        Err(e) => return Err(e),
-    };
-    
-    let total_cats = match read_int(cursor) {
-        Ok(tc) => tc,
-        Err(e) if e.to_string().contains("failed to fill whole buffer") => {
-            println!("PARSER: EOF reading total_cats - using default");
-            i32::try_from(ccats.len()).unwrap_or(0)
+        Expr::Typed(expr, ty) => {
+            buf.write_u8(6)?;
+            write_expr(buf, expr)?;
+            write_type(buf, ty)
         }
-        Err(e) => return Err(e),
-    };
-    
-    let productions = ccats.iter().map(|ccat| (ccat.id, ccat.productions.clone())).collect();
-    
-    println!("PARSER: Completed robust concrete parsing");
-    Ok(Concrete {
-        cflags,
-        productions,
-        cncfuns,
-        sequences,
-        cnccats,
-        printnames,
-        lindefs,
-        linrefs,
-        ccats,
-        total_cats,
-    })
+        Expr::ImplArg(expr) => {
+            buf.write_u8(7)?;
+            write_expr(buf, expr)
+        }
+    }
 }
 
-// Helper function to parse sequences with better error handling
-fn parse_sequences_robust(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<Vec<Vec<Symbol>>, PgfError> {
-    let sequences_len = match read_int(cursor) {
-        Ok(len) => usize::try_from(len).map_err(|_| PgfError::DeserializeError { 
-            offset: cursor.position(), 
-            message: "Sequences length cannot be negative".to_string() 
-        })?,
-        Err(e) if e.to_string().contains("failed to fill whole buffer") => {
-            println!("PARSER: EOF reading sequences_len - using 0");
-            return Ok(Vec::new());
+fn write_equation(buf: &mut Vec<u8>, equation: &Equation) -> Result<(), PgfError> {
+    write_list(buf, &equation.patterns, write_pattern)?;
+    write_expr(buf, &equation.result)
+}
+
+fn write_pattern(buf: &mut Vec<u8>, pattern: &Pattern) -> Result<(), PgfError> {
+    match pattern {
+        Pattern::PApp(constr, patterns) => {
+            buf.write_u8(0)?;
+            write_string(buf, constr)?;
+            write_list(buf, patterns, write_pattern)
         }
-        Err(e) => return Err(e),
-    };
-    
-    let mut sequences = Vec::with_capacity(sequences_len);
-    
-    for i in 0..sequences_len {
-        let syms_len = match read_int(cursor) {
-            Ok(len) => usize::try_from(len).map_err(|_| PgfError::DeserializeError { 
-                offset: cursor.position(), 
-                message: "Symbols length cannot be negative".to_string() 
-            })?,
-            Err(e) if e.to_string().contains("failed to fill whole buffer") => {
-                println!("PARSER: EOF reading syms_len for sequence {i} - stopping");
-                break;
-            }
-            Err(e) => return Err(e),
-        };
-        
-        let mut symbols = Vec::with_capacity(syms_len);
-        
-        for j in 0..syms_len {
-            match read_symbol(cursor, is_pgf_2_1) {
-                Ok(symbol) => symbols.push(symbol),
-                Err(e) if e.to_string().contains("failed to fill whole buffer") => {
-                    println!("PARSER: EOF reading symbol {j} in sequence {i} - stopping");
-                    break;
-                }
-                Err(e) => return Err(e),
-            }
+        Pattern::PVar(var) => {
+            buf.write_u8(1)?;
+            write_string(buf, var)
+        }
+        Pattern::PBind(var, pattern) => {
+            buf.write_u8(2)?;
+            write_string(buf, var)?;
+            write_pattern(buf, pattern)
+        }
+        Pattern::PWildcard => Ok(buf.write_u8(3)?),
+        Pattern::PLit(lit) => {
+            buf.write_u8(4)?;
+            write_literal(buf, lit)
+        }
+        Pattern::PImplicit(patterns) => {
+            buf.write_u8(5)?;
+            write_list(buf, patterns, write_pattern)
+        }
+        Pattern::PInaccessible(expr) => {
+            buf.write_u8(6)?;
+            write_expr(buf, expr)
         }
-        
-        sequences.push(symbols);
     }
-    
-    Ok(sequences)
 }
 
-#[allow(clippy::too_many_lines, clippy::similar_names)]
-fn read_concrete(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<Concrete, PgfError> {
-    debug_println!("DEBUG: Starting read_concrete at pos {}", cursor.position());
-    
-    let cflags = read_flags(cursor, is_pgf_2_1)?;
-    debug_println!("DEBUG: Read {} cflags at pos {}", cflags.len(), cursor.position());
-    let printnames = read_list(cursor, |c| read_printname(c, is_pgf_2_1))?;
-    debug_println!("DEBUG: Read {} printnames at pos {}", printnames.len(), cursor.position());
-    debug_println!("DEBUG: About to read sequences, next few bytes: {:?}", 
-        cursor.get_ref().get(usize::try_from(cursor.position()).unwrap_or(0)..usize::try_from(cursor.position()).unwrap_or(0) + 10).unwrap_or(&[]));
-    
-    // Read sequences normally without hardcoded positions
-    let sequences_len = match read_int(cursor) {
-        Ok(len) => usize::try_from(len).map_err(|_| PgfError::DeserializeError { offset: cursor.position(), message: "Sequences length cannot be negative".to_string() })?,
-        Err(PgfError::DeserializeError { message, .. }) if message.contains("failed to fill whole buffer") || message.contains("Parsing boundary reached") => {
-            debug_println!("DEBUG: Reached EOF reading sequences_len - using 0");
-            0
-        }
-        Err(e) => return Err(e),
-    };
-    debug_println!("DEBUG: sequences_len={} at pos {}", sequences_len, cursor.position());
-    
-    let mut sequences = Vec::with_capacity(sequences_len);
-    for i in 0..sequences_len {
-        let seq_pos = cursor.position();
-        let syms_len = match read_int(cursor) {
-            Ok(len) => usize::try_from(len).map_err(|_| PgfError::DeserializeError { offset: seq_pos, message: "Symbols length cannot be negative".to_string() })?,
-            Err(PgfError::DeserializeError { message, .. }) if message.contains("failed to fill whole buffer") || message.contains("Parsing boundary reached") => {
-                debug_println!("DEBUG: Reached EOF reading syms_len for sequence {} - breaking from loop", i);
-                break;
-            }
-            Err(e) => return Err(e),
-        };
-        debug_println!("DEBUG: Sequence {} at pos {}, syms_len: {}", i, seq_pos, syms_len);
-        
-        
-        // Peek at next bytes for debugging
-        let next_bytes = cursor
-            .get_ref()
-            .get(usize::try_from(cursor.position()).unwrap_or(0)..(usize::try_from(cursor.position()).unwrap_or(0) + 10).min(cursor.get_ref().len()))
-            .unwrap_or(&[]);
-        debug_println!("DEBUG: Next bytes after syms_len: {:?}", next_bytes);
-        
-        let mut symbols = Vec::with_capacity(syms_len);
-        
-        for j in 0..syms_len {
-            let sym_pos = cursor.position();
-            let next_byte = cursor.get_ref().get(usize::try_from(cursor.position()).unwrap_or(0)).copied();
-            debug_println!("DEBUG: About to read symbol {} at pos {}, next byte: {:?}", j, sym_pos, next_byte);
-            
-            // Check if we're getting too close to the expected function data (around pos 400+)
-            if sym_pos > 380 {
-                debug_println!("DEBUG: WARNING: Symbol parsing at pos {} is approaching function data region, might indicate alignment issue", sym_pos);
-            }
-            
-            // Read symbol normally - the manual lindef fix was specific to Letters.pgf
-            match read_symbol(cursor, is_pgf_2_1) {
-                Ok(symbol) => {
-                    debug_println!("DEBUG: Symbol {} in sequence {} at pos {}: {:?}", j, i, sym_pos, symbol);
-                    
-                    // Check if this symbol consumed an unusual amount of data
-                    let end_pos = cursor.position();
-                    let consumed = end_pos - sym_pos;
-                    if consumed > 100 {
-                        debug_println!("DEBUG: WARNING: Symbol {} consumed {} bytes ({}->{}), might indicate parsing error", j, consumed, sym_pos, end_pos);
-                        debug_println!("DEBUG: Breaking sequence parsing to prevent consuming function data");
-                        break;
-                    }
-                    
-                    symbols.push(symbol);
-                }
-                Err(PgfError::DeserializeError { message, .. }) if message.contains("structure boundary") => {
-                    debug_println!("DEBUG: Hit structure boundary at symbol {} in sequence {} - stopping", j, i);
-                    break;
-                }
-                Err(PgfError::DeserializeError { message, .. }) if message.contains("failed to fill whole buffer") || message.contains("Parsing boundary reached") => {
-                    debug_println!("DEBUG: Hit EOF at symbol {} in sequence {} - stopping", j, i);
-                    break;
-                }
-                Err(e) => return Err(e),
-            }
-        }
-        sequences.push(symbols);
+fn write_concretes(buf: &mut Vec<u8>, concretes: &HashMap<Language, Concrete>) -> Result<(), PgfError> {
+    let mut entries: Vec<_> = concretes.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| (a.0).0.cmp(&(b.0).0));
+
+    write_len(buf, entries.len())?;
+    for (lang, cnc) in entries {
+        write_string(buf, &lang.0)?;
+        write_concrete(buf, cnc)?;
     }
-    debug_println!("DEBUG: Read {} sequences at pos {}", sequences.len(), cursor.position());
-    
-    // Read concrete functions normally
-    let cncfuns = match read_list(cursor, |c| {
-        let pos = c.position();
-        debug_println!("DEBUG: Reading cncfun at pos {}", pos);
-        let result = read_cncfun(c, is_pgf_2_1);
-        match &result {
-            Ok(fun) => debug_println!("DEBUG: Successfully read cncfun '{}' with {} lins", fun.name.0, fun.lins.len()),
-            Err(e) => debug_println!("DEBUG: Failed to read cncfun at pos {}: {:?}", pos, e),
-        }
-        result
-    }) {
-        Ok(funs) => funs,
-        Err(e) => {
-            debug_println!("DEBUG: Failed to read cncfuns list, using empty list: {:?}", e);
-            Vec::new() // Use empty list instead of failing
-        }
-    };
-    debug_println!("DEBUG: Read {} cncfuns at pos {}", cncfuns.len(), cursor.position());
-    
-    // Initialize empty CCat map (like C code line 1186-1187)
-    let mut ccat_map: std::collections::HashMap<i32, CCat> = std::collections::HashMap::new();
-    
-    // Read lindefs (following C code sequence)
-    let lindefs = match read_lindefs(cursor, &mut ccat_map) {
-        Ok(ld) => {
-            debug_println!("DEBUG: Successfully read {} lindefs at pos {}", ld.len(), cursor.position());
-            ld
+    Ok(())
+}
+
+fn write_concrete(buf: &mut Vec<u8>, cnc: &Concrete) -> Result<(), PgfError> {
+    write_flags(buf, &cnc.cflags)?;
+    write_list(buf, &cnc.printnames, |buf, pn| {
+        write_string(buf, &pn.name)?;
+        write_text(buf, &pn.printname)
+    })?;
+    write_list(buf, &cnc.sequences, |buf, seq| write_list(buf, seq, write_symbol))?;
+    write_list(buf, &cnc.cncfuns, |buf, fun| {
+        write_string(buf, &fun.name)?;
+        write_list(buf, &fun.lins, |buf, seqid| write_int(buf, *seqid))
+    })?;
+    write_list(buf, &cnc.lindefs, |buf, lindef| {
+        write_int(buf, lindef.cat)?;
+        write_list(buf, &lindef.funs, |buf, funid| write_int(buf, *funid))
+    })?;
+    write_list(buf, &cnc.linrefs, |buf, linref| {
+        write_int(buf, linref.cat)?;
+        write_list(buf, &linref.funs, |buf, funid| write_int(buf, *funid))
+    })?;
+
+    // Categories that only exist because of a lindef/linref have no
+    // productions and are not part of the production sets.
+    let mut production_sets: Vec<_> = cnc.productions.iter().filter(|(_, prods)| !prods.is_empty()).collect();
+    production_sets.sort_by_key(|(fid, _)| **fid);
+    write_len(buf, production_sets.len())?;
+    for (fid, prods) in production_sets {
+        write_int(buf, *fid)?;
+        write_list(buf, prods, write_production)?;
+    }
+
+    write_len(buf, cnc.cnccats.len())?;
+    for (name, cat) in sorted_by_cid(&cnc.cnccats) {
+        write_string(buf, name)?;
+        write_int(buf, cat.start)?;
+        write_int(buf, cat.end)?;
+        write_list(buf, &cat.labels, |buf, label| write_text(buf, label))?;
+    }
+    write_int(buf, cnc.total_cats)
+}
+
+fn write_production(buf: &mut Vec<u8>, prod: &Production) -> Result<(), PgfError> {
+    match prod {
+        Production::Apply { fid, args } => {
+            buf.write_u8(0)?;
+            write_int(buf, *fid)?;
+            write_list(buf, args, |buf, arg| {
+                write_list(buf, &arg.hypos, |buf, hypo| write_int(buf, *hypo))?;
+                write_int(buf, arg.fid)
+            })
         }
-        Err(e) => {
-            debug_println!("DEBUG: Failed to read lindefs: {:?}, using empty list", e);
-            Vec::new()
+        Production::Coerce { arg } => {
+            buf.write_u8(1)?;
+            write_int(buf, *arg)
         }
-    };
-    
-    // Read linrefs (following C code sequence) 
-    let lin_refs = match read_linrefs(cursor, &mut ccat_map) {
-        Ok(lr) => {
-            debug_println!("DEBUG: Successfully read {} linrefs at pos {}", lr.len(), cursor.position());
-            lr
+        Production::Const { cid, expr, tokens } => {
+            buf.write_u8(2)?;
+            write_string(buf, cid)?;
+            write_expr(buf, expr)?;
+            write_list(buf, tokens, |buf, token| write_text(buf, token))
         }
-        Err(e) => {
-            debug_println!("DEBUG: Failed to read linrefs: {:?}, using empty list", e);
-            Vec::new()
+    }
+}
+
+fn write_symbol(buf: &mut Vec<u8>, sym: &Symbol) -> Result<(), PgfError> {
+    match sym {
+        Symbol::SymCat(d, r) => {
+            buf.write_u8(0)?;
+            write_int(buf, *d)?;
+            write_int(buf, *r)
         }
-    };
-    
-    // Read CCats productions (following C code sequence)
-    let ccats = match read_ccats_productions(cursor, &mut ccat_map) {
-        Ok(_) => {
-            debug_println!("DEBUG: Successfully read CCats productions at pos {}", cursor.position());
-            ccat_map.values().cloned().collect()
+        Symbol::SymLit(d, r) => {
+            buf.write_u8(1)?;
+            write_int(buf, *d)?;
+            write_int(buf, *r)
         }
-        Err(e) => {
-            debug_println!("DEBUG: Failed to read CCats productions: {:?}, using empty list", e);
-            Vec::new()
+        Symbol::SymVar(n, l) => {
+            buf.write_u8(2)?;
+            write_int(buf, *n)?;
+            write_int(buf, *l)
         }
-    };
-    
-    // Read categories sequentially without hardcoded positions
-    let current_pos = cursor.position();
-    debug_println!("DEBUG: Reading categories at current pos: {}", current_pos);
-    
-    // Read categories using standard list parsing (following C implementation)
-    let cnccats = match read_list(cursor, |c| read_cnccat(c, is_pgf_2_1)) {
-        Ok(category_names) => {
-            debug_println!("DEBUG: Successfully read {} categories at pos {}", category_names.len(), cursor.position());
-            category_names.into_iter().map(|c| (c.name.clone(), c)).collect()
+        Symbol::SymKS(token) => {
+            buf.write_u8(3)?;
+            write_text(buf, token)
         }
-        Err(e) => {
-            debug_println!("DEBUG: Failed to read categories list: {:?}", e);
-            HashMap::new()
+        Symbol::SymKP(tokens, alts) => {
+            buf.write_u8(4)?;
+            write_list(buf, tokens, write_symbol)?;
+            write_list(buf, alts, |buf, alt| {
+                write_list(buf, &alt.tokens, write_symbol)?;
+                write_list(buf, &alt.prefixes, |buf, prefix| write_text(buf, prefix))
+            })
         }
-    };
-    
-    let total_cats = match read_int(cursor) {
-        Ok(t) => t,
-        Err(PgfError::DeserializeError { message, .. }) if message.contains("failed to fill whole buffer") || message.contains("Parsing boundary reached") => {
-            debug_println!("DEBUG: Reached EOF reading total_cats - using 0");
-            0
+        Symbol::SymBind => Ok(buf.write_u8(5)?),
+        Symbol::SymSoftBind => Ok(buf.write_u8(6)?),
+        Symbol::SymNE => Ok(buf.write_u8(7)?),
+        Symbol::SymSoftSpace => Ok(buf.write_u8(8)?),
+        Symbol::SymCapital => Ok(buf.write_u8(9)?),
+        Symbol::SymAllCapital => Ok(buf.write_u8(10)?),
+    }
+}
+
+/// Convert a PGF structure to JSON string representation.
+///
+/// The output has the shape of `gf --output-format=json`, pretty-printed
+/// with four spaces of indentation.
+///
+/// # Errors
+/// Returns `PgfError::SerializeError` if JSON serialization fails.
+pub fn pgf_to_json(pgf: &Pgf) -> Result<String, PgfError> {
+    pgf_to_json_with(pgf, &JsonOptions::default())
+}
+
+/// Options controlling what [`pgf_to_json_with`] and [`write_pgf_json`]
+/// export. The default is the `gf --output-format=json` shape, pretty-printed.
+#[derive(Debug, Clone, Default)]
+pub struct JsonOptions {
+    /// Export the whole abstract syntax: full function types with bindings
+    /// and dependent arguments, probabilities, arity, the constructor flag,
+    /// `def` equations, category contexts and abstract flags.
+    pub full_abstract: bool,
+    /// Export the parts of each concrete syntax that GF leaves out:
+    /// printnames, lindefs, linrefs, the concrete categories in use and the
+    /// field labels of each category.
+    pub full_concrete: bool,
+    /// Layout of the JSON text.
+    pub format: JsonFormat,
+}
+
+/// Layout of the JSON text written by [`write_pgf_json`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonFormat {
+    /// Indented with four spaces.
+    #[default]
+    Pretty,
+    /// No whitespace, as written by `gf --output-format=json`.
+    Compact,
+}
+
+/// Convert a PGF structure to JSON with the given options.
+///
+/// The extra sections enabled by `options` are added next to the GF ones, so
+/// consumers of the GF shape can still read the output.
+///
+/// The output is canonical: equal grammars give identical bytes. Languages,
+/// flags, abstract functions and categories, concrete categories and
+/// printnames are sorted by name; productions, lindefs and linrefs by fid.
+/// Concrete functions and sequences keep their order, since productions and
+/// functions refer to them by index.
+///
+/// # Errors
+/// Returns `PgfError::SerializeError` if JSON serialization fails.
+pub fn pgf_to_json_with(pgf: &Pgf, options: &JsonOptions) -> Result<String, PgfError> {
+    let mut out = Vec::new();
+    write_pgf_json(pgf, &mut out, options)?;
+    String::from_utf8(out).map_err(|e| PgfError::SerializeError(e.to_string()))
+}
+
+/// Write a PGF structure as JSON to `writer`, producing the same text as
+/// [`pgf_to_json_with`] without building it in memory first.
+///
+/// The writer receives many small writes; wrap files and sockets in a
+/// [`std::io::BufWriter`].
+///
+/// # Errors
+/// Returns `PgfError::Io` if writing fails and `PgfError::SerializeError`
+/// if JSON serialization fails or some concrete syntax skipped by
+/// [`LoadOptions::languages`] has not been loaded.
+pub fn write_pgf_json<W: Write>(pgf: &Pgf, writer: W, options: &JsonOptions) -> Result<(), PgfError> {
+    pgf.check_fully_loaded()?;
+    let json = PgfJson { pgf, options };
+    let result = match options.format {
+        JsonFormat::Pretty => {
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+            json.serialize(&mut serde_json::Serializer::with_formatter(writer, formatter))
         }
-        Err(e) => return Err(e),
+        JsonFormat::Compact => json.serialize(&mut serde_json::Serializer::new(writer)),
     };
-    let productions = ccats.iter().map(|ccat| (ccat.id, ccat.productions.clone())).collect();
+    result.map_err(|e| if e.is_io() { PgfError::Io(e.into()) } else { PgfError::SerializeError(e.to_string()) })
+}
 
-    Ok(Concrete {
-        cflags,
-        productions,
-        cncfuns,
-        sequences,
-        cnccats,
-        printnames,
-        lindefs,
-        linrefs: lin_refs,
-        ccats,
-        total_cats,
-    })
+// The JSON export is written by the borrowing views below, which serialize
+// straight from the grammar without building `serde_json::Value`s.
+
+struct PgfJson<'a> {
+    pgf: &'a Pgf,
+    options: &'a JsonOptions,
 }
 
-fn read_printname(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<PrintName, PgfError> {
-    let name = read_string(cursor, is_pgf_2_1)?;
-    let printname = read_string(cursor, is_pgf_2_1)?.0;
-    Ok(PrintName { name, printname })
+impl Serialize for PgfJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("abstract", &AbstractJson { pgf: self.pgf, full: self.options.full_abstract })?;
+        map.serialize_entry("concretes", &ConcretesJson { pgf: self.pgf, full: self.options.full_concrete })?;
+        map.end()
+    }
 }
 
-fn read_lindef(cursor: &mut Cursor<&[u8]>) -> Result<LinDef, PgfError> {
-    let cat = read_int(cursor)?;
-    let funs = read_list(cursor, read_int)?;
-    Ok(LinDef { cat, funs })
+/// A `{"type": ..., "args": ...}` node, the shape shared by symbols,
+/// expressions, patterns and bindings.
+struct Tagged<A>(&'static str, A);
+
+impl<A: Serialize> Serialize for Tagged<A> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("type", self.0)?;
+        map.serialize_entry("args", &self.1)?;
+        map.end()
+    }
 }
 
-fn read_linref(cursor: &mut Cursor<&[u8]>) -> Result<LinRef, PgfError> {
-    let cat = read_int(cursor)?;
-    let funs = read_list(cursor, read_int)?;
-    Ok(LinRef { cat, funs })
+/// Arguments of a node without arguments: `[]`.
+const NO_ARGS: [u8; 0] = [];
+
+/// A slice serialized as an array of views.
+struct SeqOf<'a, T, V> {
+    items: &'a [T],
+    view: fn(&'a T) -> V,
 }
 
-fn read_ccat(cursor: &mut Cursor<&[u8]>) -> Result<CCat, PgfError> {
-    let id = read_int(cursor)?;
-    let productions = read_list(cursor, read_production)?;
-    Ok(CCat { id, productions })
+impl<T, V: Serialize> Serialize for SeqOf<'_, T, V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.items.iter().map(self.view))
+    }
 }
 
-fn read_production_set(cursor: &mut Cursor<&[u8]>) -> Result<ProductionSet, PgfError> {
-    let cat = read_int(cursor)?;
-    let prods = read_list(cursor, read_production)?;
-    Ok(ProductionSet { cat, prods })
+fn seq_of<'a, T, V>(items: &'a [T], view: fn(&'a T) -> V) -> SeqOf<'a, T, V> {
+    SeqOf { items, view }
 }
 
-struct ProductionSet {
-    cat: i32,
-    prods: Vec<Production>,
+struct AbstractJson<'a> {
+    pgf: &'a Pgf,
+    full: bool,
 }
 
-fn read_production(cursor: &mut Cursor<&[u8]>) -> Result<Production, PgfError> {
-    let offset = cursor.position();
-    let tag = cursor.read_u8()
-        .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read production tag: {e}") })?;
-    debug_println!("DEBUG: Reading production with tag {} at pos {}", tag, offset);
-    match tag {
-        0 => {
-            let fid = read_int(cursor)?;
-            let args = read_list(cursor, read_parg)?;
-            Ok(Production::Apply { fid, args })
-        }
-        1 => {
-            let arg = i32::from(cursor.read_i8()
-                .map_err(|e| PgfError::DeserializeError { offset, message: format!("Failed to read coerce arg: {e}") })?);
-            Ok(Production::Coerce { arg })
-        }
-        2 => {
-            // PConst: CId Expr [Token] 
-            let cid = read_string(cursor, true)?;
-            let expr = read_expr(cursor, 0, true)?;
-            let tokens = read_list(cursor, |c| read_string(c, true).map(|cid| cid.0))?;
-            debug_println!("DEBUG: Read PConst production: cid={:?}, tokens={:?}", cid, tokens);
-            Ok(Production::Const { cid, expr, tokens })
-        }
-        4 => {
-            debug_println!("DEBUG: Production tag 4 - attempting specialized parsing at pos {}", offset);
-            // Production tag 4 appears to have a different structure
-            // Based on the hex data: 04 05 46 6c 6f 61 74 fd ff ff ff
-            // This looks like: tag(4) + string("Float") + some binary data
-            let cid = read_string(cursor, true)?;
-            debug_println!("DEBUG: Read tag 4 string: {:?}", cid);
-            
-            // Look at the next few bytes to understand the pattern
-            let current_pos = cursor.position();
-            let mut debug_bytes = Vec::new();
-            for i in 0..16 {
-                match cursor.read_u8() {
-                    Ok(b) => debug_bytes.push(b),
-                    Err(_) => break,
-                }
-            }
-            cursor.set_position(current_pos);
-            debug_println!("DEBUG: Next 16 bytes after tag 4 string: {:?}", debug_bytes);
-            
-            // Based on the pattern fd ff ff ff 7f fd ff ff ff 7f, this looks like
-            // two 32-bit signed integers: -3 (0xFFFFFFFD) followed by something
-            // Let's try to consume 8 bytes (2 x 4-byte integers)
-            if let (Ok(val1), Ok(val2)) = (cursor.read_i32::<byteorder::LittleEndian>(), cursor.read_i32::<byteorder::LittleEndian>()) {
-                debug_println!("DEBUG: Tag 4 consumed two ints: {} and {} at pos {}", val1, val2, current_pos);
-                let expr = Expr::Meta(val1); // Use first value as meta
-                let tokens = Vec::new();
-                Ok(Production::Const { cid, expr, tokens })
-            } else {
-                // If that fails, try consuming 4 bytes
-                cursor.set_position(current_pos);
-                if let Ok(val) = cursor.read_i32::<byteorder::LittleEndian>() {
-                    debug_println!("DEBUG: Tag 4 consumed one int: {} at pos {}", val, current_pos);
-                    let expr = Expr::Meta(val);
-                    let tokens = Vec::new();
-                    Ok(Production::Const { cid, expr, tokens })
-                } else {
-                    debug_println!("DEBUG: Tag 4 int reading failed, skipping 4 bytes");
-                    cursor.set_position(current_pos + 4); // Skip 4 bytes
-                    let expr = Expr::Fun(CId(format!("tag_4_production_{}", cid.0)));
-                    let tokens = Vec::new();
-                    Ok(Production::Const { cid, expr, tokens })
-                }
-            }
+impl Serialize for AbstractJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let abs = &self.pgf.r#abstract;
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("name", &self.pgf.absname.0)?;
+        map.serialize_entry("startcat", &self.pgf.startcat.0)?;
+        map.serialize_entry("funs", &FunsJson { abs, full: self.full })?;
+        if self.full {
+            map.serialize_entry("flags", &FlagsJson(&abs.aflags))?;
+            map.serialize_entry("cats", &CatsJson(abs))?;
         }
-        _ => {
-            debug_println!("DEBUG: Unknown production tag {} at pos {} - treating as PConst fallback", tag, offset);
-            // Fallback: treat unknown tags as PConst and try to parse
-            let cid = read_string(cursor, true)?;
-            let expr = read_expr(cursor, 0, true)?;
-            let tokens = read_list(cursor, |c| read_string(c, true).map(|cid| cid.0))?;
-            debug_println!("DEBUG: Fallback PConst production: tag={}, cid={:?}, tokens={:?}", tag, cid, tokens);
-            Ok(Production::Const { cid, expr, tokens })
+        map.end()
+    }
+}
+
+struct FunsJson<'a> {
+    abs: &'a Abstract,
+    full: bool,
+}
+
+impl Serialize for FunsJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(sorted_by_cid(&self.abs.funs).into_iter()
+            .map(|(name, fun)| (&name.0, FunJson { fun, full: self.full })))
+    }
+}
+
+struct FunJson<'a> {
+    fun: &'a Function,
+    full: bool,
+}
+
+impl Serialize for FunJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let fun = self.fun;
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("args", &seq_of(&fun.ty.hypos, |hypo| &hypo.ty.category.0))?;
+        map.serialize_entry("cat", &fun.ty.category.0)?;
+        if self.full {
+            map.serialize_entry("type", &TypeJson(&fun.ty))?;
+            map.serialize_entry("arity", &fun.arity)?;
+            map.serialize_entry("constructor", &fun.is_constructor)?;
+            map.serialize_entry("prob", &fun.prob)?;
+            map.serialize_entry("equations", &fun.equations.as_ref().map(|eqs| seq_of(eqs, EquationJson)))?;
         }
+        map.end()
     }
 }
 
-fn read_parg(cursor: &mut Cursor<&[u8]>) -> Result<PArg, PgfError> {
-    let hypos = read_list(cursor, read_int)?;
-    let fid = read_int(cursor)?;
-    Ok(PArg { hypos, fid })
+struct CatsJson<'a>(&'a Abstract);
+
+impl Serialize for CatsJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(sorted_by_cid(&self.0.cats).into_iter().map(|(name, cat)| (&name.0, CatJson(cat))))
+    }
 }
 
-fn read_cncfun(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<CncFun, PgfError> {
-    let name = read_string(cursor, is_pgf_2_1)?;
-    let lins = read_list(cursor, read_int)?;
-    Ok(CncFun { name, lins })
+struct CatJson<'a>(&'a Category);
+
+impl Serialize for CatJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let cat = self.0;
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("hypos", &seq_of(&cat.hypos, HypoJson))?;
+        map.serialize_entry("funs", &seq_of(&cat.funs, |(prob, fun)| CatFunJson { fun, prob: *prob }))?;
+        map.serialize_entry("prob", &cat.prob)?;
+        map.end()
+    }
 }
 
-fn read_cnccat(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<CncCat, PgfError> {
-    let name = read_string(cursor, is_pgf_2_1)?;
-    let start = read_int(cursor)?;
-    let end = read_int(cursor)?;
-    
-    // Try to read labels, but handle the case where there are none
-    let labels = match read_list(cursor, |c| Ok(read_string(c, is_pgf_2_1)?.0)) {
-        Ok(l) => l,
-        Err(PgfError::DeserializeError { message, .. }) if message.contains("Parsing boundary reached") || message.contains("large unsigned value") => {
-            debug_println!("DEBUG: No labels for category '{}' - using empty list", name.0);
-            Vec::new()
+struct CatFunJson<'a> {
+    fun: &'a CId,
+    prob: f64,
+}
+
+impl Serialize for CatFunJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("fun", &self.fun.0)?;
+        map.serialize_entry("prob", &self.prob)?;
+        map.end()
+    }
+}
+
+struct FlagsJson<'a>(&'a HashMap<CId, Literal>);
+
+impl Serialize for FlagsJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(sorted_by_cid(self.0).into_iter().map(|(name, value)| (&name.0, LiteralJson(value))))
+    }
+}
+
+/// A flag value: the bare string or number.
+struct LiteralJson<'a>(&'a Literal);
+
+impl Serialize for LiteralJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Literal::Str(s) => serializer.serialize_str(s),
+            Literal::Int(n) => serializer.serialize_i32(*n),
+            Literal::Flt(d) => serializer.serialize_f64(*d),
         }
-        Err(e) => return Err(e),
-    };
-    
-    Ok(CncCat { name, start, end, labels })
+    }
 }
 
-#[allow(clippy::too_many_lines)]
-fn read_symbol(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<Symbol, PgfError> {
-    let start_pos = cursor.position();
-    let tag = cursor.read_u8()
-        .map_err(|e| PgfError::DeserializeError { offset: start_pos, message: format!("Failed to read symbol tag: {e}") })?;
-    debug_println!("DEBUG: Reading symbol at pos {}, tag: {}", start_pos, tag);
+/// A literal inside an expression or pattern, which keeps its kind.
+struct TaggedLiteralJson<'a>(&'a Literal);
 
-    // Peek at the next few bytes for debugging
-    let next_bytes = cursor
-        .get_ref()
-        .get(usize::try_from(cursor.position()).unwrap_or(0)..(usize::try_from(cursor.position()).unwrap_or(0) + 10).min(cursor.get_ref().len()))
-        .unwrap_or(&[]);
-    debug_println!("DEBUG: Next bytes after tag {}: {:?}", tag, next_bytes);
+impl Serialize for TaggedLiteralJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Literal::Str(s) => Tagged("Str", (s,)).serialize(serializer),
+            Literal::Int(n) => Tagged("Int", (n,)).serialize(serializer),
+            Literal::Flt(d) => Tagged("Flt", (d,)).serialize(serializer),
+        }
+    }
+}
 
-    match tag {
-        0 => {
-            let d = read_int(cursor)?;
-            let r = read_int(cursor)?;
-            debug_println!("DEBUG: PGF_SYMBOL_CAT: d={}, r={} at pos {}", d, r, start_pos);
-            Ok(Symbol::SymCat(d, r))
+struct TypeJson<'a>(&'a Type);
+
+impl Serialize for TypeJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ty = self.0;
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("hypos", &seq_of(&ty.hypos, HypoJson))?;
+        map.serialize_entry("cat", &ty.category.0)?;
+        map.serialize_entry("exprs", &seq_of(&ty.exprs, ExprJson))?;
+        map.end()
+    }
+}
+
+struct HypoJson<'a>(&'a Hypo);
+
+impl Serialize for HypoJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("bind", &BindingJson(&self.0.binding))?;
+        map.serialize_entry("type", &TypeJson(&self.0.ty))?;
+        map.end()
+    }
+}
+
+struct BindingJson<'a>(&'a Binding);
+
+impl Serialize for BindingJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Binding::Explicit(var) => Tagged("Explicit", (var,)).serialize(serializer),
+            Binding::Implicit(var) => Tagged("Implicit", (var,)).serialize(serializer),
         }
-        1 => {
-            let d = read_int(cursor)?;
-            let r = read_int(cursor)?;
-            debug_println!("DEBUG: PGF_SYMBOL_LIT: d={}, r={} at pos {}", d, r, start_pos);
-            Ok(Symbol::SymLit(d, r))
+    }
+}
+
+struct ExprJson<'a>(&'a Expr);
+
+impl Serialize for ExprJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Expr::Abs(binding, var, body) => Tagged("Abs", (BindingJson(binding), &var.0, ExprJson(body))).serialize(serializer),
+            Expr::App(fun, arg) => Tagged("App", (ExprJson(fun), ExprJson(arg))).serialize(serializer),
+            Expr::Fun(name) => Tagged("Fun", (&name.0,)).serialize(serializer),
+            Expr::Str(s) => Tagged("Str", (s,)).serialize(serializer),
+            Expr::Int(n) => Tagged("Int", (n,)).serialize(serializer),
+            Expr::Float(f) => Tagged("Float", (f64::from(*f),)).serialize(serializer),
+            Expr::Double(d) => Tagged("Double", (d,)).serialize(serializer),
+            Expr::Meta(n) => Tagged("Meta", (n,)).serialize(serializer),
+            Expr::Typed(expr, ty) => Tagged("Typed", (ExprJson(expr), TypeJson(ty))).serialize(serializer),
+            Expr::ImplArg(expr) => Tagged("ImplArg", (ExprJson(expr),)).serialize(serializer),
+            Expr::Lit(lit) => Tagged("Lit", (TaggedLiteralJson(lit),)).serialize(serializer),
+            Expr::Var(n) => Tagged("Var", (n,)).serialize(serializer),
         }
-        2 => {
-            let n = read_int(cursor)?;
-            let l = read_int(cursor)?;  // Read second parameter as per Haskell code
-            debug_println!("DEBUG: PGF_SYMBOL_VAR: n={}, l={} at pos {}", n, l, start_pos);
-            Ok(Symbol::SymVar(n, l))
+    }
+}
+
+struct EquationJson<'a>(&'a Equation);
+
+impl Serialize for EquationJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("patterns", &seq_of(&self.0.patterns, PatternJson))?;
+        map.serialize_entry("result", &ExprJson(&self.0.result))?;
+        map.end()
+    }
+}
+
+struct PatternJson<'a>(&'a Pattern);
+
+impl Serialize for PatternJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Pattern::PApp(fun, args) => Tagged("PApp", (&fun.0, seq_of(args, PatternJson))).serialize(serializer),
+            Pattern::PVar(var) => Tagged("PVar", (&var.0,)).serialize(serializer),
+            Pattern::PBind(var, pattern) => Tagged("PBind", (&var.0, PatternJson(pattern))).serialize(serializer),
+            Pattern::PWildcard => Tagged("PWildcard", NO_ARGS).serialize(serializer),
+            Pattern::PLit(lit) => Tagged("PLit", (TaggedLiteralJson(lit),)).serialize(serializer),
+            Pattern::PImplicit(args) => Tagged("PImplicit", (seq_of(args, PatternJson),)).serialize(serializer),
+            Pattern::PInaccessible(expr) => Tagged("PInaccessible", (ExprJson(expr),)).serialize(serializer),
         }
-        3 => {
-            // Tag 3: SymKS in PGF 2.1 - try length-prefixed string first, then fallback
-            let len_pos = cursor.position();
-            let token = match read_int(cursor) {
-                Ok(len) if len >= 0 && usize::try_from(len).unwrap_or(0) <= 100 => {
-                    match read_string_with_length(cursor, usize::try_from(len).unwrap_or(0), is_pgf_2_1) {
-                        Ok(s) if s.chars().all(|c| !c.is_ascii_control() || c.is_whitespace()) => {
-                            debug_println!("DEBUG: PGF_SYMBOL_KS: length-prefixed token='{}' at pos {}", s, start_pos);
-                            s
-                        }
-                        _ => {
-                            debug_println!("DEBUG: Failed length-prefixed read at pos {}, falling back", len_pos);
-                            cursor.set_position(len_pos);
-                            read_string_fallback(cursor, len_pos, is_pgf_2_1, 3)?
-                        }
-                    }
-                }
-                _ => {
-                    debug_println!("DEBUG: Invalid or missing length at pos {}, falling back", len_pos);
-                    cursor.set_position(len_pos);
-                    read_string_fallback(cursor, len_pos, is_pgf_2_1, 3)?
-                }
-            };
-            Ok(Symbol::SymKS(token))
+    }
+}
+
+struct ConcretesJson<'a> {
+    pgf: &'a Pgf,
+    full: bool,
+}
+
+impl Serialize for ConcretesJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut concretes: Vec<_> = self.pgf.concretes.iter().collect();
+        concretes.sort_by(|(a, _), (b, _)| a.0.0.cmp(&b.0.0));
+        serializer.collect_map(concretes.into_iter().map(|(lang, cnc)| (&lang.0.0, ConcreteJson { cnc, full: self.full })))
+    }
+}
+
+struct ConcreteJson<'a> {
+    cnc: &'a Concrete,
+    full: bool,
+}
+
+impl Serialize for ConcreteJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let cnc = self.cnc;
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("flags", &FlagsJson(&cnc.cflags))?;
+        map.serialize_entry("productions", &ProductionsJson(cnc))?;
+        map.serialize_entry("functions", &seq_of(&cnc.cncfuns, CncFunJson))?;
+        map.serialize_entry("sequences", &seq_of(&cnc.sequences, |seq| seq_of(seq, SymbolJson)))?;
+        map.serialize_entry("categories", &CategoriesJson { cnc, full: self.full })?;
+        map.serialize_entry("totalfids", &cnc.total_cats)?;
+        if self.full {
+            let printnames: std::collections::BTreeMap<&str, &str> = cnc.printnames.iter()
+                .map(|pn| (pn.name.0.as_str(), pn.printname.as_str()))
+                .collect();
+            map.serialize_entry("printnames", &printnames)?;
+            map.serialize_entry("lindefs", &DefsJson(cnc.lindefs.iter().map(|def| (def.cat, &def.funs[..])).collect()))?;
+            map.serialize_entry("linrefs", &DefsJson(cnc.linrefs.iter().map(|def| (def.cat, &def.funs[..])).collect()))?;
+            map.serialize_entry("ccats", &seq_of(&cnc.ccats, |ccat| ccat.id))?;
         }
-        4 => {
-            debug_println!("DEBUG: Starting SymKP parsing at pos {}, next 20 bytes: {:?}", start_pos, 
-                cursor.get_ref().get(usize::try_from(cursor.position()).unwrap_or(0)..usize::try_from(cursor.position()).unwrap_or(0) + 20).unwrap_or(&[]));
-            
-            let tokens = read_list(cursor, |c| {
-                let pos = c.position();
-                debug_println!("DEBUG: Reading SymKP token symbol at pos {}", pos);
-                read_symbol(c, is_pgf_2_1)
-            })?;
-            debug_println!("DEBUG: SymKP tokens: {:?} at pos {}", tokens, cursor.position());
-            
-            let alts = read_list(cursor, |c| {
-                let pos = c.position();
-                debug_println!("DEBUG: Reading Alt at pos {}", pos);
-                read_alt(c, is_pgf_2_1)
-            })?;
-            debug_println!("DEBUG: PGF_SYMBOL_KP: {} tokens, {} alts at pos {}", tokens.len(), alts.len(), cursor.position());
-            Ok(Symbol::SymKP(tokens, alts))
+        map.end()
+    }
+}
+
+/// Productions by fid; categories that only have a lindef or linref are
+/// left out.
+struct ProductionsJson<'a>(&'a Concrete);
+
+impl Serialize for ProductionsJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut productions: Vec<_> = self.0.productions.iter().filter(|(_, prods)| !prods.is_empty()).collect();
+        productions.sort_by_key(|(fid, _)| **fid);
+        serializer.collect_map(productions.into_iter().map(|(fid, prods)| (fid, seq_of(prods, ProductionJson))))
+    }
+}
+
+/// Lindefs or linrefs by fid.
+struct DefsJson<'a>(Vec<(i32, &'a [i32])>);
+
+impl Serialize for DefsJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut defs = self.0.clone();
+        defs.sort_by_key(|(cat, _)| *cat);
+        serializer.collect_map(defs)
+    }
+}
+
+struct CategoriesJson<'a> {
+    cnc: &'a Concrete,
+    full: bool,
+}
+
+impl Serialize for CategoriesJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut categories: std::collections::BTreeMap<&str, (i32, i32, Option<&[String]>)> = self.cnc.cnccats.iter()
+            .map(|(name, cat)| (name.0.as_str(), (cat.start, cat.end, Some(&cat.labels[..]))))
+            .collect();
+        // The literal categories always exist, even if the file does not list them.
+        for (name, fid) in [("Float", -3), ("Int", -2), ("String", -1)] {
+            categories.entry(name).or_insert((fid, fid, None));
         }
-        5 => {
-            debug_println!("DEBUG: PGF_SYMBOL_BIND at pos {}", start_pos);
-            Ok(Symbol::SymBind)
+        let full = self.full;
+        serializer.collect_map(categories.into_iter().map(|(name, (start, end, labels))| {
+            (name, CategoryJson { start, end, labels: labels.filter(|_| full) })
+        }))
+    }
+}
+
+struct CategoryJson<'a> {
+    start: i32,
+    end: i32,
+    labels: Option<&'a [String]>,
+}
+
+impl Serialize for CategoryJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("start", &self.start)?;
+        map.serialize_entry("end", &self.end)?;
+        if let Some(labels) = self.labels {
+            map.serialize_entry("labels", labels)?;
         }
-        6 => {
-            debug_println!("DEBUG: PGF_SYMBOL_SOFT_BIND at pos {}", start_pos);
-            Ok(Symbol::SymSoftBind)
+        map.end()
+    }
+}
+
+struct CncFunJson<'a>(&'a CncFun);
+
+impl Serialize for CncFunJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("name", &quote_cid(&self.0.name))?;
+        map.serialize_entry("lins", &self.0.lins)?;
+        map.end()
+    }
+}
+
+/// Render a name the way GF does: identifiers as they are, anything else
+/// (such as `lindef Cat`) in single quotes with `'` and `\` escaped.
+fn quote_cid(name: &CId) -> String {
+    let mut chars = name.0.chars();
+    let is_ident = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '\'');
+    if is_ident {
+        return name.0.to_string();
+    }
+    let mut quoted = String::with_capacity(name.0.len() + 2);
+    quoted.push('\'');
+    for c in name.0.chars() {
+        if c == '\'' || c == '\\' {
+            quoted.push('\\');
         }
-        7 => {
-            debug_println!("DEBUG: PGF_SYMBOL_NE at pos {}", start_pos);
-            Ok(Symbol::SymNE)
-        }
-        8 => {
-            debug_println!("DEBUG: PGF_SYMBOL_SOFT_SPACE at pos {}", start_pos);
-            Ok(Symbol::SymSoftSpace)
-        }
-        9 => {
-            debug_println!("DEBUG: PGF_SYMBOL_CAPITAL at pos {}", start_pos);
-            Ok(Symbol::SymCapital)
-        }
-        10 => {
-            debug_println!("DEBUG: PGF_SYMBOL_ALL_CAPITAL at pos {}", start_pos);
-            Ok(Symbol::SymAllCapital)
-        }
-        24 => {
-            debug_println!("DEBUG: Detected end marker byte 24 at pos {} - treating as structure boundary", start_pos);
-            Err(PgfError::DeserializeError {
-                offset: start_pos,
-                message: "Reached structure boundary marker".to_string(),
-            })
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// The inverse of [`quote_cid`].
+fn unquote_cid(name: &str) -> CId {
+    let Some(inner) = name.strip_prefix('\'').and_then(|n| n.strip_suffix('\'')) else {
+        return cid::mk_cid(name);
+    };
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        unquoted.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+    }
+    CId(unquoted.into())
+}
+
+struct ProductionJson<'a>(&'a Production);
+
+impl Serialize for ProductionJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        match self.0 {
+            Production::Apply { fid, args } => {
+                map.serialize_entry("type", "Apply")?;
+                map.serialize_entry("fid", fid)?;
+                map.serialize_entry("args", &seq_of(args, PArgJson))?;
+            }
+            Production::Coerce { arg } => {
+                map.serialize_entry("type", "Coerce")?;
+                map.serialize_entry("arg", arg)?;
+            }
+            Production::Const { cid, expr, tokens } => {
+                map.serialize_entry("type", "Const")?;
+                map.serialize_entry("cid", &cid.0)?;
+                map.serialize_entry("expr", &ExprJson(expr))?;
+                map.serialize_entry("tokens", tokens)?;
+            }
         }
-        _ => {
-            debug_println!("DEBUG: Invalid symbol tag {} at pos {}, attempting fallback as SymKS", tag, start_pos);
-            // Rewind to include the invalid tag as part of the string
-            cursor.set_position(start_pos);
-            let token = read_string_fallback(cursor, start_pos, is_pgf_2_1, tag)?;
-            debug_println!("DEBUG: Fallback SymKS: token='{}' at pos {}", token, start_pos);
-            Ok(Symbol::SymKS(token))
+        map.end()
+    }
+}
+
+struct PArgJson<'a>(&'a PArg);
+
+impl Serialize for PArgJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("type", "PArg")?;
+        map.serialize_entry("hypos", &self.0.hypos)?;
+        map.serialize_entry("fid", &self.0.fid)?;
+        map.end()
+    }
+}
+
+struct SymbolJson<'a>(&'a Symbol);
+
+impl Serialize for SymbolJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Symbol::SymCat(n, l) => Tagged("SymCat", (n, l)).serialize(serializer),
+            Symbol::SymLit(n, l) => Tagged("SymLit", (n, l)).serialize(serializer),
+            Symbol::SymVar(n, l) => Tagged("SymVar", (n, l)).serialize(serializer),
+            Symbol::SymKS(t) => Tagged("SymKS", (t,)).serialize(serializer),
+            Symbol::SymKP(tokens, alts) => Tagged("SymKP", (seq_of(tokens, SymbolJson), seq_of(alts, AltJson))).serialize(serializer),
+            Symbol::SymBind => Tagged("SymBind", NO_ARGS).serialize(serializer),
+            Symbol::SymSoftBind => Tagged("SymSoftBind", NO_ARGS).serialize(serializer),
+            Symbol::SymNE => Tagged("SymNE", NO_ARGS).serialize(serializer),
+            Symbol::SymSoftSpace => Tagged("SymSoftSpace", NO_ARGS).serialize(serializer),
+            Symbol::SymCapital => Tagged("SymCapital", NO_ARGS).serialize(serializer),
+            Symbol::SymAllCapital => Tagged("SymAllCapital", NO_ARGS).serialize(serializer),
         }
     }
 }
 
-fn read_alt(cursor: &mut Cursor<&[u8]>, is_pgf_2_1: bool) -> Result<Alt, PgfError> {
-    let start_pos = cursor.position();
-    debug_println!("DEBUG: read_alt starting at pos {}, next bytes: {:?}", start_pos, 
-        cursor.get_ref().get(usize::try_from(cursor.position()).unwrap_or(0)..usize::try_from(cursor.position()).unwrap_or(0) + 10).unwrap_or(&[]));
-    
-    // Read tokens as Symbols, not strings (following C implementation)
-    let tokens = read_list(cursor, |c| {
-        let pos = c.position();
-        debug_println!("DEBUG: Reading Alt token symbol at pos {}", pos);
-        read_symbol(c, is_pgf_2_1)
-    })?;
-    debug_println!("DEBUG: read_alt tokens: {:?} at pos {} (consumed {} bytes)", tokens, cursor.position(), cursor.position() - start_pos);
-    
-    // For Alt prefixes, expect only short strings (single characters or short words)
-    let prefixes = match read_list(cursor, |c| {
-        let pos = c.position();
-        
-        // Add boundary check - if we're getting too far from the start, stop
-        if pos > start_pos + 30 {
-            debug_println!("DEBUG: Alt prefix parsing exceeded boundary at pos {}", pos);
-            return Err(PgfError::DeserializeError {
-                offset: pos,
-                message: "Alt prefix parsing boundary exceeded".to_string()
-            });
+struct AltJson<'a>(&'a Alt);
+
+impl Serialize for AltJson<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Tagged("Alt", (seq_of(&self.0.tokens, SymbolJson), &self.0.prefixes)).serialize(serializer)
+    }
+}
+
+/// Encode an expression as JSON, in the `{"type": ..., "args": [...]}` shape
+/// used for symbols. [`expr_from_json`] reads it back.
+#[must_use]
+pub fn expr_to_json(expr: &Expr) -> JsonValue {
+    // Cannot fail: expressions contain no maps with non-string keys.
+    serde_json::to_value(ExprJson(expr)).unwrap_or(JsonValue::Null)
+}
+
+fn cat_skeleton(ty: &Type) -> (Vec<CId>, CId) {
+    (ty.hypos.iter().map(|h| h.ty.category.clone()).collect(), ty.category.clone())
+}
+
+/// Reconstruct a grammar from the JSON produced by [`pgf_to_json`] or
+/// `gf --output-format=json`.
+///
+/// The JSON format does not carry everything in a PGF file: probabilities
+/// are spread uniformly over the functions of each category and hypotheses
+/// are unnamed. Unless the JSON was written with
+/// [`JsonOptions::full_concrete`], field labels and printnames are empty and
+/// the lindef/linref tables are rebuilt from the `'lindef C'` functions,
+/// which apply to every concrete category of `C`.
+///
+/// # Errors
+///
+/// Returns [`PgfError::JsonError`] with the path of the offending value if
+/// the input is not valid JSON or does not have the expected shape, and
+/// [`PgfError::InvalidGrammar`] if the grammar it describes is inconsistent.
+pub fn pgf_from_json(json: &str) -> Result<Pgf, PgfError> {
+    let root: JsonValue = serde_json::from_str(json)
+        .map_err(|e| PgfError::JsonError { path: "$".to_string(), message: e.to_string() })?;
+    let abs = json_object(&root, "$")?.get("abstract").ok_or_else(|| json_missing("$", "abstract"))?;
+    let name = json_str(json_get(abs, "abstract", "name")?, "abstract.name")?;
+
+    let mut builder = PgfBuilder::new(name);
+    builder.start_cat(json_str(json_get(abs, "abstract", "startcat")?, "abstract.startcat")?);
+
+    // Literal categories are part of every compiled grammar.
+    let mut cats: Vec<String> = ["Float", "Int", "String"].iter().map(ToString::to_string).collect();
+    for (fun, ty) in json_object(json_get(abs, "abstract", "funs")?, "abstract.funs")? {
+        let path = format!("abstract.funs.{fun}");
+        let cat = json_str(json_get(ty, &path, "cat")?, &format!("{path}.cat"))?;
+        let args = json_array(json_get(ty, &path, "args")?, &format!("{path}.args"))?.iter()
+            .enumerate()
+            .map(|(i, arg)| json_str(arg, &format!("{path}.args[{i}]")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let hypos = args.iter()
+            .map(|arg| types::mk_hypo(Binding::Explicit("_".to_string()), types::mk_type(vec![], cid::mk_cid(arg), vec![])))
+            .collect();
+        cats.extend(args.iter().chain(std::iter::once(&cat)).map(|c| (*c).to_string()));
+        builder.fun(fun, types::mk_type(hypos, cid::mk_cid(cat), vec![]));
+    }
+
+    let concretes = root.get("concretes").map(|cncs| json_object(cncs, "concretes")).transpose()?;
+    let mut total_cats = Vec::new();
+    for (lang, cnc) in concretes.into_iter().flatten() {
+        let path = format!("concretes.{lang}");
+        let (concrete, total) = concrete_from_json(cnc, &path)?;
+        cats.extend(concrete.cnccats.iter().map(|cat| cat.name.0.to_string()));
+        builder.concrete(lang, concrete);
+        total_cats.push((lang, total));
+    }
+
+    cats.sort();
+    cats.dedup();
+    for cat in &cats {
+        builder.cat(cat, vec![]);
+    }
+
+    let mut pgf = builder.build()?;
+    for (lang, total) in total_cats {
+        if let Some(cnc) = pgf.concretes.get_mut(&Language(cid::mk_cid(lang))) {
+            cnc.total_cats = total;
         }
-        
-        let result = read_string(c, is_pgf_2_1);
-        match &result {
-            Ok(s) => {
-                debug_println!("DEBUG: read_alt prefix '{}' (len {}) at pos {}", s.0, s.0.len(), pos);
-                // Alt prefixes should be single characters or short words
-                if s.0.len() > 5 {
-                    debug_println!("DEBUG: ERROR: Alt prefix too long ({}), indicates parsing error", s.0.len());
-                    return Err(PgfError::DeserializeError {
-                        offset: pos,
-                        message: format!("Alt prefix too long ({} chars), parsing boundary reached", s.0.len())
-                    });
-                }
-            }
-            Err(e) => debug_println!("DEBUG: Failed to read prefix at pos {}: {:?}", pos, e),
+    }
+    Ok(pgf)
+}
+
+fn concrete_from_json(cnc: &JsonValue, path: &str) -> Result<(ConcreteBuilder, i32), PgfError> {
+    let mut builder = ConcreteBuilder::new();
+    if let Some(flags) = cnc.get("flags") {
+        for (name, value) in json_object(flags, &format!("{path}.flags"))? {
+            builder.flag(name, literal_from_json(value, &format!("{path}.flags.{name}"))?);
         }
-        result.map(|s| s.0)
-    }) {
-        Ok(p) => {
-            debug_println!("DEBUG: Alt prefixes parsed successfully: {:?}", p);
-            p
+    }
+
+    for (i, seq) in json_array(json_get(cnc, path, "sequences")?, &format!("{path}.sequences"))?.iter().enumerate() {
+        let seq_path = format!("{path}.sequences[{i}]");
+        builder.sequences.push(json_array(seq, &seq_path)?.iter()
+            .enumerate()
+            .map(|(j, sym)| symbol_from_json(sym, &format!("{seq_path}[{j}]")))
+            .collect::<Result<_, _>>()?);
+    }
+
+    for (i, fun) in json_array(json_get(cnc, path, "functions")?, &format!("{path}.functions"))?.iter().enumerate() {
+        let fun_path = format!("{path}.functions[{i}]");
+        let name = json_str(json_get(fun, &fun_path, "name")?, &format!("{fun_path}.name"))?;
+        let lins = json_array(json_get(fun, &fun_path, "lins")?, &format!("{fun_path}.lins"))?.iter()
+            .enumerate()
+            .map(|(j, lin)| json_i32(lin, &format!("{fun_path}.lins[{j}]")))
+            .collect::<Result<_, _>>()?;
+        builder.add_function(&unquote_cid(name).0, lins);
+    }
+
+    for (fid, prods) in json_object(json_get(cnc, path, "productions")?, &format!("{path}.productions"))? {
+        let prods_path = format!("{path}.productions.{fid}");
+        let cat = fid.parse::<i32>()
+            .map_err(|e| PgfError::JsonError { path: prods_path.clone(), message: format!("invalid category id: {e}") })?;
+        for (i, prod) in json_array(prods, &prods_path)?.iter().enumerate() {
+            builder.production(cat, production_from_json(prod, &format!("{prods_path}[{i}]"))?);
         }
-        Err(PgfError::DeserializeError { message, .. }) if message.contains("too long") || message.contains("boundary") => {
-            debug_println!("DEBUG: Alt prefix parsing stopped due to boundary detection");
-            // Reset cursor to a safe position
-            cursor.set_position(start_pos + 15); // Safe position after tokens
-            Vec::new()
+    }
+
+    for (name, range) in json_object(json_get(cnc, path, "categories")?, &format!("{path}.categories"))? {
+        let cat_path = format!("{path}.categories.{name}");
+        let start = json_i32(json_get(range, &cat_path, "start")?, &format!("{cat_path}.start"))?;
+        let end = json_i32(json_get(range, &cat_path, "end")?, &format!("{cat_path}.end"))?;
+        let labels = match range.get("labels") {
+            Some(labels) => json_array(labels, &format!("{cat_path}.labels"))?.iter()
+                .map(|label| json_str(label, &format!("{cat_path}.labels")).map(ToString::to_string))
+                .collect::<Result<_, _>>()?,
+            None => vec![],
+        };
+        builder.category(name, start, end, labels);
+    }
+
+    if let Some(printnames) = cnc.get("printnames") {
+        for (name, printname) in json_object(printnames, &format!("{path}.printnames"))? {
+            builder.printname(name, json_str(printname, &format!("{path}.printnames.{name}"))?);
         }
-        Err(e) => {
-            debug_println!("DEBUG: Failed to read prefixes, using empty list: {:?}", e);
-            Vec::new()
+    }
+
+    // Exported with `full_concrete`; otherwise rebuilt below.
+    if let (Some(lindefs), Some(linrefs)) = (cnc.get("lindefs"), cnc.get("linrefs")) {
+        builder.lindefs = defs_from_json(lindefs, &format!("{path}.lindefs"))?;
+        builder.linrefs = defs_from_json(linrefs, &format!("{path}.linrefs"))?;
+        let total = json_i32(json_get(cnc, path, "totalfids")?, &format!("{path}.totalfids"))?;
+        return Ok((builder, total));
+    }
+
+    // A lindef reads its category from a string literal; the linref of the
+    // same category writes it back through a category argument.
+    let helpers: Vec<(i32, String, bool)> = builder.cncfuns.iter().enumerate()
+        .filter_map(|(index, fun)| {
+            let cat = fun.name.0.strip_prefix("lindef ")?;
+            let is_lindef = fun.lins.iter()
+                .filter_map(|&lin| builder.sequences.get(usize::try_from(lin).ok()?))
+                .flatten()
+                .any(|sym| matches!(sym, Symbol::SymLit(..)));
+            Some((i32::try_from(index).ok()?, cat.to_string(), is_lindef))
+        })
+        .collect();
+    for (fun, cat, is_lindef) in helpers {
+        let Some(range) = builder.cnccats.iter().find(|c| c.name.0 == cat).map(|c| c.start..=c.end) else {
+            continue;
+        };
+        for fid in range {
+            if is_lindef {
+                builder.lindef(fid, fun);
+            } else {
+                builder.linref(fid, fun);
+            }
         }
+    }
+
+    let total = match cnc.get("totalfids") {
+        Some(total) => json_i32(total, &format!("{path}.totalfids"))?,
+        None => builder.cnccats.iter().map(|cat| cat.end + 1).max().unwrap_or(0),
     };
-    
-    debug_println!("DEBUG: read_alt prefixes: {:?}", prefixes);
-    Ok(Alt { tokens, prefixes })
+    Ok((builder, total))
 }
 
-fn read_list<T, F>(cursor: &mut Cursor<&[u8]>, f: F) -> Result<Vec<T>, PgfError>
-where
-    F: Fn(&mut Cursor<&[u8]>) -> Result<T, PgfError>,
-{
-    let offset = cursor.position();
-    
-    // Handle termination markers
-    let len = match read_int(cursor) {
-        Ok(l) => {
-            debug_println!("DEBUG: read_list at pos {} reading {} items", offset, l);
-            l
-        },
-        Err(PgfError::DeserializeError { message, .. }) if message.contains("Parsing boundary reached") || message.contains("failed to fill whole buffer") => {
-            // Only treat EOF as normal if we're near the end of the file AND the cursor position matches the file size
-            let file_size = cursor.get_ref().len() as u64;
-            if offset == file_size {
-                eprintln!("DEBUG: Parsing boundary/EOF at exact end of file (pos {offset}) - treating as end of structure");
-                return Ok(Vec::new());
-            }
-            eprintln!("DEBUG: Parsing boundary/EOF at pos {offset} (file size: {file_size}) - not at end, propagating error");
-            return Err(PgfError::DeserializeError { 
-                offset, 
-                message: format!("read_list at pos {offset} hit unexpected EOF - original error: {message}") 
-            });
+fn defs_from_json(defs: &JsonValue, path: &str) -> Result<BTreeMap<i32, Vec<i32>>, PgfError> {
+    json_object(defs, path)?.iter()
+        .map(|(cat, funs)| {
+            let def_path = format!("{path}.{cat}");
+            let cat = cat.parse::<i32>()
+                .map_err(|e| PgfError::JsonError { path: def_path.clone(), message: format!("invalid category id: {e}") })?;
+            let funs = json_array(funs, &def_path)?.iter()
+                .map(|fun| json_i32(fun, &def_path))
+                .collect::<Result<_, _>>()?;
+            Ok((cat, funs))
+        })
+        .collect()
+}
+
+fn production_from_json(prod: &JsonValue, path: &str) -> Result<Production, PgfError> {
+    match json_str(json_get(prod, path, "type")?, &format!("{path}.type"))? {
+        "Apply" => {
+            let fid = json_i32(json_get(prod, path, "fid")?, &format!("{path}.fid"))?;
+            let args = json_array(json_get(prod, path, "args")?, &format!("{path}.args"))?.iter()
+                .enumerate()
+                .map(|(i, arg)| {
+                    let arg_path = format!("{path}.args[{i}]");
+                    let hypos = json_array(json_get(arg, &arg_path, "hypos")?, &format!("{arg_path}.hypos"))?.iter()
+                        .map(|hypo| json_i32(hypo, &format!("{arg_path}.hypos")))
+                        .collect::<Result<_, _>>()?;
+                    Ok(PArg::new(hypos, json_i32(json_get(arg, &arg_path, "fid")?, &format!("{arg_path}.fid"))?))
+                })
+                .collect::<Result<_, PgfError>>()?;
+            Ok(Production::Apply { fid, args })
         }
-        Err(e) => {
-            eprintln!("DEBUG: read_list error at pos {offset}: {e:?}");
-            return Err(e);
+        "Coerce" => Ok(Production::Coerce { arg: json_i32(json_get(prod, path, "arg")?, &format!("{path}.arg"))? }),
+        "Const" => {
+            let cid = json_str(json_get(prod, path, "cid")?, &format!("{path}.cid"))?;
+            let expr = expr_from_json_at(json_get(prod, path, "expr")?, &format!("{path}.expr"))?;
+            let tokens = json_array(json_get(prod, path, "tokens")?, &format!("{path}.tokens"))?.iter()
+                .map(|token| json_str(token, &format!("{path}.tokens")).map(ToString::to_string))
+                .collect::<Result<_, _>>()?;
+            Ok(Production::Const { cid: cid::mk_cid(cid), expr, tokens })
         }
-    };
-    
-    // Handle negative lengths as end-of-list markers (matching C implementation)
-    if len < 0 {
-        debug_println!("DEBUG: read_list: negative length {} at pos {} - treating as end of list", len, offset);
-        return Ok(Vec::new());
+        other => Err(PgfError::JsonError { path: path.to_string(), message: format!("unsupported production type {other}") }),
     }
-    
-    if len > 1_000_000 {  // Reasonable upper limit
-        return Err(PgfError::DeserializeError {
-            offset,
-            message: format!("List length {len} too large at pos {offset} - likely parsing error")
-        });
-    }
-    
-    let mut result = Vec::with_capacity(usize::try_from(len).unwrap_or(0));
-    for _ in 0..len {
-        result.push(f(cursor)?);
+}
+
+fn symbol_from_json(sym: &JsonValue, path: &str) -> Result<Symbol, PgfError> {
+    let kind = json_str(json_get(sym, path, "type")?, &format!("{path}.type"))?;
+    let args_path = format!("{path}.args");
+    let args = json_array(json_get(sym, path, "args")?, &args_path)?;
+    let arg = |i: usize| args.get(i).ok_or_else(|| PgfError::JsonError {
+        path: args_path.clone(),
+        message: format!("{kind} expects more than {} arguments", args.len()),
+    });
+    let int_pair = || -> Result<(i32, i32), PgfError> {
+        Ok((json_i32(arg(0)?, &format!("{args_path}[0]"))?, json_i32(arg(1)?, &format!("{args_path}[1]"))?))
+    };
+    let symbols = |value: &JsonValue, path: &str| -> Result<Vec<Symbol>, PgfError> {
+        json_array(value, path)?.iter()
+            .enumerate()
+            .map(|(i, sym)| symbol_from_json(sym, &format!("{path}[{i}]")))
+            .collect()
+    };
+    match kind {
+        "SymCat" => int_pair().map(|(n, l)| Symbol::SymCat(n, l)),
+        "SymLit" => int_pair().map(|(n, l)| Symbol::SymLit(n, l)),
+        "SymVar" => int_pair().map(|(n, l)| Symbol::SymVar(n, l)),
+        "SymKS" => Ok(Symbol::SymKS(json_str(arg(0)?, &format!("{args_path}[0]"))?.into())),
+        "SymKP" => {
+            let tokens = symbols(arg(0)?, &format!("{args_path}[0]"))?;
+            let alts_path = format!("{args_path}[1]");
+            let alts = json_array(arg(1)?, &alts_path)?.iter()
+                .enumerate()
+                .map(|(i, alt)| {
+                    let alt_path = format!("{alts_path}[{i}]");
+                    let alt_args = json_array(json_get(alt, &alt_path, "args")?, &format!("{alt_path}.args"))?;
+                    let [tokens, prefixes] = alt_args.as_slice() else {
+                        return Err(PgfError::JsonError { path: alt_path, message: "Alt expects 2 arguments".to_string() });
+                    };
+                    let prefixes = json_array(prefixes, &format!("{alt_path}.args[1]"))?.iter()
+                        .map(|prefix| json_str(prefix, &format!("{alt_path}.args[1]")).map(ToString::to_string))
+                        .collect::<Result<_, _>>()?;
+                    Ok(Alt::new(symbols(tokens, &format!("{alt_path}.args[0]"))?, prefixes))
+                })
+                .collect::<Result<_, PgfError>>()?;
+            Ok(Symbol::SymKP(tokens, alts))
+        }
+        "SymBind" => Ok(Symbol::SymBind),
+        "SymSoftBind" => Ok(Symbol::SymSoftBind),
+        "SymNE" => Ok(Symbol::SymNE),
+        "SymSoftSpace" => Ok(Symbol::SymSoftSpace),
+        "SymCapital" => Ok(Symbol::SymCapital),
+        "SymAllCapital" => Ok(Symbol::SymAllCapital),
+        other => Err(PgfError::JsonError { path: path.to_string(), message: format!("unknown symbol type {other}") }),
     }
-    Ok(result)
 }
 
-/// Convert a PGF structure to JSON string representation.
-/// 
+/// Decode an expression written by [`expr_to_json`].
+///
 /// # Errors
-/// Returns `PgfError::SerializeError` if JSON serialization fails.
-pub fn pgf_to_json(pgf: &Pgf) -> Result<String, PgfError> {
-    let json = json!({
-        "abstract": abstract_to_json(&pgf.absname, &pgf.startcat, &pgf.r#abstract),
-        "concretes": concretes_to_json(&pgf.concretes, &pgf.r#abstract),
-    });
-    serde_json::to_string_pretty(&json)
-    .map_err(|e| PgfError::SerializeError(e.to_string()))
+///
+/// Returns [`PgfError::JsonError`] with the path of the offending value if
+/// the JSON is not an encoded expression.
+pub fn expr_from_json(json: &JsonValue) -> Result<Expr, PgfError> {
+    expr_from_json_at(json, "$")
 }
 
-fn abstract_to_json(name: &CId, startcat: &CId, abs: &Abstract) -> JsonValue {
-    use serde_json::{Map, Value};
-    
-    let mut obj = Map::new();
-    // Insert in the correct order to match target output
-    obj.insert("name".to_string(), Value::String(cid::show_cid(name)));
-    obj.insert("startcat".to_string(), Value::String(cid::show_cid(startcat)));
-    obj.insert("funs".to_string(), json!(abs.funs.iter().map(|(cid, fun)| {
-        let (args, cat) = cat_skeleton(&fun.ty);
-        (cid::show_cid(cid), json!({
-            "args": args.into_iter().map(|c| cid::show_cid(&c)).collect::<Vec<_>>(),
-            "cat": cid::show_cid(&cat),
-        }))
-    }).collect::<std::collections::BTreeMap<_, _>>()));
-    
-    Value::Object(obj)
+/// The `type` tag and `args` of a `{"type": ..., "args": [...]}` node.
+fn json_tagged<'a>(value: &'a JsonValue, path: &str) -> Result<(&'a str, &'a [JsonValue]), PgfError> {
+    let kind = json_str(json_get(value, path, "type")?, &format!("{path}.type"))?;
+    let args = json_array(json_get(value, path, "args")?, &format!("{path}.args"))?;
+    Ok((kind, args))
 }
 
-fn concretes_to_json(concretes: &HashMap<Language, Concrete>, abs: &Abstract) -> JsonValue {
-    json!(concretes.iter().map(|(lang, cnc)| {
-        (cid::show_cid(&lang.0), concrete_to_json(cnc, abs))
-    }).collect::<HashMap<_, _>>())
+fn json_args<'a, const N: usize>(kind: &str, args: &'a [JsonValue], path: &str) -> Result<&'a [JsonValue; N], PgfError> {
+    args.try_into().map_err(|_| PgfError::JsonError {
+        path: format!("{path}.args"),
+        message: format!("{kind} expects {N} arguments, found {}", args.len()),
+    })
 }
 
-fn generate_expected_productions(cnc: &Concrete) -> JsonValue {
-    use std::collections::BTreeMap;
-    // First try using the pre-built productions HashMap
-    debug_println!("DEBUG: cnc.productions has {} entries", cnc.productions.len());
-    if !cnc.productions.is_empty() {
-        let productions_map: BTreeMap<String, Vec<JsonValue>> = cnc.productions
-            .iter()
-            .map(|(id, prods)| {
-                debug_println!("DEBUG: Category {} has {} productions", id, prods.len());
-                let json_prods: Vec<JsonValue> = prods.iter().map(production_to_json).collect();
-                (id.to_string(), json_prods)
+fn expr_from_json_at(json: &JsonValue, path: &str) -> Result<Expr, PgfError> {
+    let (kind, args) = json_tagged(json, path)?;
+    let arg = |i: usize| format!("{path}.args[{i}]");
+    match kind {
+        "Abs" => {
+            let [binding, var, body] = json_args(kind, args, path)?;
+            Ok(Expr::Abs(
+                binding_from_json(binding, &arg(0))?,
+                cid::mk_cid(json_str(var, &arg(1))?),
+                Box::new(expr_from_json_at(body, &arg(2))?),
+            ))
+        }
+        "App" => {
+            let [fun, x] = json_args(kind, args, path)?;
+            Ok(Expr::App(Box::new(expr_from_json_at(fun, &arg(0))?), Box::new(expr_from_json_at(x, &arg(1))?)))
+        }
+        "Typed" => {
+            let [expr, ty] = json_args(kind, args, path)?;
+            Ok(Expr::Typed(Box::new(expr_from_json_at(expr, &arg(0))?), type_from_json(ty, &arg(1))?))
+        }
+        "ImplArg" => {
+            let [expr] = json_args(kind, args, path)?;
+            Ok(Expr::ImplArg(Box::new(expr_from_json_at(expr, &arg(0))?)))
+        }
+        "Lit" => {
+            let [lit] = json_args(kind, args, path)?;
+            Ok(Expr::Lit(tagged_literal_from_json(lit, &arg(0))?))
+        }
+        "Fun" | "Str" | "Int" | "Float" | "Double" | "Meta" | "Var" => {
+            let [value] = json_args(kind, args, path)?;
+            let path = arg(0);
+            Ok(match kind {
+                "Fun" => Expr::Fun(cid::mk_cid(json_str(value, &path)?)),
+                "Str" => Expr::Str(json_str(value, &path)?.to_string()),
+                "Int" => Expr::Int(json_i32(value, &path)?),
+                #[allow(clippy::cast_possible_truncation)]
+                "Float" => Expr::Float(json_f64(value, &path)? as f32),
+                "Double" => Expr::Double(json_f64(value, &path)?),
+                "Meta" => Expr::Meta(json_i32(value, &path)?),
+                _ => Expr::Var(json_i32(value, &path)?),
             })
-            .collect();
-        return json!(productions_map);
-    }
-    
-    // Fallback: Generate productions from ccats if productions HashMap is empty
-    debug_println!("DEBUG: Falling back to cnc.ccats with {} entries", cnc.ccats.len());
-    let mut productions_map = BTreeMap::new();
-    
-    for cc in &cnc.ccats {
-        debug_println!("DEBUG: CCat {} has {} productions", cc.id, cc.productions.len());
-        if !cc.productions.is_empty() {
-            let prods: Vec<JsonValue> = cc.productions.iter().map(production_to_json).collect();
-            productions_map.insert(cc.id.to_string(), prods);
         }
+        other => Err(PgfError::JsonError { path: path.to_string(), message: format!("unknown expression type {other}") }),
     }
-    
-    json!(productions_map)
 }
 
-fn generate_categories_map(cnc: &Concrete, abs: &Abstract) -> JsonValue {
-    use std::collections::BTreeMap;
-    let mut categories_map = BTreeMap::new();
-    
-    debug_println!("DEBUG: cnc.cnccats has {} entries", cnc.cnccats.len());
-    
-    // Add parsed categories if available
-    if !cnc.cnccats.is_empty() {
-        for (c, cat) in &cnc.cnccats {
-            debug_println!("DEBUG: Category {} -> start={}, end={}", cid::show_cid(c), cat.start, cat.end);
-            categories_map.insert(
-                cid::show_cid(c),
-                cnc_cat_to_json_with_context(cat, cnc.cncfuns.len())
-            );
-        }
-    } else {
-        // If cnccats is empty, derive categories from the abstract grammar
-        debug_println!("DEBUG: cnccats empty, deriving from abstract grammar with {} categories", abs.cats.len());
-        
-        // Create mapping of category names to IDs based on alphabetical order (as GF typically does)
-        let mut category_names: Vec<&CId> = abs.cats.keys().collect();
-        category_names.sort_by(|a, b| cid::show_cid(a).cmp(&cid::show_cid(b)));
-        
-        for (index, cat_cid) in category_names.iter().enumerate() {
-            let cat_name = cid::show_cid(cat_cid);
-            let id = index as i32;  // Categories start from ID 0
-            debug_println!("DEBUG: Derived category {} -> start={}, end={}", cat_name, id, id);
-            categories_map.insert(cat_name, json!({"start": id, "end": id}));
-        }
-    }
-    
-    // Add built-in categories with their fixed negative ranges (do this last to avoid conflicts)
-    categories_map.insert("Float".to_string(), json!({"start": -3, "end": -3}));
-    categories_map.insert("Int".to_string(), json!({"start": -2, "end": -2}));
-    categories_map.insert("String".to_string(), json!({"start": -1, "end": -1}));
-    
-    json!(categories_map)
+fn type_from_json(json: &JsonValue, path: &str) -> Result<Type, PgfError> {
+    let hypos = json_array(json_get(json, path, "hypos")?, &format!("{path}.hypos"))?.iter()
+        .enumerate()
+        .map(|(i, hypo)| {
+            let hypo_path = format!("{path}.hypos[{i}]");
+            Ok(Hypo {
+                binding: binding_from_json(json_get(hypo, &hypo_path, "bind")?, &format!("{hypo_path}.bind"))?,
+                ty: type_from_json(json_get(hypo, &hypo_path, "type")?, &format!("{hypo_path}.type"))?,
+            })
+        })
+        .collect::<Result<_, PgfError>>()?;
+    let category = cid::mk_cid(json_str(json_get(json, path, "cat")?, &format!("{path}.cat"))?);
+    let exprs = json_array(json_get(json, path, "exprs")?, &format!("{path}.exprs"))?.iter()
+        .enumerate()
+        .map(|(i, expr)| expr_from_json_at(expr, &format!("{path}.exprs[{i}]")))
+        .collect::<Result<_, _>>()?;
+    Ok(Type { hypos, category, exprs })
 }
 
-fn concrete_to_json(cnc: &Concrete, abs: &Abstract) -> JsonValue {
-    json!({
-        "flags": cnc.cflags.iter().map(|(k, v)| (cid::show_cid(k), literal_to_json(v))).collect::<HashMap<_, _>>(),
-        "productions": generate_expected_productions(cnc),
-        "functions": cnc.cncfuns.iter().map(cnc_fun_to_json).collect::<Vec<_>>(),
-        "sequences": cnc.sequences.iter().map(|seq| sequence_to_json(seq)).collect::<Vec<_>>(),
-        "categories": generate_categories_map(cnc, abs),
-        "totalfids": cnc.total_cats,
-    })
+fn binding_from_json(json: &JsonValue, path: &str) -> Result<Binding, PgfError> {
+    let (kind, args) = json_tagged(json, path)?;
+    let [var] = json_args(kind, args, path)?;
+    let var = json_str(var, &format!("{path}.args[0]"))?.to_string();
+    match kind {
+        "Explicit" => Ok(Binding::Explicit(var)),
+        "Implicit" => Ok(Binding::Implicit(var)),
+        other => Err(PgfError::JsonError { path: path.to_string(), message: format!("unknown binding type {other}") }),
+    }
 }
 
-fn literal_to_json(lit: &Literal) -> JsonValue {
-    match lit {
-        Literal::Str(s) => json!(s),
-        Literal::Int(n) => json!(n),
-        Literal::Flt(d) => json!(d),
+fn tagged_literal_from_json(json: &JsonValue, path: &str) -> Result<Literal, PgfError> {
+    let (kind, args) = json_tagged(json, path)?;
+    let [value] = json_args(kind, args, path)?;
+    let value_path = format!("{path}.args[0]");
+    match kind {
+        "Str" => Ok(Literal::Str(json_str(value, &value_path)?.to_string())),
+        "Int" => Ok(Literal::Int(json_i32(value, &value_path)?)),
+        "Flt" => Ok(Literal::Flt(json_f64(value, &value_path)?)),
+        other => Err(PgfError::JsonError { path: path.to_string(), message: format!("unknown literal type {other}") }),
     }
 }
 
-fn cnc_cat_to_json_with_context(cat: &CncCat, num_functions: usize) -> JsonValue {
-    // Adjust category values for ZeroSwe (8 functions)
-    if num_functions == 8 {
-        let adjusted_values = match cat.name.0.as_str() {
-            "N" => (0, 1),    // ZeroSwe N: start=0, end=1
-            "Utt" => (2, 2),  // ZeroSwe Utt: start=2, end=2
-            _ => (cat.start, cat.end) // Keep original for other categories
-        };
-        json!({
-            "start": adjusted_values.0,
-            "end": adjusted_values.1
-        })
-    } else {
-        // ZeroEng (7 functions) - use original values
-        json!({
-            "start": cat.start,
-            "end": cat.end
-        })
+fn literal_from_json(value: &JsonValue, path: &str) -> Result<Literal, PgfError> {
+    match value {
+        JsonValue::String(s) => Ok(Literal::Str(s.clone())),
+        JsonValue::Number(n) => match n.as_i64().and_then(|n| i32::try_from(n).ok()) {
+            Some(n) => Ok(Literal::Int(n)),
+            None => n.as_f64().map(Literal::Flt).ok_or_else(|| json_expected(path, "a number")),
+        },
+        _ => Err(json_expected(path, "a string or number")),
     }
 }
 
-fn cnc_cat_to_json(cat: &CncCat) -> JsonValue {
-    json!({
-        "start": cat.start,
-        "end": cat.end
-    })
+fn json_get<'a>(value: &'a JsonValue, path: &str, key: &str) -> Result<&'a JsonValue, PgfError> {
+    json_object(value, path)?.get(key).ok_or_else(|| json_missing(path, key))
 }
 
-fn cnc_fun_to_json(fun: &CncFun) -> JsonValue {
-    let name_str = cid::show_cid(&fun.name);
-    // Only add quotes for lindef functions, not regular functions
-    let formatted_name = if name_str.starts_with("lindef ") {
-        format!("'{}'", name_str)
-    } else {
-        // Remove any existing quotes that might have been added during parsing
-        name_str.trim_matches('\'').to_string()
-    };
-    json!({
-        "name": formatted_name,
-        "lins": fun.lins,
-    })
+fn json_object<'a>(value: &'a JsonValue, path: &str) -> Result<&'a serde_json::Map<String, JsonValue>, PgfError> {
+    value.as_object().ok_or_else(|| json_expected(path, "an object"))
 }
 
-fn production_to_json(prod: &Production) -> JsonValue {
-    match prod {
-        Production::Apply { fid, args } => json!({
-            "type": "Apply",
-            "fid": *fid, // Use actual fid without offset
-            "args": args.iter().map(p_arg_to_json).collect::<Vec<_>>(),
-        }),
-        Production::Coerce { arg } => json!({
-            "type": "Coerce",
-            "arg": arg,
-        }),
-        Production::Const { cid, expr, tokens } => json!({
-            "type": "Const",
-            "cid": cid.0,
-            "expr": "expr_placeholder",
-            "tokens": tokens,
-        }),
-    }
-}
-
-fn p_arg_to_json(arg: &PArg) -> JsonValue {
-    json!({
-        "type": "PArg",
-        "hypos": &arg.hypos,
-        "fid": arg.fid,
-    })
+fn json_array<'a>(value: &'a JsonValue, path: &str) -> Result<&'a Vec<JsonValue>, PgfError> {
+    value.as_array().ok_or_else(|| json_expected(path, "an array"))
 }
 
-fn sequence_to_json(seq: &[Symbol]) -> JsonValue {
-    json!(seq.iter().map(symbol_to_json).collect::<Vec<_>>())
+fn json_str<'a>(value: &'a JsonValue, path: &str) -> Result<&'a str, PgfError> {
+    value.as_str().ok_or_else(|| json_expected(path, "a string"))
 }
 
-fn symbol_to_json(sym: &Symbol) -> JsonValue {
-    match sym {
-        Symbol::SymCat(n, l) => json!({"type": "SymCat", "args": [n, l]}),
-        Symbol::SymLit(n, l) => json!({"type": "SymLit", "args": [n, l]}),
-        Symbol::SymVar(n, l) => json!({"type": "SymVar", "args": [n, l]}),
-        Symbol::SymKS(t) => json!({"type": "SymKS", "args": [t]}),
-        Symbol::SymKP(ts, alts) => json!({"type": "SymKP", "args": [
-            ts.iter().map(symbol_to_json).collect::<Vec<_>>(),
-            alts.iter().map(alt_to_json).collect::<Vec<_>>()
-            ]}),
-            Symbol::SymBind => json!({"type": "SymBind", "args": []}),
-            Symbol::SymSoftBind => json!({"type": "SymSoftBind", "args": []}),
-            Symbol::SymNE => json!({"type": "SymNE", "args": []}),
-            Symbol::SymSoftSpace => json!({"type": "SymSoftSpace", "args": []}),
-            Symbol::SymCapital => json!({"type": "SymCapital", "args": []}),
-            Symbol::SymAllCapital => json!({"type": "SymAllCapital", "args": []}),
-        }
-    }
-    
-    fn alt_to_json(alt: &Alt) -> JsonValue {
-        json!({
-            "type": "Alt",
-            "args": [
-                alt.tokens.iter().map(symbol_to_json).collect::<Vec<_>>(),
-                alt.prefixes,
-                ]
-            })
-        }
-        
-        fn cat_skeleton(ty: &Type) -> (Vec<CId>, CId) {
-            (ty.hypos.iter().map(|h| h.ty.category.clone()).collect(), ty.category.clone())
-        }
+fn json_i32(value: &JsonValue, path: &str) -> Result<i32, PgfError> {
+    value.as_i64().and_then(|n| i32::try_from(n).ok()).ok_or_else(|| json_expected(path, "a 32-bit integer"))
+}
+
+fn json_f64(value: &JsonValue, path: &str) -> Result<f64, PgfError> {
+    value.as_f64().ok_or_else(|| json_expected(path, "a number"))
+}
+
+fn json_expected(path: &str, expected: &str) -> PgfError {
+    PgfError::JsonError { path: path.to_string(), message: format!("expected {expected}") }
+}
+
+fn json_missing(path: &str, key: &str) -> PgfError {
+    PgfError::JsonError { path: path.to_string(), message: format!("missing field {key}") }
+}
         
         /// Parses input text into abstract syntax expressions using the given grammar and language.
         ///
@@ -2816,66 +5254,460 @@ pub fn check_expr(pgf: &Pgf, expr: &Expr, expected: &Type) -> Result<(Expr, Type
             )))
         }
     }
-    Expr::App(e1, e2) => {
-        let (e1_checked, e1_type) = check_expr(pgf, e1, expected)?;
-        let (args, result_cat) = cat_skeleton(&e1_type);
-        if args.is_empty() || result_cat != expected.category {
-            return Err(PgfError::TypeCheckError("Invalid application".to_string()));
+    Expr::App(e1, e2) => {
+        let (e1_checked, e1_type) = check_expr(pgf, e1, expected)?;
+        let (args, result_cat) = cat_skeleton(&e1_type);
+        if args.is_empty() || result_cat != expected.category {
+            return Err(PgfError::TypeCheckError("Invalid application".to_string()));
+        }
+        let arg_type = &args[0];
+        let (e2_checked, _e2_type) = check_expr(pgf, e2, &Type {
+            hypos: vec![],
+            category: arg_type.clone(),
+            exprs: vec![],
+        })?;
+        Ok((Expr::App(Box::new(e1_checked), Box::new(e2_checked)), expected.clone()))
+    }
+    _ => Err(PgfError::TypeCheckError("Unsupported expression for type checking".to_string())),
+}
+}
+
+/// Linearizes an abstract syntax expression into a string using the specified language.
+///
+/// Each subtree is linearized with the productions of its function whose
+/// argument categories match the categories its children were linearized
+/// in, so inherent features such as gender and number select the right
+/// forms. The sentence is the first field of the tree, or the linref of its
+/// category if the grammar has one. Its tokens are joined with spaces, except
+/// where `BIND` or `SOFT_BIND` glue them together; `CAPIT` and `ALL_CAPIT`
+/// capitalize the token that follows. A `pre` picks the variant whose
+/// prefixes match the word that follows it, or its default at the end.
+///
+/// # Errors
+///
+/// Returns [`PgfError::UnknownLanguage`] if the specified language is not found in the grammar.
+///
+/// Returns [`PgfError::LinearizeError`] if:
+/// - A function has no production in the concrete syntax that accepts its arguments
+/// - The expression contains a metavariable, a variable or a lambda abstraction
+/// - The sentence uses a form the grammar marks as non-existent (`nonExist`)
+pub fn linearize(pgf: &Pgf, lang: &Language, expr: &Expr) -> Result<String, PgfError> {
+    let cnc = pgf.concretes.get(lang).ok_or_else(|| PgfError::UnknownLanguage(cid::show_cid(&lang.0)))?;
+    Linearizer::new(cnc).sentence_text(expr)
+}
+
+/// Linearizes every field of an expression, like GF's `linearize -table`.
+///
+/// Each field is paired with its label from the concrete category of the
+/// tree, e.g. `s Sg` and `s Pl` for a noun inflected for number. Fields
+/// without a label are named by their index, and fields that use a
+/// non-existent form are left out.
+///
+/// # Errors
+///
+/// Returns the same errors as [`linearize`], except for non-existent forms.
+pub fn linearize_table(pgf: &Pgf, lang: &Language, expr: &Expr) -> Result<Vec<(String, String)>, PgfError> {
+    let cnc = pgf.concretes.get(lang).ok_or_else(|| PgfError::UnknownLanguage(cid::show_cid(&lang.0)))?;
+    let lin = Linearizer::new(cnc).first(expr)?;
+    let labels = cnc.cnccats.values()
+        .find(|cat| cat.fids().contains(&lin.cat))
+        .map_or(&[][..], |cat| &cat.labels[..]);
+    let mut table = Vec::with_capacity(lin.fields.len());
+    for (index, field) in lin.fields.into_iter().enumerate() {
+        // Only non-existent forms fail to render.
+        if let Ok(text) = unlex(field) {
+            table.push((labels.get(index).cloned().unwrap_or_else(|| index.to_string()), text));
+        }
+    }
+    Ok(table)
+}
+
+/// Every distinct realization of an expression, for free variation and
+/// functions with several matching productions.
+///
+/// Realizations are computed lazily, in the order of the grammar's
+/// productions, and each string is returned once. The first one is the
+/// result of [`linearize`]. Further variants that cannot be linearized, e.g.
+/// because they use a non-existent form, are left out.
+///
+/// # Errors
+///
+/// Returns the same errors as [`linearize`].
+pub fn linearize_all<'a>(pgf: &'a Pgf, lang: &Language, expr: &'a Expr) -> Result<impl Iterator<Item = String> + 'a, PgfError> {
+    let cnc = pgf.concretes.get(lang).ok_or_else(|| PgfError::UnknownLanguage(cid::show_cid(&lang.0)))?;
+    let linearizer = Rc::new(Linearizer::new(cnc));
+    linearizer.sentence_text(expr)?;
+    let mut seen = std::collections::HashSet::new();
+    Ok(variants(Rc::clone(&linearizer), expr)
+        .filter_map(move |lin| unlex(linearizer.sentence(&lin).ok()?).ok())
+        .filter(move |text| seen.insert(text.clone())))
+}
+
+/// A subtree linearized in one concrete category.
+#[derive(Debug, Clone)]
+struct Lin {
+    cat: i32,
+    fields: Vec<Vec<Token>>,
+}
+
+/// An element of a linearization, before it is rendered by [`unlex`].
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    /// Glue the surrounding words together.
+    Bind,
+    /// Like `Bind`, but a parser may also accept a space here.
+    SoftBind,
+    /// A space that a parser may also accept without.
+    SoftSpace,
+    /// Capitalize the first letter of the next word.
+    Capit,
+    /// Upper-case all of the next word.
+    AllCapit,
+    /// A form the grammar declares does not exist.
+    NonExist,
+    /// A `pre` whose variant depends on the next word.
+    Pre { default: Vec<Token>, alts: Vec<(Vec<Token>, Vec<String>)> },
+}
+
+/// A [`Token`] once [`select_pre`] has resolved every `pre`.
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Word(String),
+    Bind,
+    SoftBind,
+    SoftSpace,
+    Capit,
+    AllCapit,
+    NonExist,
+}
+
+/// Render a token stream as text.
+fn unlex(tokens: Vec<Token>) -> Result<String, PgfError> {
+    let tokens = select_pre(tokens);
+    let mut text = String::new();
+    let mut bind = true;
+    let mut capit: Option<&Lexeme> = None;
+    for lexeme in &tokens {
+        match lexeme {
+            Lexeme::Word(word) if word.is_empty() => {}
+            Lexeme::Word(word) => {
+                if !bind {
+                    text.push(' ');
+                }
+                match capit.take() {
+                    Some(Lexeme::Capit) => {
+                        let mut chars = word.chars();
+                        text.extend(chars.next().into_iter().flat_map(char::to_uppercase));
+                        text.push_str(chars.as_str());
+                    }
+                    Some(Lexeme::AllCapit) => text.push_str(&word.to_uppercase()),
+                    _ => text.push_str(word),
+                }
+                bind = false;
+            }
+            Lexeme::Bind | Lexeme::SoftBind => bind = true,
+            Lexeme::SoftSpace => {}
+            Lexeme::Capit | Lexeme::AllCapit => capit = Some(lexeme),
+            Lexeme::NonExist => {
+                return Err(PgfError::LinearizeError("the sentence uses a non-existent form".to_string()));
+            }
+        }
+    }
+    Ok(text)
+}
+
+/// Replace every `pre` with the variant selected by the word after it.
+///
+/// Tokens are visited from the end, so the following word is always known,
+/// including when it comes from another `pre`.
+fn select_pre(mut pending: Vec<Token>) -> Vec<Lexeme> {
+    let mut selected = Vec::with_capacity(pending.len());
+    let mut next: Option<String> = None;
+    while let Some(token) = pending.pop() {
+        match token {
+            Token::Pre { default, alts } => {
+                let alt = next.as_deref().and_then(|word| {
+                    alts.into_iter().find(|(_, prefixes)| prefixes.iter().any(|prefix| word.starts_with(prefix.as_str())))
+                });
+                pending.extend(alt.map_or(default, |(tokens, _)| tokens));
+            }
+            Token::Word(word) => {
+                if !word.is_empty() {
+                    next = Some(word.clone());
+                }
+                selected.push(Lexeme::Word(word));
+            }
+            Token::Bind => selected.push(Lexeme::Bind),
+            Token::SoftBind => selected.push(Lexeme::SoftBind),
+            Token::SoftSpace => selected.push(Lexeme::SoftSpace),
+            Token::Capit => selected.push(Lexeme::Capit),
+            Token::AllCapit => selected.push(Lexeme::AllCapit),
+            Token::NonExist => selected.push(Lexeme::NonExist),
+        }
+    }
+    selected.reverse();
+    selected
+}
+
+/// The productions of one concrete syntax, indexed by abstract function.
+struct Linearizer<'a> {
+    cnc: &'a Concrete,
+    applications: HashMap<&'a CId, Vec<Application<'a>>>,
+}
+
+/// A `Production::Apply` together with the category it produces.
+#[derive(Clone, Copy)]
+struct Application<'a> {
+    cat: i32,
+    fun: &'a CncFun,
+    args: &'a [PArg],
+}
+
+impl<'a> Linearizer<'a> {
+    fn new(cnc: &'a Concrete) -> Self {
+        let mut productions: Vec<(&i32, &Vec<Production>)> = cnc.productions.iter().collect();
+        productions.sort_by_key(|(cat, _)| **cat);
+        let mut applications: HashMap<&CId, Vec<_>> = HashMap::new();
+        for (&cat, prods) in productions {
+            for prod in prods {
+                if let Production::Apply { fid, args } = prod {
+                    if let Some(fun) = usize::try_from(*fid).ok().and_then(|i| cnc.cncfuns.get(i)) {
+                        applications.entry(&fun.name).or_default().push(Application { cat, fun, args });
+                    }
+                }
+            }
+        }
+        Linearizer { cnc, applications }
+    }
+
+    /// All the ways to linearize `expr`, at most one per concrete category.
+    fn linearize(&self, expr: &Expr) -> Result<Vec<Lin>, PgfError> {
+        let (head, args) = spine(expr);
+        if let Some(lin) = literal(head).filter(|_| args.is_empty()) {
+            return Ok(vec![lin]);
+        }
+        let Expr::Fun(name) = head else {
+            return Err(PgfError::LinearizeError(format!("cannot linearize {}", show_head(head))));
+        };
+
+        let children = args.iter().map(|arg| self.linearize(arg)).collect::<Result<Vec<_>, _>>()?;
+        let mut lins: Vec<Lin> = Vec::new();
+        for app in self.applications(name, children.len()) {
+            if lins.iter().any(|lin| lin.cat == app.cat) {
+                continue;
+            }
+            let chosen: Option<Vec<&Lin>> = app.args.iter().zip(&children)
+                .map(|(param, alternatives)| alternatives.iter().find(|lin| self.accepts(param.fid, lin.cat, 0)))
+                .collect();
+            if let Some(chosen) = chosen {
+                lins.push(self.apply(app, &chosen)?);
+            }
+        }
+        if lins.is_empty() {
+            return Err(PgfError::LinearizeError(format!("no production of {} accepts its arguments", name.0)));
+        }
+        Ok(lins)
+    }
+
+    /// The linearization [`linearize`] renders: the first one of `expr`.
+    fn first(&self, expr: &Expr) -> Result<Lin, PgfError> {
+        self.linearize(expr)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| PgfError::LinearizeError(format!("no linearization of {}", show_head(expr))))
+    }
+
+    /// The text [`linearize`] returns for `expr`.
+    fn sentence_text(&self, expr: &Expr) -> Result<String, PgfError> {
+        unlex(self.sentence(&self.first(expr)?)?)
+    }
+
+    /// The productions of the function `name` that take `arity` arguments.
+    fn applications(&self, name: &CId, arity: usize) -> impl Iterator<Item = &Application<'a>> + '_ {
+        self.applications.get(name)
+            .map_or(&[][..], |apps| &apps[..])
+            .iter()
+            .filter(move |app| app.args.len() == arity)
+    }
+
+    fn apply(&self, app: &Application<'a>, args: &[&Lin]) -> Result<Lin, PgfError> {
+        let fields = app.fun.lins.iter()
+            .map(|&seq| eval(self.sequence(seq)?, args))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Lin { cat: app.cat, fields })
+    }
+
+    /// The tokens of a linearized tree: its linref, or else its first field.
+    fn sentence(&self, lin: &Lin) -> Result<Vec<Token>, PgfError> {
+        let linref = self.cnc.linrefs.iter()
+            .find(|linref| linref.cat == lin.cat)
+            .and_then(|linref| linref.funs.first())
+            .and_then(|&fun| usize::try_from(fun).ok().and_then(|i| self.cnc.cncfuns.get(i)));
+        match linref.and_then(|fun| fun.lins.first()) {
+            Some(&seq) => eval(self.sequence(seq)?, &[lin]),
+            None => Ok(lin.fields.first().cloned().unwrap_or_default()),
         }
-        let arg_type = &args[0];
-        let (e2_checked, _e2_type) = check_expr(pgf, e2, &Type {
-            hypos: vec![],
-            category: arg_type.clone(),
-            exprs: vec![],
-        })?;
-        Ok((Expr::App(Box::new(e1_checked), Box::new(e2_checked)), expected.clone()))
     }
-    _ => Err(PgfError::TypeCheckError("Unsupported expression for type checking".to_string())),
+
+    fn sequence(&self, index: i32) -> Result<&'a [Symbol], PgfError> {
+        usize::try_from(index).ok()
+            .and_then(|i| self.cnc.sequences.get(i))
+            .map(Vec::as_slice)
+            .ok_or_else(|| PgfError::LinearizeError(format!("unknown sequence {index}")))
+    }
+
+    /// Whether an argument of category `param` can be filled by a tree of
+    /// category `cat`, directly or through coercions.
+    fn accepts(&self, param: i32, cat: i32, depth: usize) -> bool {
+        param == cat
+            || depth < self.cnc.productions.len() && self.cnc.productions.get(&param).is_some_and(|prods| {
+                prods.iter().any(|prod| matches!(prod, Production::Coerce { arg } if self.accepts(*arg, cat, depth + 1)))
+            })
+    }
 }
+
+/// The tokens of a sequence, with each argument's fields substituted in.
+fn eval(symbols: &[Symbol], args: &[&Lin]) -> Result<Vec<Token>, PgfError> {
+    let mut tokens = Vec::new();
+    for symbol in symbols {
+        match symbol {
+            Symbol::SymCat(arg, field) | Symbol::SymLit(arg, field) => {
+                let tokens_of = usize::try_from(*arg).ok().and_then(|i| args.get(i))
+                    .and_then(|lin| usize::try_from(*field).ok().and_then(|r| lin.fields.get(r)))
+                    .ok_or_else(|| PgfError::LinearizeError(format!("argument {arg} has no field {field}")))?;
+                tokens.extend(tokens_of.iter().cloned());
+            }
+            Symbol::SymKS(token) => tokens.push(Token::Word(token.clone())),
+            Symbol::SymKP(default, alts) => tokens.push(Token::Pre {
+                default: eval(default, args)?,
+                alts: alts.iter()
+                    .map(|alt| Ok((eval(&alt.tokens, args)?, alt.prefixes.clone())))
+                    .collect::<Result<_, PgfError>>()?,
+            }),
+            Symbol::SymBind => tokens.push(Token::Bind),
+            Symbol::SymSoftBind => tokens.push(Token::SoftBind),
+            Symbol::SymSoftSpace => tokens.push(Token::SoftSpace),
+            Symbol::SymCapital => tokens.push(Token::Capit),
+            Symbol::SymAllCapital => tokens.push(Token::AllCapit),
+            Symbol::SymNE => tokens.push(Token::NonExist),
+            Symbol::SymVar(..) => {}
+        }
+    }
+    Ok(tokens)
 }
 
-/// Linearizes an abstract syntax expression into a string using the specified language.
-///
-/// # Errors
-///
-/// Returns [`PgfError::UnknownLanguage`] if the specified language is not found in the grammar.
+/// Every linearization of `expr`, computed on demand.
 ///
-/// Returns [`PgfError::ParseError`] if:
-/// - The function is not found in the concrete syntax for the language
-/// - The expression type is not supported for linearization
-/// - Recursive linearization of sub-expressions fails
-pub fn linearize(pgf: &Pgf, lang: &Language, expr: &Expr) -> Result<String, PgfError> {
-    let cnc = pgf.concretes.get(lang).ok_or_else(|| PgfError::UnknownLanguage(cid::show_cid(&lang.0)))?;
-    match expr {
-        Expr::Fun(cid) => {
-            let cnc_fun = cnc.cncfuns.iter().find(|f| f.name == *cid);
-            if let Some(fun) = cnc_fun {
-                let seq = fun.lins.iter()
-                .filter_map(|&i| cnc.sequences.get(usize::try_from(i).ok()?))
-                .flat_map(|seq| seq.iter().filter_map(|sym| match sym {
-                    Symbol::SymKS(s) => Some(s.clone()),
-                    Symbol::SymKP(tokens, alts) => {
-                        // Extract string from first SymKS token if available
-                        match tokens.first() {
-                            Some(Symbol::SymKS(s)) => Some(s.clone()),
-                            _ => None
-                        }
-                    },
-                    _ => None,
-                }))
-                .collect::<Vec<_>>();
-            Ok(seq.join(" "))
-        } else {
-            Err(PgfError::ParseError("Function not found in concrete syntax".to_string()))
+/// Unlike [`Linearizer::linearize`], this keeps all productions of a
+/// category and all argument combinations, so free variation shows up.
+fn variants<'a>(linearizer: Rc<Linearizer<'a>>, expr: &'a Expr) -> Box<dyn Iterator<Item = Lin> + 'a> {
+    let (head, args) = spine(expr);
+    if let Some(lin) = literal(head).filter(|_| args.is_empty()) {
+        return Box::new(std::iter::once(lin));
+    }
+    let Expr::Fun(name) = head else { return Box::new(std::iter::empty()) };
+    let apps: Vec<Application<'a>> = linearizer.applications(name, args.len()).copied().collect();
+    if apps.is_empty() {
+        return Box::new(std::iter::empty());
+    }
+    let children: Rc<[Variants<'a>]> = args.iter()
+        .map(|&arg| Variants::new(variants(Rc::clone(&linearizer), arg)))
+        .collect();
+    Box::new(apps.into_iter().flat_map(move |app| {
+        let linearizer = Rc::clone(&linearizer);
+        combinations(Rc::clone(&linearizer), app.args, Rc::clone(&children), Vec::new())
+            .filter_map(move |chosen| linearizer.apply(&app, &chosen.iter().map(|lin| &**lin).collect::<Vec<_>>()).ok())
+    }))
+}
+
+/// The variants of one argument, computed only as far as they are asked for
+/// and then shared by every production and combination that uses them.
+struct Variants<'a> {
+    pending: RefCell<Box<dyn Iterator<Item = Lin> + 'a>>,
+    computed: RefCell<Vec<Rc<Lin>>>,
+}
+
+impl<'a> Variants<'a> {
+    fn new(pending: Box<dyn Iterator<Item = Lin> + 'a>) -> Self {
+        Variants { pending: RefCell::new(pending), computed: RefCell::new(Vec::new()) }
+    }
+
+    /// The variant at `index`, or `None` if there are not that many.
+    fn get(&self, index: usize) -> Option<Rc<Lin>> {
+        let mut computed = self.computed.borrow_mut();
+        while computed.len() <= index {
+            let lin = self.pending.borrow_mut().next()?;
+            computed.push(Rc::new(lin));
         }
+        Some(Rc::clone(&computed[index]))
     }
-    Expr::App(e1, e2) => {
-        let s1 = linearize(pgf, lang, e1)?;
-        let s2 = linearize(pgf, lang, e2)?;
-        Ok(format!("{s1} {s2}"))
+}
+
+/// Every choice of a variant of each of `children` that fits the category of
+/// the corresponding parameter.
+fn combinations<'a>(
+    linearizer: Rc<Linearizer<'a>>,
+    params: &'a [PArg],
+    children: Rc<[Variants<'a>]>,
+    chosen: Vec<Rc<Lin>>,
+) -> Box<dyn Iterator<Item = Vec<Rc<Lin>>> + 'a> {
+    let Some((param, rest)) = params.split_first() else {
+        return Box::new(std::iter::once(chosen));
+    };
+    let child = chosen.len();
+    let candidates = Rc::clone(&children);
+    let accepts = Rc::clone(&linearizer);
+    Box::new((0..)
+        .map_while(move |index| candidates[child].get(index))
+        .filter(move |lin| accepts.accepts(param.fid, lin.cat, 0))
+        .flat_map(move |lin| {
+            let mut chosen = chosen.clone();
+            chosen.push(lin);
+            combinations(Rc::clone(&linearizer), rest, Rc::clone(&children), chosen)
+        }))
+}
+
+/// Split an application into its head and its arguments.
+fn spine(expr: &Expr) -> (&Expr, Vec<&Expr>) {
+    let mut head = expr;
+    let mut args = Vec::new();
+    loop {
+        match head {
+            Expr::App(fun, arg) => {
+                args.push(&**arg);
+                head = fun;
+            }
+            Expr::Typed(expr, _) | Expr::ImplArg(expr) => head = expr,
+            _ => break,
+        }
     }
-    _ => Err(PgfError::ParseError("Unsupported expression for linearization".to_string())),
+    args.reverse();
+    (head, args)
+}
+
+/// The linearization of a literal, in the category -1 (String), -2 (Int) or -3 (Float).
+fn literal(expr: &Expr) -> Option<Lin> {
+    let (cat, token) = match expr {
+        Expr::Str(s) | Expr::Lit(Literal::Str(s)) => (-1, s.clone()),
+        Expr::Int(i) | Expr::Lit(Literal::Int(i)) => (-2, i.to_string()),
+        Expr::Float(f) => (-3, f.to_string()),
+        Expr::Double(f) | Expr::Lit(Literal::Flt(f)) => (-3, f.to_string()),
+        _ => return None,
+    };
+    Some(Lin { cat, fields: vec![vec![Token::Word(token)]] })
 }
+
+/// The function at the head of `expr`, for error messages.
+fn show_head(expr: &Expr) -> String {
+    match expr {
+        Expr::App(fun, _) | Expr::Typed(fun, _) | Expr::ImplArg(fun) => show_head(fun),
+        Expr::Fun(name) => name.0.to_string(),
+        Expr::Meta(_) => "a metavariable".to_string(),
+        Expr::Var(_) => "a variable".to_string(),
+        Expr::Abs(..) => "a lambda abstraction".to_string(),
+        _ => "a literal".to_string(),
+    }
 }
 
 #[must_use]
@@ -2909,14 +5741,13 @@ pub fn function_type(pgf: &Pgf, fun: &CId) -> Option<Type> {
 
 // New functions to implement C code parsing sequence
 
-fn read_lindefs(cursor: &mut Cursor<&[u8]>, ccat_map: &mut std::collections::HashMap<i32, CCat>) -> Result<Vec<LinDef>, PgfError> {
+fn read_lindefs(cursor: &mut Input<'_>, ccat_map: &mut std::collections::HashMap<i32, CCat>) -> Result<Vec<LinDef>, PgfError> {
     // Following C code: pgf_read_lindefs
-    let len = read_int(cursor)?;
-    debug_println!("DEBUG: Reading {} lindefs at pos {}", len, cursor.position());
+    let len = cursor.list_len()?;
     
+    let mut lindefs = Vec::with_capacity(cursor.capacity(len));
     for _ in 0..len {
         let fid = read_int(cursor)?; // pgf_read_fid equivalent
-        debug_println!("DEBUG: Processing lindef for FID {}", fid);
         
         // Ensure CCat exists (lazy creation like C code)
         ccat_map.entry(fid).or_insert_with(|| CCat { 
@@ -2924,27 +5755,25 @@ fn read_lindefs(cursor: &mut Cursor<&[u8]>, ccat_map: &mut std::collections::Has
             productions: Vec::new() 
         });
         
-        let n_funs = read_int(cursor)?;
-        debug_println!("DEBUG: Reading {} functions for lindef FID {}", n_funs, fid);
+        let n_funs = cursor.list_len()?;
         
-        // Read the functions (but we're not storing them in our simplified structure)
+        let mut funs = Vec::with_capacity(cursor.capacity(n_funs));
         for _ in 0..n_funs {
-            let _fun_id = read_int(cursor)?;
+            funs.push(read_int(cursor)?);
         }
+        lindefs.push(LinDef { cat: fid, funs });
     }
     
-    // Return empty lindefs (we're not using them in JSON output)
-    Ok(Vec::new())
+    Ok(lindefs)
 }
 
-fn read_linrefs(cursor: &mut Cursor<&[u8]>, ccat_map: &mut std::collections::HashMap<i32, CCat>) -> Result<Vec<LinRef>, PgfError> {
+fn read_linrefs(cursor: &mut Input<'_>, ccat_map: &mut std::collections::HashMap<i32, CCat>) -> Result<Vec<LinRef>, PgfError> {
     // Following C code: pgf_read_linrefs  
-    let len = read_int(cursor)?;
-    debug_println!("DEBUG: Reading {} linrefs at pos {}", len, cursor.position());
+    let len = cursor.list_len()?;
     
+    let mut linrefs = Vec::with_capacity(cursor.capacity(len));
     for _ in 0..len {
         let fid = read_int(cursor)?; // pgf_read_fid equivalent
-        debug_println!("DEBUG: Processing linref for FID {}", fid);
         
         // Ensure CCat exists (lazy creation like C code)
         ccat_map.entry(fid).or_insert_with(|| CCat { 
@@ -2952,27 +5781,24 @@ fn read_linrefs(cursor: &mut Cursor<&[u8]>, ccat_map: &mut std::collections::Has
             productions: Vec::new() 
         });
         
-        let n_funs = read_int(cursor)?;
-        debug_println!("DEBUG: Reading {} functions for linref FID {}", n_funs, fid);
+        let n_funs = cursor.list_len()?;
         
-        // Read the functions (but we're not storing them in our simplified structure)
+        let mut funs = Vec::with_capacity(cursor.capacity(n_funs));
         for _ in 0..n_funs {
-            let _fun_id = read_int(cursor)?;
+            funs.push(read_int(cursor)?);
         }
+        linrefs.push(LinRef { cat: fid, funs });
     }
     
-    // Return empty linrefs (we're not using them in JSON output)
-    Ok(Vec::new())
+    Ok(linrefs)
 }
 
-fn read_ccats_productions(cursor: &mut Cursor<&[u8]>, ccat_map: &mut std::collections::HashMap<i32, CCat>) -> Result<(), PgfError> {
+fn read_ccats_productions(cursor: &mut Input<'_>, ccat_map: &mut std::collections::HashMap<i32, CCat>, is_pgf_2_1: bool) -> Result<(), PgfError> {
     // Following C code: pgf_read_ccats
-    let len = read_int(cursor)?;
-    debug_println!("DEBUG: Reading {} ccats productions at pos {}", len, cursor.position());
+    let len = cursor.list_len()?;
     
     for i in 0..len {
         let fid = read_int(cursor)?; // pgf_read_fid equivalent
-        debug_println!("DEBUG: Processing productions for CCat {} (FID {})", i, fid);
         
         // Ensure CCat exists (lazy creation like C code)
         let ccat = ccat_map.entry(fid).or_insert_with(|| CCat { 
@@ -2980,13 +5806,11 @@ fn read_ccats_productions(cursor: &mut Cursor<&[u8]>, ccat_map: &mut std::collec
             productions: Vec::new() 
         });
         
-        let n_prods = read_int(cursor)?;
-        debug_println!("DEBUG: Reading {} productions for CCat FID {}", n_prods, fid);
+        let n_prods = cursor.list_len()?;
         
-        let mut productions = Vec::with_capacity(n_prods as usize);
+        let mut productions = Vec::with_capacity(cursor.capacity(n_prods));
         for j in 0..n_prods {
-            debug_println!("DEBUG: Reading production {} for CCat FID {}", j, fid);
-            let prod = read_production(cursor)?;
+            let prod = read_production(cursor, is_pgf_2_1)?;
             productions.push(prod);
         }
         
@@ -3034,19 +5858,21 @@ mod tests {
         });
 
         let mut cats = HashMap::new();
-        cats.insert(cid::mk_cid("Comment"), Category { hypos: vec![], funs: vec![(0, cid::mk_cid("Pred"))] });
-        cats.insert(cid::mk_cid("Item"), Category { hypos: vec![], funs: vec![(0, cid::mk_cid("This"))] });
+        cats.insert(cid::mk_cid("Comment"), Category { hypos: vec![], funs: vec![(1.0, cid::mk_cid("Pred"))], prob: 0.0 });
+        cats.insert(cid::mk_cid("Item"), Category { hypos: vec![], funs: vec![(1.0, cid::mk_cid("This"))], prob: 0.0 });
 
-        let abstract_syntax = Abstract { funs, cats };
+        let abstract_syntax = Abstract { aflags: HashMap::new(), funs, cats };
 
         let mut concretes = HashMap::new();
-        let mut cncfuns = Vec::new();
-        cncfuns.push(CncFun { name: cid::mk_cid("Pred"), lins: vec![0] });
-        cncfuns.push(CncFun { name: cid::mk_cid("This"), lins: vec![1] });
+        let cncfuns = vec![
+            CncFun { name: cid::mk_cid("Pred"), lins: vec![0] },
+            CncFun { name: cid::mk_cid("This"), lins: vec![1] },
+        ];
 
-        let mut sequences = Vec::new();
-        sequences.push(vec![Symbol::SymKS("is".to_string())]);
-        sequences.push(vec![Symbol::SymKS("this".to_string())]);
+        let sequences = vec![
+            vec![Symbol::SymKS("is".to_string())],
+            vec![Symbol::SymKS("this".to_string())],
+        ];
 
         let mut cnccats = HashMap::new();
         cnccats.insert(cid::mk_cid("Comment"), CncCat { name: cid::mk_cid("Comment"), start: 0, end: 1, labels: vec!["C1".to_string()] });
@@ -3073,7 +5899,902 @@ mod tests {
             r#abstract: abstract_syntax,
             startcat: cid::mk_cid("Comment"),
             flags: HashMap::new(),
+            unloaded: HashMap::new(),
+            options: LoadOptions::default(),
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_pgf_binary_round_trip() {
+        fn pgf_files(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) {
+            for entry in std::fs::read_dir(dir).expect("Failed to read grammar directory") {
+                let path = entry.expect("Failed to read directory entry").path();
+                if path.is_dir() && !path.ends_with("malformed") {
+                    pgf_files(&path, files);
+                } else if path.extension().is_some_and(|ext| ext == "pgf") {
+                    files.push(path);
+                }
+            }
+        }
+        let mut grammars = Vec::new();
+        pgf_files(std::path::Path::new("./grammars"), &mut grammars);
+        assert!(grammars.len() >= 12, "{grammars:?}");
+
+        for path in grammars {
+            let original = std::fs::read(&path).expect("Failed to read PGF file");
+            let pgf = parse_pgf(&Bytes::from(original.clone())).expect("Failed to parse PGF");
+            let written = serialize_pgf(&pgf).expect("Failed to serialize PGF");
+            // The writer always emits 2.1, so only those files come back byte for byte.
+            if original.starts_with(&[0, 2]) {
+                assert_eq!(&written[..], &original[..], "{} was not written back byte for byte", path.display());
+            }
+            let reparsed = parse_pgf(&written).expect("Failed to parse written PGF");
+            assert_eq!(reparsed, pgf, "{} changed after a round trip", path.display());
+        }
+    }
+
+    #[test]
+    fn test_pgf_1_0_matches_2_1() {
+        let pairs = [
+            ("./grammars/Hello/Hello-1.0.pgf", "./grammars/Hello/Hello.pgf"),
+            ("./grammars/Movies/Movies-1.0.pgf", "./grammars/Movies/Movies.pgf"),
+            ("./grammars/compare/generated_Zero-1.0.pgf", "./grammars/compare/generated_Zero.pgf"),
+        ];
+        for (v1_path, v2_path) in pairs {
+            let mut v1 = read_pgf(v1_path).expect("Failed to read PGF 1.0 file");
+            let mut v2 = read_pgf(v2_path).expect("Failed to read PGF 2.1 file");
+            // Category probabilities and linrefs only exist in 2.1, and linrefs
+            // also register their (production-less) categories.
+            for cat in v2.r#abstract.cats.values_mut() {
+                cat.prob = 0.0;
+            }
+            for cnc in v1.concretes.values_mut().chain(v2.concretes.values_mut()) {
+                cnc.linrefs.clear();
+                cnc.productions.retain(|_, prods| !prods.is_empty());
+                cnc.ccats.retain(|ccat| !ccat.productions.is_empty());
+            }
+            assert_eq!(v1, v2, "{v1_path} does not match {v2_path}");
+        }
+    }
+
+    #[test]
+    fn test_pgf_2_1_utf8_tokens() {
+        let pgf = read_pgf("./grammars/compare/generated_Zero.pgf").expect("Failed to read Zero PGF");
+        let swe = &pgf.concretes[&language::read_language("ZeroSwe").expect("Invalid language")];
+        let tokens: Vec<&str> = swe.sequences.iter().flatten()
+            .filter_map(|sym| match sym { Symbol::SymKS(token) => Some(token.as_str()), _ => None })
+            .collect();
+        assert!(tokens.contains(&"äpple"), "Expected the token 'äpple', got {tokens:?}");
+    }
+
+    #[test]
+    fn test_unknown_symbol_tag_is_an_error() {
+        let mut data = std::fs::read("./grammars/Hello/Hello.pgf").expect("Failed to read PGF file");
+        // Corrupt the tag of the first SymKS "hello" token.
+        let needle = b"\x03\x05hello";
+        let pos = data.windows(needle.len()).position(|w| w == needle).expect("Token not found");
+        data[pos] = 42;
+        let result = parse_pgf(&Bytes::from(data));
+        assert!(
+            matches!(result, Err(PgfError::DeserializeError {
+                kind: DecodeErrorKind::UnknownTag { what: "symbol", expected: "0..=10", found: 42 }, ref path, ..
+            }) if path.starts_with("concrete[HelloEng].sequences[")),
+            "Expected an unknown symbol tag error, got {result:?}"
+        );
+    }
+
+    #[test]
+    fn test_deserialize_errors_are_structured() {
+        let data = Bytes::from(std::fs::read("./grammars/malformed/bad_symbol_tag.pgf").expect("Failed to read PGF file"));
+        let err = parse_pgf(&data).expect_err("Corrupted grammar was accepted");
+        assert_eq!(
+            err.to_string(),
+            "Deserialization error at offset 795 in concrete[FoodEng].sequences[0].symbol[0]: \
+             unknown symbol tag: expected 0..=10, found 42"
+        );
+
+        // Reads past the end keep the underlying I/O error as their source.
+        let err = parse_pgf(&Bytes::from_static(b"\x00\x02")).expect_err("Truncated header was accepted");
+        assert_eq!(err.decode_kind(), Some(&DecodeErrorKind::UnexpectedEof { what: "minor version" }));
+        let source = std::error::Error::source(&err).expect("Missing error source");
+        assert_eq!(source.downcast_ref::<io::Error>().map(io::Error::kind), Some(io::ErrorKind::UnexpectedEof));
+
+        let err = parse_pgf(&Bytes::from_static(b"\x00\x09\x00\x00")).expect_err("Unknown version was accepted");
+        assert_eq!(err.decode_kind(), Some(&DecodeErrorKind::UnsupportedVersion { major: 9, minor: 0 }));
+    }
+
+    #[test]
+    fn test_strict_loading_reports_truncated_concrete() {
+        let data = std::fs::read("./grammars/Food/Food.pgf").expect("Failed to read PGF file");
+        let truncated = Bytes::from(data[..data.len() - 40].to_vec());
+
+        let strict = LoadOptions { strict: true, ..LoadOptions::default() };
+        match parse_pgf_with(&truncated, &strict) {
+            Err(PgfError::DeserializeError { offset, path, .. }) => {
+                assert!(path.starts_with("concrete[FoodEng]."), "Unexpected path: {path}");
+                assert!(offset > 0 && offset <= truncated.len() as u64, "Unexpected offset {offset}");
+            }
+            other => panic!("Expected a strict deserialization error, got {other:?}"),
+        }
+
+        let (pgf, warnings) = parse_pgf_with(&truncated, &LoadOptions::default()).expect("Lenient loading failed");
+        assert_eq!(pgf.concretes.len(), 1);
+        assert!(!warnings.is_empty(), "Expected recovered warnings");
+        assert!(warnings.iter().all(|w| w.structure.starts_with("concrete[FoodEng].")), "{warnings:?}");
+
+        let (_, warnings) = read_pgf_with("./grammars/Food/Food.pgf", &strict).expect("Strict loading failed");
+        assert!(warnings.is_empty());
+
+        // No prefix of the file loads in strict mode, including ones that end
+        // exactly where a list starts.
+        for len in 0..data.len() {
+            let result = parse_pgf_with(&Bytes::copy_from_slice(&data[..len]), &strict);
+            assert!(result.is_err(), "prefix of {len} bytes was accepted");
+        }
+        let empty = Bytes::new();
+        assert!(read_list(&mut Input::new(&empty, &strict), read_int).is_err());
+        assert_eq!(read_list(&mut Input::new(&empty, &LoadOptions::default()), read_int).expect("Lenient list failed"), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn test_selective_language_loading() {
+        for (path, skipped, kept) in [
+            ("./grammars/compare/generated_Zero.pgf", "ZeroEng", "ZeroSwe"),
+            ("./grammars/compare/generated_Zero-1.0.pgf", "ZeroEng", "ZeroSwe"),
+            ("./grammars/Movies/Movies.pgf", "MoviesEng", "MoviesFre"),
+        ] {
+            let full = read_pgf(path).expect("Failed to read PGF file");
+            let (mut pgf, warnings) = read_pgf_with(path, &LoadOptions::languages(&[kept])).expect("Failed to read PGF file");
+            assert!(warnings.is_empty(), "{warnings:?}");
+            let skipped_lang = language::read_language(skipped).expect("Invalid language");
+            let kept_lang = language::read_language(kept).expect("Invalid language");
+            assert_eq!(pgf.concretes.len(), 1);
+            assert_eq!(pgf.concrete(&kept_lang), full.concrete(&kept_lang));
+            assert_eq!(pgf.unloaded_languages().collect::<Vec<_>>(), vec![&skipped_lang]);
+            assert!(matches!(serialize_pgf(&pgf), Err(PgfError::SerializeError(_))));
+            assert!(matches!(pgf_to_json(&pgf), Err(PgfError::SerializeError(_))));
+
+            let (loaded, warnings) = pgf.load_language(path, skipped).expect("Failed to load language");
+            assert!(warnings.is_empty(), "{warnings:?}");
+            assert_eq!(Some(loaded), full.concrete(&skipped_lang));
+            assert_eq!(pgf, full);
+            assert!(matches!(pgf.load_language(path, "Klingon"), Err(PgfError::UnknownLanguage(_))));
+            assert_eq!(pgf_to_json(&pgf).expect("Failed to convert"), pgf_to_json(&full).expect("Failed to convert"));
+
+            let unknown = read_pgf_with(path, &LoadOptions::languages(&[kept, "Klingon"]));
+            assert!(matches!(unknown, Err(PgfError::UnknownLanguage(name)) if name == "Klingon"));
+        }
+
+        // Languages loaded later are bound by the limits of the first load.
+        let data = Bytes::from(std::fs::read("./grammars/Movies/Movies.pgf").expect("Failed to read PGF file"));
+        let limits = Limits { max_bytes: data.len(), ..Limits::default() };
+        let options = LoadOptions { limits, ..LoadOptions::languages(&["MoviesFre"]) };
+        let (mut pgf, _) = parse_pgf_with(&data, &options).expect("Failed to parse PGF");
+        assert_eq!(pgf, parse_pgf_with(&data, &LoadOptions::languages(&["MoviesFre"])).expect("Failed to parse PGF").0);
+        let padded = Bytes::from([&data[..], &[0]].concat());
+        assert!(matches!(pgf.load_language_from(&padded, "MoviesEng"),
+            Err(PgfError::LimitExceeded { limit: Limit::Bytes, .. })));
+        let mut rebuilt = data.to_vec();
+        *rebuilt.last_mut().expect("Empty file") ^= 1;
+        assert!(matches!(pgf.load_language_from(&Bytes::from(rebuilt), "MoviesEng"), Err(PgfError::InvalidGrammar(_))));
+        let food = std::fs::read("./grammars/Food/Food.pgf").expect("Failed to read PGF file");
+        assert!(matches!(pgf.load_language_from(&Bytes::from(food), "MoviesEng"), Err(PgfError::InvalidGrammar(_))));
+        pgf.load_language_from(&data, "MoviesEng").expect("Failed to load language");
+    }
+
+    #[test]
+    fn test_malformed_corpus_is_rejected() {
+        // Files named `limit_*` ask for more than the limits allow; the rest
+        // are truncated or corrupted. None may be accepted in strict mode.
+        let strict = LoadOptions { strict: true, ..LoadOptions::default() };
+        let mut entries: Vec<_> = std::fs::read_dir("./grammars/malformed").expect("Failed to read corpus")
+            .map(|entry| entry.expect("Failed to read corpus entry").path())
+            .collect();
+        entries.sort();
+        assert!(entries.len() >= 10);
+        for path in entries {
+            let data = Bytes::from(std::fs::read(&path).expect("Failed to read corpus file"));
+            let name = path.file_name().and_then(|n| n.to_str()).expect("Invalid file name");
+            let result = parse_pgf_with(&data, &strict);
+            assert!(result.is_err(), "{name} was accepted");
+            let lenient = parse_pgf_with(&data, &LoadOptions::default());
+            if name.starts_with("limit_") {
+                assert!(matches!(result, Err(PgfError::LimitExceeded { .. })), "{name}: {result:?}");
+                assert!(matches!(lenient, Err(PgfError::LimitExceeded { .. })), "{name}: {lenient:?}");
+            }
+        }
+
+        // Pre blocks nested 2000 deep, in a decoded and in a skipped concrete syntax.
+        let data = Bytes::from(std::fs::read("./grammars/malformed/limit_deep_pre.pgf").expect("Failed to read corpus file"));
+        for options in [LoadOptions::default(), LoadOptions::languages(&[])] {
+            assert!(matches!(parse_pgf_with(&data, &options), Err(PgfError::LimitExceeded { limit: Limit::Depth, .. })));
+        }
+
+        let data = Bytes::from(std::fs::read("./grammars/Food/Food.pgf").expect("Failed to read PGF file"));
+        let tight = LoadOptions { limits: Limits { max_bytes: 1000, ..Limits::default() }, ..LoadOptions::default() };
+        assert!(matches!(parse_pgf_with(&data, &tight),
+            Err(PgfError::LimitExceeded { limit: Limit::Bytes, value: 1363, max: 1000, .. })));
+        let tight = LoadOptions { limits: Limits { max_string_len: 8, ..Limits::default() }, ..LoadOptions::default() };
+        assert!(matches!(parse_pgf_with(&data, &tight), Err(PgfError::LimitExceeded { limit: Limit::StringLength, .. })));
+    }
+
+    #[test]
+    fn test_mutated_grammars_never_panic() {
+        let limits = Limits { max_list_len: 10_000, max_string_len: 1_000, ..Limits::default() };
+        for path in ["./grammars/Food/Food.pgf", "./grammars/Movies/Movies-1.0.pgf"] {
+            let original = std::fs::read(path).expect("Failed to read PGF file");
+            for strict in [false, true] {
+                let options = LoadOptions { strict, limits, ..LoadOptions::default() };
+                for len in 0..original.len() {
+                    let _ = parse_pgf_with(&Bytes::copy_from_slice(&original[..len]), &options);
+                }
+                for pos in 0..original.len() {
+                    for byte in [0x00, 0xff] {
+                        let mut data = original.clone();
+                        data[pos] = byte;
+                        let _ = parse_pgf_with(&Bytes::from(data), &options);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_observer_receives_events() {
+        #[derive(Default)]
+        struct Collector(std::sync::Mutex<Vec<String>>);
+        impl LoadObserver for Collector {
+            fn on_event(&self, event: &LoadEvent<'_>) {
+                let line = match event {
+                    LoadEvent::Version { major, minor } => format!("version {major}.{minor}"),
+                    LoadEvent::SectionStart { section, .. } => format!("start {section}"),
+                    LoadEvent::SectionEnd { section, count, .. } => format!("end {section} {count:?}"),
+                    LoadEvent::StartCat { name, from_flag } => format!("startcat {} {from_flag}", name.0),
+                    LoadEvent::Recovered(warning) => format!("recovered {}", warning.structure),
+                };
+                self.0.lock().expect("Poisoned lock").push(line);
+            }
+        }
+
+        let collector = Arc::new(Collector::default());
+        let options = LoadOptions { observer: Some(collector.clone()), ..LoadOptions::default() };
+        read_pgf_with("./grammars/Food/Food.pgf", &options).expect("Failed to read PGF file");
+        let events = collector.0.lock().expect("Poisoned lock");
+        assert_eq!(events.first().map(String::as_str), Some("version 2.1"));
+        assert!(events.contains(&"end abstract.funs Some(14)".to_string()), "{events:?}");
+        assert!(events.contains(&"end concrete[FoodEng].cnccats Some(7)".to_string()), "{events:?}");
+        assert!(events.contains(&"end concretes Some(1)".to_string()), "{events:?}");
+        assert!(events.contains(&"startcat Phrase true".to_string()), "{events:?}");
+        assert!(!events.iter().any(|e| e.starts_with("recovered")), "{events:?}");
+        drop(events);
+
+        // Languages loaded later report to the same observer, in the same mode.
+        let path = "./grammars/Movies/Movies.pgf";
+        let options = LoadOptions { strict: true, observer: Some(collector.clone()), ..LoadOptions::languages(&["MoviesFre"]) };
+        let (mut pgf, _) = read_pgf_with(path, &options).expect("Failed to read PGF file");
+        collector.0.lock().expect("Poisoned lock").clear();
+        pgf.load_language(path, "MoviesEng").expect("Failed to load language");
+        assert!(pgf.options.strict);
+        let events = collector.0.lock().expect("Poisoned lock");
+        assert_eq!(events.first().map(String::as_str), Some("start concrete[MoviesEng]"), "{events:?}");
+        assert!(events.contains(&"end concrete[MoviesEng].cnccats Some(8)".to_string()), "{events:?}");
+    }
+
+    #[test]
+    fn test_navigation_api() {
+        let pgf = read_pgf("./grammars/Food/Food.pgf").expect("Failed to read PGF file");
+        assert_eq!(pgf.abstract_name().as_str(), "Food");
+        assert_eq!(pgf.start_cat().as_str(), "Phrase");
+
+        let abs = pgf.abstract_syntax();
+        let wine = abs.function(&cid::mk_cid("Wine")).expect("No function Wine");
+        assert_eq!(wine.ty().category().as_str(), "Kind");
+        assert_eq!(wine.arity(), 0);
+        assert!(wine.probability() > 0.0);
+        let kind = abs.category(&cid::mk_cid("Kind")).expect("No category Kind");
+        assert!(kind.functions().any(|(_, name)| name == &cid::mk_cid("Wine")));
+
+        let lang = language::read_language("FoodEng").expect("Invalid language");
+        let cnc = pgf.concrete(&lang).expect("No concrete FoodEng");
+        let (fid, _) = cnc.functions().iter().enumerate()
+            .find(|(_, fun)| fun.name().as_str() == "Wine")
+            .expect("No concrete function Wine");
+        let fid = i32::try_from(fid).expect("Function index out of range");
+        let kind_fids = cnc.category(&cid::mk_cid("Kind")).expect("No cnccat Kind").fids();
+        let wine_cat = kind_fids
+            .into_iter()
+            .find(|cat| cnc.productions_for(*cat).iter().any(|p| matches!(p, Production::Apply { fid: f, .. } if *f == fid)))
+            .expect("No production for Wine");
+        assert!(cnc.ccats().iter().any(|ccat| ccat.id() == wine_cat));
+        let lin = cnc.functions()[usize::try_from(fid).expect("Negative fid")].lins()[0];
+        assert_eq!(cnc.sequence(lin), Some(&[Symbol::SymKS("wine".to_string())][..]));
+        assert!(cnc.productions_for(-1).is_empty());
+    }
+
+    #[test]
+    fn test_builder_round_trips() {
+        let cat = |name: &str| types::mk_type(vec![], cid::mk_cid(name), vec![]);
+        let arg = |name: &str| types::mk_hypo(Binding::Explicit("_".to_string()), cat(name));
+
+        let mut cnc = ConcreteBuilder::new();
+        let greet = cnc.add_sequence(vec![Symbol::SymKS("hello".to_string()), Symbol::SymCat(0, 0)]);
+        let world = cnc.add_sequence(vec![Symbol::SymKS("world".to_string())]);
+        let arg0 = cnc.add_sequence(vec![Symbol::SymCat(0, 0)]);
+        assert_eq!(cnc.add_sequence(vec![Symbol::SymKS("world".to_string())]), world);
+        let hello = cnc.add_function("Hello", vec![greet]);
+        let world = cnc.add_function("World", vec![world]);
+        let lindef = cnc.add_function("lindef Greeting", vec![arg0]);
+        cnc.category("Recipient", 0, 0, vec!["s".to_string()])
+            .category("Greeting", 1, 1, vec!["s".to_string()])
+            .production(0, Production::Apply { fid: world, args: vec![] })
+            .production(1, Production::Apply { fid: hello, args: vec![PArg::new(vec![], 0)] })
+            .lindef(1, lindef)
+            .printname("Hello", "greeting");
+
+        let mut builder = PgfBuilder::new("Hello");
+        builder.cat("Greeting", vec![])
+            .cat("Recipient", vec![])
+            .fun("Hello", types::mk_type(vec![arg("Recipient")], cid::mk_cid("Greeting"), vec![]))
+            .fun("World", cat("Recipient"))
+            .start_cat("Greeting")
+            .concrete("HelloEng", cnc.clone());
+        let pgf = builder.build().expect("Failed to build grammar");
+        assert_eq!(pgf.start_cat().as_str(), "Greeting");
+        assert_eq!(pgf.abstract_syntax().function(&cid::mk_cid("Hello")).map(|fun| fun.ty().hypos().len()), Some(1));
+        let reparsed = parse_pgf(&serialize_pgf(&pgf).expect("Failed to serialize PGF"))
+            .expect("Failed to parse built PGF");
+        assert_eq!(reparsed, pgf);
+
+        cnc.production(1, Production::Apply { fid: hello, args: vec![PArg::new(vec![], 7)] });
+        builder.concrete("HelloBad", cnc);
+        assert!(matches!(builder.build(), Err(PgfError::InvalidGrammar(message)) if message.contains("HelloBad")));
+    }
+
+    #[test]
+    fn test_validate_reports_dangling_references() {
+        for path in ["./grammars/Food/Food.pgf", "./grammars/Movies/Movies.pgf", "./grammars/Flight/Flight.pgf"] {
+            let pgf = read_pgf(path).expect("Failed to read PGF file");
+            assert_eq!(validate(&pgf), vec![], "{path}");
+        }
+
+        let mut fixture = fixture_builder();
+        let hello = fixture.sentence("Hello", 1, vec![Symbol::SymKS("hello".into()), Symbol::SymCat(0, 0)]);
+        let world = fixture.word("World", vec![Symbol::SymKS("world".into())]);
+        fixture.abs.fun("Goodbye", types::mk_type(vec![], cid::mk_cid("S"), vec![]));
+        let mut pgf = fixture.build().expect("Failed to build grammar");
+        let issues = validate(&pgf);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Warning);
+        assert_eq!(issues[0].to_string(), "warning: concrete[FixtureEng].cncfuns: abstract function Goodbye has no linearization");
+
+        let cnc = pgf.concretes.get_mut(&Language(cid::mk_cid(FIXTURE_LANG))).expect("Missing concrete");
+        cnc.cncfuns[usize::try_from(world).expect("Negative fid")].lins.push(99);
+        cnc.productions.insert(1, vec![
+            Production::Apply { fid: hello, args: vec![] },
+            Production::Apply { fid: 42, args: vec![] },
+        ]);
+        let errors: Vec<String> = validate(&pgf).iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(errors, [
+            "error: concrete[FixtureEng].cncfuns[1].lins[1]: World refers to unknown sequence 99",
+            "error: concrete[FixtureEng].productions[1][0]: sequence 0 of Hello uses argument 0, but the production has 0 arguments",
+            "error: concrete[FixtureEng].productions[1][1]: applies unknown concrete function 42",
+        ]);
+
+        // W has the single field `s`, so field 1 of a W argument does not exist.
+        let mut fixture = fixture_builder();
+        fixture.sentence("Second", 1, vec![Symbol::SymKS("the".into()), Symbol::SymCat(0, 1)]);
+        fixture.word("World", vec![Symbol::SymKS("world".into())]);
+        let pgf = fixture.build().expect("Failed to build grammar");
+        let errors: Vec<String> = validate(&pgf).iter().map(ToString::to_string).collect();
+        assert_eq!(errors, [
+            "error: concrete[FixtureEng].productions[1][0]: sequence 0 of Second uses field 1 of argument 0, but its category 0 has 1 fields",
+        ]);
+    }
+
+    #[test]
+    fn test_builder_rejects_categories_outside_ranges() {
+        let mut fixture = fixture_builder();
+        let hello = fixture.word("Hello", vec![Symbol::SymKS("hello".into())]);
+        fixture.cnc.production(3, Production::Apply { fid: hello, args: vec![] });
+        let result = fixture.build();
+        assert!(matches!(result, Err(PgfError::InvalidGrammar(message)) if message.contains("unknown concrete category 3")));
+    }
+
+    #[test]
+    fn test_pgf_from_gf_json() {
+        for (json, pgf) in [
+            ("./grammars/Food/gf_make_generated.json", "./grammars/Food/Food.pgf"),
+            ("./grammars/compare/correctly_generated_Zero.json", "./grammars/compare/generated_Zero.pgf"),
+        ] {
+            let json = std::fs::read_to_string(json).expect("Failed to read JSON file");
+            let mut loaded = pgf_from_json(&json).expect("Failed to load JSON grammar");
+            let mut expected = read_pgf(pgf).expect("Failed to read PGF file");
+            // Labels and the lindef/linref tables are not part of GF's JSON.
+            for cnc in loaded.concretes.values_mut().chain(expected.concretes.values_mut()) {
+                for cat in cnc.cnccats.values_mut() {
+                    cat.labels.clear();
+                }
+                cnc.lindefs.clear();
+                cnc.linrefs.clear();
+                cnc.ccats.retain(|ccat| !ccat.productions.is_empty());
+                cnc.productions.retain(|_, prods| !prods.is_empty());
+            }
+            assert_eq!(loaded, expected);
+        }
+
+        let err = pgf_from_json(r#"{"abstract": {"name": "A", "startcat": "S", "funs": {"f": {"args": [], "cat": 1}}}}"#);
+        assert!(matches!(err, Err(PgfError::JsonError { path, .. }) if path == "abstract.funs.f.cat"));
+    }
+
+    #[test]
+    fn test_gf_json_golden_files() {
+        // Pretty-printed `gf --output-format=json` output.
+        for (pgf, golden) in [
+            ("./grammars/Food/Food.pgf", "./grammars/Food/gf_make_generated.json"),
+            ("./grammars/compare/generated_Zero.pgf", "./grammars/compare/correctly_generated_Zero.json"),
+        ] {
+            let pgf = read_pgf(pgf).expect("Failed to read PGF file");
+            let json = pgf_to_json(&pgf).expect("Failed to convert PGF to JSON");
+            let expected = std::fs::read_to_string(golden).expect("Failed to read golden file");
+            assert_eq!(json, expected, "{golden} differs");
+        }
+
+        // Unformatted `gf --output-format=json` output.
+        let pgf = read_pgf("./grammars/Ticket/Ticket.pgf").expect("Failed to read PGF file");
+        let json: serde_json::Value = serde_json::from_str(&pgf_to_json(&pgf).expect("Failed to convert PGF to JSON"))
+            .expect("Invalid JSON");
+        let expected = std::fs::read_to_string("./grammars/Ticket/Ticket.json").expect("Failed to read golden file");
+        assert_eq!(serde_json::to_string(&json).expect("Failed to serialize JSON"), expected);
+    }
+
+    #[test]
+    fn test_expr_json_round_trip() {
+        let ty = types::mk_type(
+            vec![types::mk_hypo(Binding::Implicit("x".to_string()), types::mk_type(vec![], cid::mk_cid("N"), vec![]))],
+            cid::mk_cid("Vec"),
+            vec![Expr::Var(0)],
+        );
+        let app = |f: Expr, x: Expr| Expr::App(Box::new(f), Box::new(x));
+        let expr = Expr::Abs(
+            Binding::Explicit("x".to_string()),
+            cid::mk_cid("x"),
+            Box::new(app(
+                app(Expr::Fun(cid::mk_cid("f")), Expr::Typed(Box::new(Expr::Meta(3)), ty)),
+                app(
+                    app(Expr::ImplArg(Box::new(Expr::Str("s".to_string()))), Expr::Int(-7)),
+                    app(
+                        app(Expr::Float(0.5), Expr::Double(2.25)),
+                        app(Expr::Lit(Literal::Flt(1.0)), Expr::Lit(Literal::Int(1))),
+                    ),
+                ),
+            )),
+        );
+        assert_eq!(expr_from_json(&expr_to_json(&expr)).expect("Failed to decode expression"), expr);
+
+        let prod = Production::Const { cid: cid::mk_cid("Int"), expr: Expr::Lit(Literal::Int(42)), tokens: vec!["42".to_string()] };
+        assert_eq!(production_from_json(&serde_json::to_value(ProductionJson(&prod)).expect("Failed to encode production"), "$").expect("Failed to decode production"), prod);
+
+        let err = expr_from_json(&json!({"type": "App", "args": [{"type": "Fun", "args": ["f"]}]}));
+        assert!(matches!(err, Err(PgfError::JsonError { path, .. }) if path == "$.args"));
+    }
+
+    #[test]
+    fn test_full_abstract_json() {
+        let pgf = read_pgf("./grammars/Food/Food.pgf").expect("Failed to read PGF file");
+        let options = JsonOptions { full_abstract: true, ..JsonOptions::default() };
+        let json: serde_json::Value = serde_json::from_str(&pgf_to_json_with(&pgf, &options).expect("Failed to convert PGF to JSON"))
+            .expect("Invalid JSON");
+        let abs = &json["abstract"];
+        assert_eq!(abs["flags"]["startcat"], "Phrase");
+
+        let qkind = &abs["funs"]["QKind"];
+        assert_eq!(qkind["args"], json!(["Quality", "Kind"]));
+        assert_eq!(qkind["prob"], 0.25);
+        assert_eq!(qkind["constructor"], false);
+        assert_eq!(qkind["equations"], json!([]));
+        let ty = &qkind["type"];
+        assert_eq!(ty["cat"], "Kind");
+        assert_eq!(ty["hypos"][0]["bind"], json!({"type": "Explicit", "args": ["_"]}));
+        assert_eq!(ty["hypos"][0]["type"]["cat"], "Quality");
+
+        let kind = &abs["cats"]["Kind"];
+        assert_eq!(kind["hypos"], json!([]));
+        assert_eq!(kind["funs"].as_array().map(Vec::len), Some(4));
+        assert_eq!(kind["funs"][0]["prob"], 0.25);
+
+        let gf: serde_json::Value = serde_json::from_str(&pgf_to_json(&pgf).expect("Failed to convert PGF to JSON"))
+            .expect("Invalid JSON");
+        assert!(gf["abstract"].get("cats").is_none());
+        assert_eq!(json["concretes"], gf["concretes"]);
+    }
+
+    #[test]
+    fn test_full_concrete_json_round_trips() {
+        let options = JsonOptions { full_concrete: true, ..JsonOptions::default() };
+        for path in ["./grammars/Food/Food.pgf", "./grammars/Movies/Movies.pgf", "./grammars/compare/generated_Zero.pgf"] {
+            let pgf = read_pgf(path).expect("Failed to read PGF file");
+            let json = pgf_to_json_with(&pgf, &options).expect("Failed to convert PGF to JSON");
+            assert_eq!(pgf_from_json(&json).expect("Failed to load JSON grammar"), pgf, "{path} changed after a round trip");
+        }
+
+        let pgf = read_pgf("./grammars/Food/Food.pgf").expect("Failed to read PGF file");
+        let json: serde_json::Value = serde_json::from_str(&pgf_to_json_with(&pgf, &options).expect("Failed to convert PGF to JSON"))
+            .expect("Invalid JSON");
+        let cnc = &json["concretes"]["FoodEng"];
+        assert_eq!(cnc["categories"]["Kind"]["labels"], json!(["s"]));
+        assert_eq!(cnc["lindefs"]["0"], json!([0]));
+        assert_eq!(cnc["linrefs"]["0"], json!([1]));
+        assert_eq!(cnc["ccats"], json!([0, 1, 2, 3]));
+        assert_eq!(cnc["printnames"], json!({}));
+    }
+
+    #[test]
+    fn test_json_output_is_canonical() {
+        let options = JsonOptions { full_abstract: true, full_concrete: true, ..JsonOptions::default() };
+        for path in ["./grammars/Food/Food.pgf", "./grammars/Movies/Movies.pgf", "./grammars/compare/generated_Zero.pgf"] {
+            // Each load fills its hash maps in a different order.
+            let outputs: Vec<String> = (0..4)
+                .map(|_| pgf_to_json_with(&read_pgf(path).expect("Failed to read PGF file"), &options).expect("Failed to convert PGF to JSON"))
+                .collect();
+            assert!(outputs.windows(2).all(|pair| pair[0] == pair[1]), "{path} gave different JSON across loads");
+
+            let reloaded = pgf_from_json(&outputs[0]).expect("Failed to load JSON grammar");
+            assert_eq!(pgf_to_json_with(&reloaded, &options).expect("Failed to convert PGF to JSON"), outputs[0]);
+
+            let json: serde_json::Value = serde_json::from_str(&outputs[0]).expect("Invalid JSON");
+            for cnc in json["concretes"].as_object().expect("No concretes").values() {
+                let fids: Vec<i32> = cnc["productions"].as_object().expect("No productions").keys()
+                    .map(|fid| fid.parse().expect("Invalid fid"))
+                    .collect();
+                assert!(fids.windows(2).all(|pair| pair[0] < pair[1]), "{path} productions are not ordered by fid");
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_pgf_json_streams() {
+        struct FailingWriter;
+        impl Write for FailingWriter {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk full"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let pgf = read_pgf("./grammars/Ticket/Ticket.pgf").expect("Failed to read PGF file");
+        let mut out = Vec::new();
+        let compact = JsonOptions { format: JsonFormat::Compact, ..JsonOptions::default() };
+        write_pgf_json(&pgf, &mut out, &compact).expect("Failed to write JSON");
+        let expected = std::fs::read("./grammars/Ticket/Ticket.json").expect("Failed to read golden file");
+        assert_eq!(out, expected);
+
+        let options = JsonOptions { full_abstract: true, full_concrete: true, ..JsonOptions::default() };
+        let mut out = Vec::new();
+        write_pgf_json(&pgf, &mut out, &options).expect("Failed to write JSON");
+        assert_eq!(String::from_utf8(out).expect("Invalid UTF-8"), pgf_to_json_with(&pgf, &options).expect("Failed to convert PGF to JSON"));
+
+        assert!(matches!(write_pgf_json(&pgf, FailingWriter, &options), Err(PgfError::Io(_))));
+    }
+
+    #[test]
+    fn test_binary_cache() {
+        let dir = std::env::temp_dir().join(format!("pgf2json-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Failed to create cache directory");
+        let cache = dir.join("Food.cache");
+        let cache = cache.to_str().expect("Non UTF-8 temp path");
+        let source = std::fs::read("./grammars/Food/Food.pgf").expect("Failed to read PGF file");
+        let pgf = parse_pgf(&Bytes::from(source.clone())).expect("Failed to parse PGF");
+
+        assert!(Pgf::load_cache(cache, &source).expect("Failed to read cache").is_none());
+        assert_eq!(read_pgf_cached("./grammars/Food/Food.pgf", cache).expect("Failed to read PGF through cache"), pgf);
+        assert_eq!(Pgf::load_cache(cache, &source).expect("Failed to read cache"), Some(pgf.clone()));
+
+        // A different source, a corrupted payload and another format version are all stale.
+        let mut other = source.clone();
+        other.push(0);
+        assert!(Pgf::load_cache(cache, &other).expect("Failed to read cache").is_none());
+        let mut data = std::fs::read(cache).expect("Failed to read cache file");
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        std::fs::write(cache, &data).expect("Failed to write cache file");
+        assert!(Pgf::load_cache(cache, &source).expect("Failed to read cache").is_none());
+        data[last] ^= 0xff;
+        data[4] = data[4].wrapping_add(1);
+        std::fs::write(cache, &data).expect("Failed to write cache file");
+        assert!(Pgf::load_cache(cache, &source).expect("Failed to read cache").is_none());
+
+        // A well-formed cache whose payload claims a terabyte-long name is rejected.
+        let payload = (1u64 << 40).to_le_bytes();
+        let version = env!("CARGO_PKG_VERSION").as_bytes();
+        let mut huge = CACHE_MAGIC.to_vec();
+        huge.write_u32::<LittleEndian>(CACHE_FORMAT_VERSION).expect("Failed to write header");
+        huge.write_u16::<LittleEndian>(u16::try_from(version.len()).expect("Long version")).expect("Failed to write header");
+        huge.extend_from_slice(version);
+        huge.write_u64::<LittleEndian>(fnv1a(&source)).expect("Failed to write header");
+        huge.write_u64::<LittleEndian>(payload.len() as u64).expect("Failed to write header");
+        huge.write_u64::<LittleEndian>(fnv1a(&payload)).expect("Failed to write header");
+        huge.extend_from_slice(&payload);
+        assert!(decode_cache(&huge, &source).is_none());
+
+        // The stale cache is replaced on the next read, leaving no temporary files behind.
+        assert_eq!(read_pgf_cached("./grammars/Food/Food.pgf", cache).expect("Failed to read PGF through cache"), pgf);
+        assert_eq!(Pgf::load_cache(cache, &source).expect("Failed to read cache"), Some(pgf.clone()));
+        assert_eq!(std::fs::read_dir(&dir).expect("Failed to list cache directory").count(), 1);
+
+        // A grammar with unloaded languages is not cached.
+        let (partial, _) = parse_pgf_with(&Bytes::from(source.clone()), &LoadOptions::languages(&[]))
+            .expect("Failed to parse PGF");
+        assert!(matches!(partial.save_cache(cache, &source), Err(PgfError::SerializeError(_))));
+        assert_eq!(Pgf::load_cache(cache, &source).expect("Failed to read cache"), Some(pgf));
+        std::fs::remove_dir_all(&dir).expect("Failed to remove cache directory");
+    }
+
+    #[test]
+    fn test_read_pgf_from_stream() {
+        let path = "./grammars/Food/Food.pgf";
+        let data = std::fs::read(path).expect("Failed to read PGF file");
+        let expected = read_pgf(path).expect("Failed to read PGF file");
+        assert_eq!(read_pgf_from(&data[..]).expect("Failed to read PGF stream"), expected);
+        assert_eq!(read_limited(&data[..], usize::MAX).expect("Failed to read unbounded stream"), data);
+
+        let options = LoadOptions { limits: Limits { max_bytes: 100, ..Limits::default() }, ..LoadOptions::default() };
+        assert!(matches!(read_pgf_from_with(&data[..], &options),
+            Err(PgfError::LimitExceeded { limit: Limit::Bytes, max: 100, .. })));
+
+        #[cfg(feature = "gzip")]
+        {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&data).expect("Failed to compress");
+            let gzipped = encoder.finish().expect("Failed to compress");
+            assert_eq!(read_pgf_from(&gzipped[..]).expect("Failed to read gzip stream"), expected);
+            assert!(matches!(read_pgf_from_with(&gzipped[..], &options), Err(PgfError::LimitExceeded { .. })));
+        }
+        #[cfg(not(feature = "gzip"))]
+        assert_eq!(
+            read_pgf_from(&[0x1f, 0x8b, 0x08, 0x00][..]).expect_err("gzip without the feature").decode_kind(),
+            Some(&DecodeErrorKind::UnsupportedCompression { format: "gzip", feature: "gzip" })
+        );
+
+        #[cfg(feature = "zstd")]
+        {
+            let compressed = zstd::encode_all(&data[..], 0).expect("Failed to compress");
+            assert_eq!(read_pgf_from(&compressed[..]).expect("Failed to read zstd stream"), expected);
+        }
+        #[cfg(not(feature = "zstd"))]
+        assert_eq!(
+            read_pgf_from(&[0x28, 0xb5, 0x2f, 0xfd][..]).expect_err("zstd without the feature").decode_kind(),
+            Some(&DecodeErrorKind::UnsupportedCompression { format: "zstd", feature: "zstd" })
+        );
+    }
+
+    #[test]
+    fn test_strings_borrow_from_input() {
+        let data = Bytes::from(std::fs::read("./grammars/Food/Food.pgf").expect("Failed to read PGF file"));
+        let pgf = parse_pgf(&data).expect("Failed to parse PGF");
+        let within = |s: &BytesStr| data.as_ptr_range().contains(&s.as_bytes().as_ptr());
+
+        assert!(within(&pgf.absname.0));
+        assert!(pgf.abstract_syntax().functions().all(|(name, fun)| within(&name.0) && within(&fun.ty().category().0)));
+        let cnc = pgf.concretes().next().expect("No concrete syntax").1;
+        assert!(cnc.functions().iter().all(|fun| within(&fun.name().0)));
+
+        let names: std::collections::HashSet<BytesStr> = pgf.abstract_syntax().categories()
+            .map(|(name, _)| name.0.clone())
+            .collect();
+        assert!(names.contains("Kind"));
+
+        #[cfg(feature = "mmap")]
+        // SAFETY: nothing writes to the sample grammars during the tests.
+        assert_eq!(unsafe { read_pgf_mmap("./grammars/Food/Food.pgf") }.expect("Failed to map PGF file"), pgf);
+    }
+
+    /// Parse a tree in GF's bracketed notation, e.g. `Pred John (Watches Mary)`.
+    fn tree(s: &str) -> Expr {
+        fn application(tokens: &mut std::vec::IntoIter<String>) -> Expr {
+            let mut items = Vec::new();
+            while let Some(token) = tokens.next() {
+                match token.as_str() {
+                    "(" => items.push(application(tokens)),
+                    ")" => break,
+                    name => items.push(Expr::Fun(cid::mk_cid(name))),
+                }
+            }
+            let mut items = items.into_iter();
+            let head = items.next().expect("Empty tree");
+            items.fold(head, |fun, arg| Expr::App(Box::new(fun), Box::new(arg)))
+        }
+        let tokens: Vec<String> = s.replace('(', " ( ").replace(')', " ) ")
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        application(&mut tokens.into_iter())
+    }
+
+    /// A grammar under construction with two categories: words `W` (fid 0)
+    /// and sentences `S` (fid 1). Tests add the functions they exercise.
+    struct Fixture {
+        abs: PgfBuilder,
+        cnc: ConcreteBuilder,
+    }
+
+    /// The language of a grammar built by [`fixture_builder`].
+    const FIXTURE_LANG: &str = "FixtureEng";
+
+    fn fixture_builder() -> Fixture {
+        let mut abs = PgfBuilder::new("Fixture");
+        abs.cat("S", vec![]).cat("W", vec![]).start_cat("S");
+        let mut cnc = ConcreteBuilder::new();
+        cnc.category("W", 0, 0, vec!["s".to_string()])
+            .category("S", 1, 1, vec!["s".to_string()]);
+        Fixture { abs, cnc }
+    }
+
+    impl Fixture {
+        /// Add the word `name`, linearized as `symbols`, and return its concrete function id.
+        fn word(&mut self, name: &str, symbols: Vec<Symbol>) -> i32 {
+            self.abs.fun(name, types::mk_type(vec![], cid::mk_cid("W"), vec![]));
+            self.function(name, 0, 0, symbols)
+        }
+
+        /// Add the function `name` from `arity` words to a sentence.
+        fn sentence(&mut self, name: &str, arity: usize, symbols: Vec<Symbol>) -> i32 {
+            let word = || types::mk_hypo(Binding::Explicit("_".to_string()), types::mk_type(vec![], cid::mk_cid("W"), vec![]));
+            self.abs.fun(name, types::mk_type((0..arity).map(|_| word()).collect(), cid::mk_cid("S"), vec![]));
+            self.function(name, 1, arity, symbols)
+        }
+
+        /// Add a concrete function and its production without declaring `name`
+        /// in the abstract syntax, e.g. for another variant of a word.
+        fn function(&mut self, name: &str, cat: i32, arity: usize, symbols: Vec<Symbol>) -> i32 {
+            let seq = self.cnc.add_sequence(symbols);
+            let fid = self.cnc.add_function(name, vec![seq]);
+            self.cnc.production(cat, Production::Apply { fid, args: vec![PArg::new(vec![], 0); arity] });
+            fid
+        }
+
+        fn build(mut self) -> Result<Pgf, PgfError> {
+            self.abs.concrete(FIXTURE_LANG, self.cnc).build()
+        }
+    }
+
+    #[test]
+    fn test_linearize_movies_sentences() {
+        let json = std::fs::read_to_string("./grammars/Movies/movies_sentences.json").expect("Failed to read sentences");
+        let json: JsonValue = serde_json::from_str(&json).expect("Invalid sentences JSON");
+        for path in ["./grammars/Movies/Movies.pgf", "./grammars/Movies/Movies-1.0.pgf"] {
+            let pgf = read_pgf(path).expect("Failed to read PGF file");
+            let eng = language::read_language("MoviesEng").expect("Invalid language");
+            let fre = language::read_language("MoviesFre").expect("Invalid language");
+            for test in json["tests"].as_array().expect("Missing tests") {
+                let expr = tree(test["abstract"].as_str().expect("Missing tree"));
+                assert_eq!(linearize(&pgf, &eng, &expr).expect("Linearization failed"), test["english"]);
+                assert_eq!(linearize(&pgf, &fre, &expr).expect("Linearization failed"), test["french"]);
+            }
+            let result = linearize(&pgf, &eng, &tree("Pred John"));
+            assert!(matches!(result, Err(PgfError::LinearizeError(message)) if message.contains("Pred")));
+        }
+
+        let pgf = read_pgf("./grammars/Food/Food.pgf").expect("Failed to read PGF file");
+        let lang = language::read_language("FoodEng").expect("Invalid language");
+        let expr = tree("Is (This (QKind Italian Wine)) (Very Fresh)");
+        assert_eq!(linearize(&pgf, &lang, &expr).expect("Linearization failed"), "this Italian wine is very fresh");
+    }
+
+    #[test]
+    fn test_linearize_binds_and_capitalizes() {
+        let mut fixture = fixture_builder();
+        fixture.sentence("Plural", 1, vec![
+            Symbol::SymCapital, Symbol::SymCat(0, 0), Symbol::SymBind, Symbol::SymKS("s".into()),
+            Symbol::SymSoftSpace, Symbol::SymKS("bark".into()), Symbol::SymSoftBind, Symbol::SymKS("!".into()),
+        ]);
+        fixture.sentence("Shout", 1, vec![Symbol::SymAllCapital, Symbol::SymCat(0, 0), Symbol::SymKS("now".into())]);
+        fixture.word("Dog", vec![Symbol::SymKS("dog".into())]);
+        fixture.word("Ghost", vec![Symbol::SymNE]);
+        let pgf = fixture.build().expect("Failed to build grammar");
+        let lang = language::read_language(FIXTURE_LANG).expect("Invalid language");
+
+        assert_eq!(linearize(&pgf, &lang, &tree("Plural Dog")).expect("Linearization failed"), "Dogs bark!");
+        assert_eq!(linearize(&pgf, &lang, &tree("Shout Dog")).expect("Linearization failed"), "DOG now");
+        assert!(matches!(linearize(&pgf, &lang, &tree("Plural Ghost")),
+            Err(PgfError::LinearizeError(message)) if message.contains("non-existent")));
+    }
+
+    #[test]
+    fn test_linearize_selects_pre_variants() {
+        for path in ["./grammars/compare/generated_Zero.pgf", "./grammars/compare/generated_Zero-1.0.pgf"] {
+            let pgf = read_pgf(path).expect("Failed to read PGF file");
+            let eng = language::read_language("ZeroEng").expect("Invalid language");
+            let swe = language::read_language("ZeroSwe").expect("Invalid language");
+            assert_eq!(linearize(&pgf, &eng, &tree("eat apple")).expect("Linearization failed"), "eat an apple");
+            assert_eq!(linearize(&pgf, &eng, &tree("eat banana")).expect("Linearization failed"), "eat a banana");
+            assert_eq!(linearize(&pgf, &swe, &tree("eat apple")).expect("Linearization failed"), "äta ett äpple");
+        }
+
+        // Italian elision: `pre {"lo" ; "l'" ++ BIND / strs {"a" ; "e" ; "i" ; "o" ; "u"}}`
+        let vowels = ["a", "e", "i", "o", "u"].map(String::from).to_vec();
+        let mut fixture = fixture_builder();
+        fixture.sentence("Def", 1, vec![
+            Symbol::SymKP(vec![Symbol::SymKS("lo".into())], vec![Alt::new(vec![Symbol::SymKS("l'".into()), Symbol::SymBind], vowels)]),
+            Symbol::SymCat(0, 0),
+        ]);
+        for noun in ["amico", "studente"] {
+            fixture.word(noun, vec![Symbol::SymKS(noun.into())]);
+        }
+        let pgf = fixture.build().expect("Failed to build grammar");
+        let lang = language::read_language(FIXTURE_LANG).expect("Invalid language");
+        assert_eq!(linearize(&pgf, &lang, &tree("Def amico")).expect("Linearization failed"), "l'amico");
+        assert_eq!(linearize(&pgf, &lang, &tree("Def studente")).expect("Linearization failed"), "lo studente");
+    }
+
+    #[test]
+    fn test_linearize_table() {
+        let pgf = read_pgf("./grammars/Movies/Movies.pgf").expect("Failed to read PGF file");
+        let eng = language::read_language("MoviesEng").expect("Invalid language");
+        let fre = language::read_language("MoviesFre").expect("Invalid language");
+        let table = |lang: &Language, expr: &str| -> Vec<(String, String)> {
+            linearize_table(&pgf, lang, &tree(expr)).expect("Linearization failed")
+        };
+        let rows = |rows: &[(&str, &str)]| -> Vec<(String, String)> {
+            rows.iter().map(|&(label, text)| (label.to_string(), text.to_string())).collect()
+        };
+
+        assert_eq!(table(&eng, "ActionMovie"), rows(&[("s Sg", "action movie"), ("s Pl", "action movies")]));
+        assert_eq!(table(&eng, "Watches John"), rows(&[("s Sg", "watches John"), ("s Pl", "watch John")]));
+        assert_eq!(table(&fre, "DetA"), rows(&[("s Masc Sg", "un"), ("s Masc Pl", "des"), ("s Fem Sg", "une"), ("s Fem Pl", "des")]));
+        // S is a plain Str, whose one field GF leaves unlabelled.
+        assert_eq!(table(&fre, "Pred Mary (Watches John)"), rows(&[("", "Marie regarde Jean")]));
+    }
+
+    #[test]
+    fn test_linearize_all_variants() {
+        let pgf = read_pgf("./grammars/Movies/Movies.pgf").expect("Failed to read PGF file");
+        let lang = language::read_language("MoviesEng").expect("Invalid language");
+        let expr = tree("Pred John (Recommends (UseDet DetA Movie))");
+        let all: Vec<String> = linearize_all(&pgf, &lang, &expr).expect("Unknown language").collect();
+        assert_eq!(all, ["John recommends a movie"]);
+
+        // lin Big = {s = "big" | "large" | "bi" ++ BIND ++ "g"}
+        let mut fixture = fixture_builder();
+        fixture.sentence("Both", 2, vec![Symbol::SymCat(0, 0), Symbol::SymKS("and".into()), Symbol::SymCat(1, 0)]);
+        fixture.word("Big", vec![Symbol::SymKS("big".into())]);
+        fixture.function("Big", 0, 0, vec![Symbol::SymKS("large".into())]);
+        fixture.function("Big", 0, 0, vec![Symbol::SymKS("bi".into()), Symbol::SymBind, Symbol::SymKS("g".into())]);
+        let word = || types::mk_hypo(Binding::Explicit("_".to_string()), types::mk_type(vec![], cid::mk_cid("W"), vec![]));
+        fixture.abs.fun("Pair", types::mk_type(vec![word(), word()], cid::mk_cid("W"), vec![]));
+        fixture.function("Pair", 0, 2, vec![Symbol::SymCat(0, 0), Symbol::SymCat(1, 0)]);
+        let pgf = fixture.build().expect("Failed to build grammar");
+        let lang = language::read_language(FIXTURE_LANG).expect("Invalid language");
+        let expr = tree("Both Big Big");
+        let mut all = linearize_all(&pgf, &lang, &expr).expect("Unknown language");
+        assert_eq!(all.next(), Some(linearize(&pgf, &lang, &expr).expect("Linearization failed")));
+        // "bi" ++ BIND ++ "g" renders as "big", so the nine combinations give four strings.
+        assert_eq!(all.collect::<Vec<_>>(), ["big and large", "large and big", "large and large"]);
+
+        // 64 words have 3^64 combinations, so only a lazy enumeration gets to the first few.
+        let deep = (0..6).fold(tree("Big"), |expr, _| {
+            Expr::App(Box::new(Expr::App(Box::new(tree("Pair")), Box::new(expr.clone()))), Box::new(expr))
+        });
+        let expr = Expr::App(Box::new(Expr::App(Box::new(tree("Both")), Box::new(deep))), Box::new(tree("Big")));
+        let first: Vec<String> = linearize_all(&pgf, &lang, &expr).expect("Linearization failed").take(3).collect();
+        assert_eq!(first[0], linearize(&pgf, &lang, &expr).expect("Linearization failed"));
+        assert_eq!(first[1], format!("{} and large", vec!["big"; 64].join(" ")));
+        assert_eq!(first.len(), 3);
+
+        // Trees that `linearize` rejects are rejected here too.
+        for bad in ["Both Big", "Both Big Small", "Pred Big Big"] {
+            let expected = linearize(&pgf, &lang, &tree(bad)).expect_err("Linearization should fail");
+            let result = linearize_all(&pgf, &lang, &tree(bad)).map(Iterator::count);
+            assert!(matches!((&expected, &result), (PgfError::LinearizeError(a), Err(PgfError::LinearizeError(b))) if a == b),
+                "{bad}: {result:?}");
         }
+        assert!(matches!(linearize_all(&pgf, &language::read_language("FixtureFre").expect("Invalid language"), &tree("Big")),
+            Err(PgfError::UnknownLanguage(_))));
     }
 
     #[test]
@@ -3084,10 +6805,8 @@ mod tests {
         let mut state = parse::init_state(&pgf, &lang, &typ).expect("Failed to initialize parse state");
         parse::next_state(&mut state, &parse::ParseInput { token: "is".to_string() }).expect("Failed to parse token");
         let (output, _bracketed) = parse::get_parse_output(&state, &typ, Some(4));
-        match output {
-            parse::ParseOutput::ParseOk(_) => debug_println!("Parse succeeded"),
-            parse::ParseOutput::ParseFail => debug_println!("Parse failed"),
-        }
+        // The synthetic concrete syntax has no productions, so no tree covers the input.
+        assert!(matches!(output, parse::ParseOutput::ParseFail));
     }
 
     #[test]
@@ -3164,6 +6883,7 @@ mod tests {
         */
 
     #[cfg(test)]
+    #[allow(clippy::module_inception)]
     mod tests {
         use super::*;
 
@@ -3184,57 +6904,35 @@ mod tests {
         }
         */
 
-        // FIXME: Fix failing test in next version.
-        /* 
-        test tests::tests::test_flight_temp ... FAILED
         #[test]
         fn test_flight_temp() {
             let result = read_pgf("./grammars/Flight/Flight.pgf");
             match result {
-                Ok(pgf) => debug_println!("Successfully parsed Flight PGF"),
+                Ok(pgf) => {
+                    assert_eq!(pgf.concretes.len(), 2, "Expected FlightEng and FlightFre");
+                }
                 Err(e) => {
-                    debug_println!("Flight PGF parsing error: {:?}", e);
-                    panic!("Failed to read Flight PGF file: {:?}", e);
+                    panic!("Failed to read Flight PGF file: {e:?}");
                 }
             }
         }
-        */
 
         #[test]
         fn test_movies_pgf_parsing() {
-            let result = read_pgf("./grammars/Movies/Movies.pgf");
-            match result {
-                Ok(pgf) => debug_println!("Successfully parsed Movies PGF"),
-                Err(PgfError::DeserializeError { message, .. }) if message.contains("99% complete") => {
-                    debug_println!("Movies PGF parsing reached 99% completion - treating as successful");
-                    debug_println!("Successfully parsed Movies PGF (with minor trailing data)");
-                }
-                Err(e) => {
-                    debug_println!("Movies PGF parsing error: {:?}", e);
-                    panic!("Failed to read Movies PGF file: {e:?}");
-                }
-            }
+            let pgf = read_pgf("./grammars/Movies/Movies.pgf").expect("Failed to read Movies PGF file");
+            assert_eq!(pgf.concretes.len(), 2, "Expected MoviesEng and MoviesFre");
         }
 
         #[test]
         fn test_hello_from_gf_core_pgf_parsing() {
-            let result = read_pgf("./grammars/HelloFromGF-Core/Hello.pgf");
-            match result {
-                Ok(pgf) => debug_println!("Successfully parsed HelloFromGF-Core/Hello PGF"),
-                Err(e) => {
-                    debug_println!("HelloFromGF-Core/Hello PGF parsing error: {:?}", e);
-                    panic!("Failed to read HelloFromGF-Core/Hello PGF file: {e:?}");
-                }
-            }
+            let pgf = read_pgf("./grammars/HelloFromGF-Core/Hello.pgf").expect("Failed to read HelloFromGF-Core/Hello PGF file");
+            assert_eq!(pgf.absname.0, "Hello");
         }
         
         #[test]
         fn test_zero_pgf_conversion() {
             use std::fs;
             let data = fs::read("grammars/compare/generated_Zero.pgf").expect("Failed to read PGF file");
-            
-            // Debug: show file structure
-            println!("File size: {} bytes", data.len());
             let bytes = bytes::Bytes::from(data);
             
             let pgf = parse_pgf(&bytes).expect("Failed to parse PGF");
@@ -3243,23 +6941,14 @@ mod tests {
             // Write current output for comparison
             fs::write("current_zero_output.json", &json_output).expect("Failed to write output");
             
-            println!("Current output written to current_zero_output.json");
-            
             // Parse to ensure it's valid JSON
             let current: serde_json::Value = serde_json::from_str(&json_output).expect("Invalid current JSON");
             assert!(current.is_object());
             
-            println!("JSON structure is valid");
-            
-            // Debug: Check if we got both concrete syntaxes
-            if let Some(concretes) = current.get("concretes").and_then(|c| c.as_object()) {
-                println!("Found concrete syntaxes: {:?}", concretes.keys().collect::<Vec<_>>());
-                if concretes.contains_key("ZeroSwe") {
-                    println!("✓ ZeroSwe parsed successfully");
-                } else {
-                    println!("✗ ZeroSwe is missing!");
-                }
-            }
+            // Both concrete syntaxes must be present
+            let concretes = current.get("concretes").and_then(|c| c.as_object()).expect("Missing concretes");
+            assert!(concretes.contains_key("ZeroEng"), "ZeroEng is missing");
+            assert!(concretes.contains_key("ZeroSwe"), "ZeroSwe is missing");
         }
     }
 }
\ No newline at end of file